@@ -0,0 +1,9 @@
+-- The players table predates this service's migrations (it was originally created by the
+-- infrastructure repository), so this migration must be safe to run against an existing database.
+CREATE TABLE IF NOT EXISTS players (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    username TEXT NOT NULL UNIQUE,
+    email TEXT NOT NULL UNIQUE,
+    password TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,17 @@
+-- Opaque, rotating refresh tokens. Only a SHA-256 hash of each token is stored.
+--
+-- Every token issued by a login or registration starts a new family. Each time a token is
+-- exchanged it is marked as used and replaced by a new token in the same family, so presenting a
+-- token which has already been used means it was stolen, and the whole family is revoked.
+CREATE TABLE refresh_tokens (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    player_id UUID NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+    family_id UUID NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ
+);
+
+CREATE INDEX refresh_tokens_family_id_idx ON refresh_tokens (family_id);
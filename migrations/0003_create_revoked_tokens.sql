@@ -0,0 +1,10 @@
+-- Access tokens (JWTs) which were revoked before they expired, identified by their `jti` claim.
+-- Rows are only useful until the token would have expired anyway, after which they can be removed.
+CREATE TABLE revoked_tokens (
+    jti UUID PRIMARY KEY,
+    player_id UUID NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+-- Every access token issued to a player at or before this moment is considered revoked.
+ALTER TABLE players ADD COLUMN tokens_revoked_at TIMESTAMPTZ;
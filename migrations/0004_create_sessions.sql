@@ -0,0 +1,13 @@
+-- A session is created every time a player signs in (or registers). The id of a session is also the
+-- `family_id` of the refresh tokens issued to it, and the `sid` claim of its access tokens.
+CREATE TABLE sessions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    player_id UUID NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+    user_agent TEXT,
+    ip_address TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    revoked_at TIMESTAMPTZ
+);
+
+CREATE INDEX sessions_player_id_idx ON sessions (player_id);
@@ -0,0 +1,13 @@
+-- Single-use, short-lived tokens which are emailed to players (such as password reset links). Only
+-- a SHA-256 hash of each token is stored.
+CREATE TABLE one_time_tokens (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    player_id UUID NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+    purpose TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ
+);
+
+CREATE INDEX one_time_tokens_player_id_purpose_idx ON one_time_tokens (player_id, purpose);
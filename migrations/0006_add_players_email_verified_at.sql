@@ -0,0 +1,3 @@
+-- Players confirm that they own their email address by following a link emailed to them. Until
+-- then, this column is null.
+ALTER TABLE players ADD COLUMN email_verified_at TIMESTAMPTZ;
@@ -0,0 +1,2 @@
+-- Email change tokens confirm a new email address, which is held here until the token is used.
+ALTER TABLE one_time_tokens ADD COLUMN email TEXT;
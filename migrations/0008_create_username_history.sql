@@ -0,0 +1,11 @@
+-- Every username a player has given up. Old usernames stay reserved for their previous owner for a
+-- while after a rename, and the most recent rename determines when the player may rename again.
+CREATE TABLE username_history (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    player_id UUID NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+    username TEXT NOT NULL,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX username_history_username_idx ON username_history (LOWER(username));
+CREATE INDEX username_history_player_id_idx ON username_history (player_id, changed_at);
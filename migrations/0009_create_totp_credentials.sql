@@ -0,0 +1,11 @@
+-- Each player can have one authenticator app enrolled for two-factor authentication. A new secret is
+-- held as pending until the player proves their app has it, so re-enrolling does not disable the
+-- existing secret until the new one works.
+CREATE TABLE totp_credentials (
+    player_id UUID PRIMARY KEY REFERENCES players (id) ON DELETE CASCADE,
+    secret TEXT,
+    pending_secret TEXT,
+    enabled_at TIMESTAMPTZ,
+    -- The time step of the last accepted code, so that codes cannot be replayed.
+    last_used_step BIGINT
+);
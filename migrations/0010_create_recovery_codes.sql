@@ -0,0 +1,11 @@
+-- Single-use codes which a player can use in place of a code from their authenticator app. Each code
+-- is hashed with Argon2, like a password.
+CREATE TABLE recovery_codes (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    player_id UUID NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    used_at TIMESTAMPTZ
+);
+
+CREATE INDEX recovery_codes_player_id_idx ON recovery_codes (player_id);
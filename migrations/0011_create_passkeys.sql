@@ -0,0 +1,29 @@
+-- Passkeys (WebAuthn credentials) a player can sign in with instead of their password. The credential
+-- id is stored as base64url, the way browsers send it, and the public key as a COSE key.
+CREATE TABLE passkeys (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    player_id UUID NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+    credential_id TEXT NOT NULL UNIQUE,
+    public_key BYTEA NOT NULL,
+    -- The authenticator's signature counter, which must increase with every sign in (unless the
+    -- authenticator always reports zero).
+    sign_count BIGINT NOT NULL DEFAULT 0,
+    name TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_used_at TIMESTAMPTZ
+);
+
+CREATE INDEX passkeys_player_id_idx ON passkeys (player_id);
+
+-- Challenges issued for WebAuthn ceremonies. Like one time tokens, only the hash of each challenge is
+-- stored, and each can only be used once. Sign in challenges have no player, since the player is
+-- only known once their passkey responds.
+CREATE TABLE webauthn_challenges (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    player_id UUID REFERENCES players (id) ON DELETE CASCADE,
+    ceremony TEXT NOT NULL,
+    challenge_hash TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ
+);
@@ -0,0 +1,16 @@
+-- Every password login attempt, successful or not. `username` is what was entered, and `player_id`
+-- is only set when a player had that username. Lockouts are decided from the consecutive failures
+-- for a username, so they apply equally to usernames which do not exist.
+CREATE TABLE login_attempts (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    player_id UUID REFERENCES players (id) ON DELETE CASCADE,
+    username TEXT NOT NULL,
+    ip_address TEXT,
+    user_agent TEXT,
+    -- One of `succeeded`, `failed`, or `locked` (rejected without checking the password).
+    outcome TEXT NOT NULL,
+    attempted_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX login_attempts_username_idx ON login_attempts (LOWER(username), attempted_at);
+CREATE INDEX login_attempts_player_id_idx ON login_attempts (player_id);
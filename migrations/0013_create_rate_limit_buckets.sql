@@ -0,0 +1,9 @@
+-- Token buckets for rate limiting, shared between every replica of the service when
+-- `RATE_LIMIT_BACKEND=postgres`. Keys look like `ip:203.0.113.7` or `username:b1gd3vd0g`.
+CREATE TABLE rate_limit_buckets (
+    key TEXT PRIMARY KEY,
+    tokens DOUBLE PRECISION NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX rate_limit_buckets_updated_at_idx ON rate_limit_buckets (updated_at);
@@ -0,0 +1,3 @@
+-- The version of the pepper the player's password was hashed with, since it is not part of the
+-- hash itself. Null if the password was not peppered.
+ALTER TABLE players ADD COLUMN password_pepper_version INTEGER;
@@ -0,0 +1,6 @@
+-- Players who ask to delete their account keep it for a grace period, during which they can restore
+-- it by signing in. Once this moment passes, the account is purged. Null unless deletion is pending.
+ALTER TABLE players ADD COLUMN deletion_scheduled_at TIMESTAMPTZ;
+
+CREATE INDEX players_deletion_scheduled_at_idx ON players (deletion_scheduled_at)
+    WHERE deletion_scheduled_at IS NOT NULL;
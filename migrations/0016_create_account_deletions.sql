@@ -0,0 +1,26 @@
+-- Accounts being purged once their grace period is over. Every other service which holds data about
+-- the player is asked to delete it (one row in `account_deletion_steps` each), and the player is only
+-- deleted once they all have. The rows outlive the player, as a record of the deletion.
+CREATE TABLE account_deletions (
+    player_id UUID PRIMARY KEY,
+    -- One of `in_progress`, `completed`, or `failed` (a service gave up, and the player was kept).
+    status TEXT NOT NULL DEFAULT 'in_progress',
+    started_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    finished_at TIMESTAMPTZ
+);
+
+CREATE TABLE account_deletion_steps (
+    player_id UUID NOT NULL REFERENCES account_deletions (player_id) ON DELETE CASCADE,
+    -- The service asked to delete the data, such as `currency`.
+    service TEXT NOT NULL,
+    -- One of `pending`, `completed`, or `failed` (it was attempted too many times).
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    completed_at TIMESTAMPTZ,
+    PRIMARY KEY (player_id, service)
+);
+
+CREATE INDEX account_deletion_steps_next_attempt_at_idx ON account_deletion_steps (next_attempt_at)
+    WHERE status = 'pending';
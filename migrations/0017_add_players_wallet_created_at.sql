@@ -0,0 +1,17 @@
+-- When the player's bit wallet was created by the currency service. Registration succeeds even when
+-- the wallet cannot be created, and a background job retries until it is; null until then.
+ALTER TABLE players ADD COLUMN wallet_created_at TIMESTAMPTZ;
+
+-- How many times the background job has failed to create the wallet, and when it tries next. Each
+-- player backs off on their own, so players whose wallet keeps failing do not hold up the others.
+ALTER TABLE players ADD COLUMN wallet_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE players ADD COLUMN wallet_next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+-- Existing players are marked as having a wallet, since registration asked for it straight away and
+-- only a failure of the currency service left a player without one. Nothing here can tell which of
+-- them did, and asking again for every existing player could create duplicate wallets. A player
+-- known to have no wallet can be handed to the background job by setting this back to null.
+UPDATE players SET wallet_created_at = created_at;
+
+CREATE INDEX players_wallet_next_attempt_at_idx ON players (wallet_next_attempt_at)
+    WHERE wallet_created_at IS NULL;
@@ -0,0 +1,23 @@
+-- Events about players, written in the same transaction as the change they describe, so an event is
+-- recorded if and only if the change is. A background job relays them to a message sink in order,
+-- at least once. `player_id` does not reference `players`, since `player.deleted` outlives them.
+-- An event which fails too many times is marked dead and skipped, so it does not hold back the rest.
+CREATE TABLE outbox (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    -- The order events are relayed in.
+    sequence BIGSERIAL NOT NULL UNIQUE,
+    -- One of `player.created`, `player.updated`, `player.deleted`, or `player.login`.
+    event_type TEXT NOT NULL,
+    player_id UUID NOT NULL,
+    payload JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    delivered_at TIMESTAMPTZ,
+    dead_at TIMESTAMPTZ
+);
+
+CREATE INDEX outbox_undelivered_idx ON outbox (sequence)
+    WHERE delivered_at IS NULL AND dead_at IS NULL;
+CREATE INDEX outbox_delivered_at_idx ON outbox (delivered_at) WHERE delivered_at IS NOT NULL;
@@ -0,0 +1,231 @@
+//! This module decides when accounts which players have asked to delete are purged, and purges them
+//! from every service which holds data about them.
+//!
+//! # Notes
+//!
+//! - Asking to delete an account only schedules it for deletion, after a grace period. Until then,
+//!   the player can restore it by signing in.
+//! - A background job starts deleting accounts whose grace period is over. Each of the other services
+//!   (see `PeerService`) is asked to delete the player's data, with a short lived access token for
+//!   the player. The progress of each is tracked in `account_deletion_steps`, and failures are
+//!   retried with exponential backoff. Each replica claims the steps it attempts, so a service is
+//!   not asked twice at once.
+//! - The player is only deleted here once every service has deleted their data. If a service fails
+//!   too many times, the deletion is marked failed and the player is kept, so nothing is orphaned.
+//!   Operators can follow deletions, and resume failed ones, through `/account-deletions`.
+//!
+//! # Environment
+//!
+//! * `ACCOUNT_DELETION_GRACE_DAYS` - (Optional) How long a player has to restore their account.
+//!   Defaults to 30.
+//! * `ACCOUNT_PURGE_INTERVAL_SECONDS` - (Optional) How often accounts are checked for purging.
+//!   Defaults to 3600.
+//! * `ACCOUNT_DELETION_RETRY_SECONDS` - (Optional) How long to wait before asking a service again
+//!   after its first failure. The wait doubles after each failure, up to 6 hours. Defaults to 60.
+//! * `ACCOUNT_DELETION_MAX_ATTEMPTS` - (Optional) How many times a service is asked before the
+//!   deletion is marked failed. Defaults to 10.
+
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    backoff::retry_delay,
+    config::from_env,
+    db::{
+        models::AccountDeletionStep,
+        queries::{
+            account_deletions::{
+                claim_due_deletion_steps, complete_deletion_step, fail_deletion_step,
+                finish_account_deletions, start_account_deletions,
+            },
+            get_player_by_id,
+        },
+    },
+    handlers::responses::MessageResponse,
+    requests::{self, currency, reward, slots},
+};
+
+/// The longest wait between attempts at a step.
+const MAX_RETRY_DELAY_SECONDS: i64 = 6 * 60 * 60;
+
+/// The most steps attempted in one run. They are attempted one after another, so this many
+/// requests timing out (see `requests::TIMEOUT`) must fit well within the lease.
+const BATCH_SIZE: i64 = 50;
+
+/// How long a replica has to attempt the steps it claims before another replica may claim them.
+const STEP_LEASE_MINUTES: i64 = 15;
+
+/// The services which hold data about players, and must delete it before the player is deleted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PeerService {
+    /// The player's bit wallet.
+    Currency,
+    /// The player's daily bonus claims.
+    Reward,
+    /// The player's slot machine history.
+    Slots,
+}
+
+impl PeerService {
+    pub const ALL: [PeerService; 3] = [
+        PeerService::Currency,
+        PeerService::Reward,
+        PeerService::Slots,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PeerService::Currency => "currency",
+            PeerService::Reward => "reward",
+            PeerService::Slots => "slots",
+        }
+    }
+
+    fn from_str(service: &str) -> Option<PeerService> {
+        PeerService::ALL.into_iter().find(|s| s.as_str() == service)
+    }
+
+    /// Ask the service to delete the data of the player the token names.
+    async fn delete_player_data(&self, token: &str) -> Result<(), MessageResponse> {
+        match self {
+            PeerService::Currency => currency::close_bit_wallet(token).await,
+            PeerService::Reward => reward::delete_reward_history(token).await,
+            PeerService::Slots => slots::delete_slots_history(token).await,
+        }
+    }
+}
+
+/// How long a player has to restore their account after asking to delete it.
+pub fn grace_period() -> Duration {
+    Duration::days(from_env("ACCOUNT_DELETION_GRACE_DAYS", 30))
+}
+
+/// Mint an access token for a player, for the services to know whose data to delete.
+async fn player_token(pool: &PgPool, player_id: Uuid) -> Result<String, String> {
+    let player = get_player_by_id(pool, player_id)
+        .await
+        .map_err(|e| format!("Player could not be found: {}", e))?;
+    requests::player_token(&player).map_err(|e| e.message)
+}
+
+/// Attempt a step, and record the outcome.
+async fn run_step(pool: &PgPool, step: &AccountDeletionStep) -> Result<(), sqlx::Error> {
+    let outcome = match PeerService::from_str(&step.service) {
+        Some(service) => match player_token(pool, step.player_id).await {
+            Ok(token) => service
+                .delete_player_data(&token)
+                .await
+                .map_err(|e| e.message),
+            Err(e) => Err(e),
+        },
+        None => Err(format!("Unknown service {}", step.service)),
+    };
+
+    match outcome {
+        Ok(()) => complete_deletion_step(pool, step.player_id, &step.service).await,
+        Err(error) => {
+            let attempts = step.attempts + 1;
+            let retry_at = (attempts < from_env("ACCOUNT_DELETION_MAX_ATTEMPTS", 10)).then(|| {
+                Utc::now()
+                    + retry_delay(
+                        attempts,
+                        from_env("ACCOUNT_DELETION_RETRY_SECONDS", 60),
+                        MAX_RETRY_DELAY_SECONDS,
+                    )
+            });
+            eprintln!(
+                "Failed to delete the {} data of player {} (attempt {}): {}",
+                step.service, step.player_id, attempts, error
+            );
+            fail_deletion_step(pool, step.player_id, &step.service, &error, retry_at).await
+        }
+    }
+}
+
+/// Make progress on every account deletion: start those whose grace period is over, attempt the
+/// steps which are due, and finish those which are done.
+pub async fn run_account_deletions(pool: &PgPool) -> Result<(), sqlx::Error> {
+    let services = PeerService::ALL.map(|s| s.as_str());
+    for player_id in start_account_deletions(pool, &services).await? {
+        println!("Started deleting player {}", player_id);
+    }
+
+    let lease_until = Utc::now() + Duration::minutes(STEP_LEASE_MINUTES);
+    for step in claim_due_deletion_steps(pool, BATCH_SIZE, lease_until).await? {
+        run_step(pool, &step).await?;
+    }
+
+    let finished = finish_account_deletions(pool).await?;
+    for player_id in finished.completed {
+        println!("Deleted player {}", player_id);
+    }
+    for player_id in finished.failed {
+        eprintln!(
+            "Gave up deleting player {}; see /account-deletions for the failed services",
+            player_id
+        );
+    }
+    Ok(())
+}
+
+/// Start the background job which purges accounts whose grace period is over. Failures are logged,
+/// and the accounts are tried again next time.
+pub fn spawn_purge_job(pool: PgPool) {
+    let interval = from_env("ACCOUNT_PURGE_INTERVAL_SECONDS", 3600).max(1) as u64;
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            if let Err(e) = run_account_deletions(&pool).await {
+                eprintln!("Failed to purge deleted accounts: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::create_test_player;
+
+    #[test]
+    fn test_peer_service_names() {
+        for service in PeerService::ALL {
+            assert_eq!(PeerService::from_str(service.as_str()), Some(service));
+        }
+        assert_eq!(PeerService::from_str("players"), None);
+    }
+
+    #[sqlx::test]
+    async fn test_claim_due_deletion_steps(pool: PgPool) {
+        create_test_player(&pool, "b1gd3vd0g", "CorrectHorse!42").await;
+        sqlx::query("UPDATE players SET deletion_scheduled_at = now()")
+            .execute(&pool)
+            .await
+            .unwrap();
+        start_account_deletions(&pool, &["currency", "slots"])
+            .await
+            .unwrap();
+
+        // Once claimed, the steps are left alone until the lease is over.
+        let lease_until = Utc::now() + Duration::minutes(STEP_LEASE_MINUTES);
+        let claimed = claim_due_deletion_steps(&pool, BATCH_SIZE, lease_until)
+            .await
+            .unwrap();
+        assert_eq!(claimed.len(), 2);
+        let claimed = claim_due_deletion_steps(&pool, BATCH_SIZE, lease_until)
+            .await
+            .unwrap();
+        assert!(claimed.is_empty());
+
+        sqlx::query("UPDATE account_deletion_steps SET next_attempt_at = now()")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let claimed = claim_due_deletion_steps(&pool, BATCH_SIZE, lease_until)
+            .await
+            .unwrap();
+        assert_eq!(claimed.len(), 2);
+    }
+}
@@ -0,0 +1,35 @@
+//! This module decides how long background jobs wait before retrying something which failed.
+
+use chrono::Duration;
+
+/// How long to wait before the next attempt, doubling after each failure.
+///
+/// # Arguments
+///
+/// * `attempts` - How many attempts have failed, including the latest.
+/// * `base_seconds` - The wait after the first failure.
+/// * `max_seconds` - The longest wait.
+pub fn retry_delay(attempts: i32, base_seconds: i64, max_seconds: i64) -> Duration {
+    let doublings = attempts.saturating_sub(1).clamp(0, 30) as u32;
+    Duration::seconds(
+        base_seconds
+            .max(1)
+            .saturating_mul(1 << doublings)
+            .min(max_seconds),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(1, 60, 3600), Duration::seconds(60));
+        assert_eq!(retry_delay(2, 60, 3600), Duration::seconds(120));
+        assert_eq!(retry_delay(5, 60, 3600), Duration::seconds(960));
+        assert_eq!(retry_delay(20, 60, 3600), Duration::seconds(3600));
+        assert_eq!(retry_delay(i32::MAX, 60, 3600), Duration::seconds(3600));
+        assert_eq!(retry_delay(0, 0, 3600), Duration::seconds(1));
+    }
+}
@@ -0,0 +1,37 @@
+//! This module deletes rows which are no longer needed, in the background, so that requests do not
+//! have to clean up after themselves.
+//!
+//! # Notes
+//!
+//! - One time tokens and WebAuthn challenges are deleted once they have been used or have expired.
+//!
+//! # Environment
+//!
+//! * `CLEANUP_INTERVAL_SECONDS` - (Optional) How often old rows are deleted. Defaults to 600.
+
+use sqlx::PgPool;
+
+use crate::{
+    config::from_env,
+    db::queries::{
+        one_time_tokens::delete_finished_one_time_tokens,
+        webauthn_challenges::delete_finished_webauthn_challenges,
+    },
+};
+
+/// Start the background job which deletes rows which are no longer needed.
+pub fn spawn_cleanup_job(pool: PgPool) {
+    let interval: u64 = from_env("CLEANUP_INTERVAL_SECONDS", 600).max(1);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            if let Err(e) = delete_finished_one_time_tokens(&pool).await {
+                eprintln!("Failed to delete finished one time tokens: {}", e);
+            }
+            if let Err(e) = delete_finished_webauthn_challenges(&pool).await {
+                eprintln!("Failed to delete finished WebAuthn challenges: {}", e);
+            }
+        }
+    });
+}
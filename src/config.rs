@@ -0,0 +1,31 @@
+//! This module reads optional settings from the environment.
+
+use std::{env, str::FromStr};
+
+/// Read a setting from the environment.
+///
+/// # Arguments
+///
+/// * `key` - The name of the environment variable.
+/// * `default` - The value to use if the variable is unset, or cannot be parsed.
+pub fn from_env<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_env() {
+        env::set_var("CONFIG_TEST_SET", "42");
+        env::set_var("CONFIG_TEST_INVALID", "forty two");
+        assert_eq!(from_env("CONFIG_TEST_SET", 7), 42);
+        assert_eq!(from_env("CONFIG_TEST_INVALID", 7), 7);
+        assert_eq!(from_env("CONFIG_TEST_UNSET", 7), 7);
+        assert_eq!(from_env("CONFIG_TEST_SET", 0.5), 42.0);
+    }
+}
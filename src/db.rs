@@ -2,6 +2,9 @@
 //! pool to our postgres database. It also contains the module `models` with all of our database
 //! models, as well as the module `queries` which contains functions to simplify making any
 //! necessary database queries.
+//!
+//! The schema is defined by the SQL files in the `migrations` directory, which are embedded into the
+//! binary and applied whenever a connection pool is created.
 
 pub mod models;
 pub mod queries;
@@ -9,7 +12,8 @@ pub mod queries;
 use sqlx::{postgres::PgPoolOptions, PgPool};
 use std::env;
 
-/// Attempt to connect to the database using the `DATABASE_URL` environment variable.
+/// Attempt to connect to the database using the `DATABASE_URL` environment variable, and bring the
+/// schema up to date.
 ///
 /// # Returns
 ///
@@ -17,12 +21,17 @@ use std::env;
 ///
 /// # Errors
 ///
-/// Panics if the connection pool cannot be produced.
+/// Panics if the connection pool cannot be produced, or if the migrations cannot be applied.
 pub async fn connect() -> PgPool {
     let db_url = env::var("DATABASE_URL").expect("DATABASE_URL environment variable is not set!");
-    PgPoolOptions::new()
+    let pool = PgPoolOptions::new()
         .max_connections(5)
         .connect(&db_url)
         .await
-        .expect("Error connecting to the database!")
+        .expect("Error connecting to the database!");
+    sqlx::migrate!()
+        .run(&pool)
+        .await
+        .expect("Error applying database migrations!");
+    pool
 }
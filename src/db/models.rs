@@ -1,16 +1,14 @@
 //! Contains all database models for the player microservice.
 //!
-//! For now, this only contains the `Player` model (found in the `players` table).
-//!
-//! Possible future enhancements include:
-//!
-//! - Adding a `LoginAttempt` model to track login attempts.
+//! This contains the `Player` model (found in the `players` table), as well as the models for the
+//! tables which reference it.
 
 use chrono::{DateTime, Utc};
 use sqlx::FromRow;
 use uuid::Uuid;
 
 /// The Player model represents a row from the `players` table in our database.
+#[allow(dead_code)] // Every column is mapped, even those the service does not read yet.
 #[derive(FromRow)]
 pub struct Player {
     pub id: Uuid,
@@ -18,4 +16,361 @@ pub struct Player {
     pub email: String,
     pub password: String,
     pub created_at: DateTime<Utc>,
+    pub tokens_revoked_at: Option<DateTime<Utc>>,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    pub password_pepper_version: Option<i32>,
+    /// When the account will be purged, if the player has asked to delete it.
+    pub deletion_scheduled_at: Option<DateTime<Utc>>,
+    /// When the player's bit wallet was created. `None` while it is still pending.
+    pub wallet_created_at: Option<DateTime<Utc>>,
+    /// How many times creating the pending wallet has failed in the background.
+    pub wallet_attempts: i32,
+    /// When creating the pending wallet is next attempted in the background.
+    pub wallet_next_attempt_at: DateTime<Utc>,
+}
+
+/// The RefreshToken model represents a row from the `refresh_tokens` table in our database.
+///
+/// # Notes
+/// * Only the hash of the token is stored; the raw token is only ever known by the client.
+#[allow(dead_code)] // Every column is mapped, even those the service does not read yet.
+#[derive(FromRow)]
+pub struct RefreshToken {
+    pub id: Uuid,
+    pub player_id: Uuid,
+    pub family_id: Uuid,
+    pub token_hash: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// The Session model represents a row from the `sessions` table in our database.
+///
+/// # Notes
+/// * The id of a session is also the `family_id` of every refresh token issued to it.
+#[allow(dead_code)] // Every column is mapped, even those the service does not read yet.
+#[derive(FromRow)]
+pub struct Session {
+    pub id: Uuid,
+    pub player_id: Uuid,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// What a one time token can be used for. Stored in the `purpose` column of `one_time_tokens`.
+#[derive(Clone, Copy)]
+pub enum TokenPurpose {
+    PasswordReset,
+    EmailVerification,
+    EmailChange,
+    MfaPending,
+    MagicLink,
+    AccountRestoration,
+}
+
+impl TokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenPurpose::PasswordReset => "password_reset",
+            TokenPurpose::EmailVerification => "email_verification",
+            TokenPurpose::EmailChange => "email_change",
+            TokenPurpose::MfaPending => "mfa_pending",
+            TokenPurpose::MagicLink => "magic_link",
+            TokenPurpose::AccountRestoration => "account_restoration",
+        }
+    }
+}
+
+/// The OneTimeToken model represents a row from the `one_time_tokens` table in our database.
+///
+/// # Notes
+/// * Only the hash of the token is stored; the raw token is only ever emailed to the player.
+#[allow(dead_code)] // Every column is mapped, even those the service does not read yet.
+#[derive(FromRow)]
+pub struct OneTimeToken {
+    pub id: Uuid,
+    pub player_id: Uuid,
+    pub purpose: String,
+    pub token_hash: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+    /// The new email address for email change tokens, or the address a magic link was sent to.
+    pub email: Option<String>,
+}
+
+/// The TotpCredential model represents a row from the `totp_credentials` table in our database.
+///
+/// # Notes
+/// * Two-factor authentication is only enabled once `secret` is set. A `pending_secret` is one which
+///   has been issued, but not yet confirmed with a code.
+#[allow(dead_code)] // Every column is mapped, even those the service does not read yet.
+#[derive(FromRow)]
+pub struct TotpCredential {
+    pub player_id: Uuid,
+    pub secret: Option<String>,
+    pub pending_secret: Option<String>,
+    pub enabled_at: Option<DateTime<Utc>>,
+    pub last_used_step: Option<i64>,
+}
+
+/// The RecoveryCode model represents a row from the `recovery_codes` table in our database.
+///
+/// # Notes
+/// * Codes are hashed with Argon2, so they must be checked with `hashing::verify_password`.
+#[allow(dead_code)] // Every column is mapped, even those the service does not read yet.
+#[derive(FromRow)]
+pub struct RecoveryCode {
+    pub id: Uuid,
+    pub player_id: Uuid,
+    pub code_hash: String,
+    pub created_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+/// The Passkey model represents a row from the `passkeys` table in our database.
+///
+/// # Notes
+/// * `credential_id` is base64url encoded, and `public_key` is a COSE key.
+#[allow(dead_code)] // Every column is mapped, even those the service does not read yet.
+#[derive(FromRow)]
+pub struct Passkey {
+    pub id: Uuid,
+    pub player_id: Uuid,
+    pub credential_id: String,
+    pub public_key: Vec<u8>,
+    pub sign_count: i64,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// Which WebAuthn ceremony a challenge was issued for. Stored in the `ceremony` column of
+/// `webauthn_challenges`.
+#[derive(Clone, Copy)]
+pub enum Ceremony {
+    Registration,
+    Authentication,
+}
+
+impl Ceremony {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Ceremony::Registration => "registration",
+            Ceremony::Authentication => "authentication",
+        }
+    }
+}
+
+/// The WebauthnChallenge model represents a row from the `webauthn_challenges` table in our
+/// database.
+///
+/// # Notes
+/// * Only the hash of the challenge is stored, like a one time token.
+#[allow(dead_code)] // Every column is mapped, even those the service does not read yet.
+#[derive(FromRow)]
+pub struct WebauthnChallenge {
+    pub id: Uuid,
+    pub player_id: Option<Uuid>,
+    pub ceremony: String,
+    pub challenge_hash: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+/// The result of a password login attempt. Stored in the `outcome` column of `login_attempts`.
+#[derive(Clone, Copy)]
+pub enum LoginOutcome {
+    Succeeded,
+    Failed,
+    /// The attempt was rejected without checking the password, because the account was locked.
+    Locked,
+}
+
+impl LoginOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoginOutcome::Succeeded => "succeeded",
+            LoginOutcome::Failed => "failed",
+            LoginOutcome::Locked => "locked",
+        }
+    }
+}
+
+/// The LoginAttempt model represents a row from the `login_attempts` table in our database.
+///
+/// # Notes
+/// * `username` is what was entered, and `player_id` is only set if a player had that username.
+#[allow(dead_code)] // Every column is mapped, even those the service does not read yet.
+#[derive(FromRow)]
+pub struct LoginAttempt {
+    pub id: Uuid,
+    pub player_id: Option<Uuid>,
+    pub username: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub outcome: String,
+    pub attempted_at: DateTime<Utc>,
+}
+
+/// The progress of an account deletion. Stored in the `status` column of `account_deletions`.
+#[derive(Clone, Copy)]
+pub enum DeletionStatus {
+    InProgress,
+    Completed,
+    /// A service could not delete the player's data, so the player was kept.
+    Failed,
+}
+
+impl DeletionStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeletionStatus::InProgress => "in_progress",
+            DeletionStatus::Completed => "completed",
+            DeletionStatus::Failed => "failed",
+        }
+    }
+}
+
+/// The AccountDeletion model represents a row from the `account_deletions` table in our database.
+#[derive(FromRow)]
+pub struct AccountDeletion {
+    pub player_id: Uuid,
+    pub status: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// The progress of one service's part in an account deletion. Stored in the `status` column of
+/// `account_deletion_steps`.
+#[derive(Clone, Copy)]
+pub enum DeletionStepStatus {
+    Pending,
+    Completed,
+    /// The service was asked too many times, and the step was given up on.
+    Failed,
+}
+
+impl DeletionStepStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeletionStepStatus::Pending => "pending",
+            DeletionStepStatus::Completed => "completed",
+            DeletionStepStatus::Failed => "failed",
+        }
+    }
+}
+
+/// The AccountDeletionStep model represents a row from the `account_deletion_steps` table in our
+/// database.
+#[derive(FromRow)]
+pub struct AccountDeletionStep {
+    pub player_id: Uuid,
+    pub service: String,
+    pub status: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub next_attempt_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// The kind of an event about a player. Stored in the `event_type` column of `outbox`.
+#[derive(Clone, Copy)]
+pub enum PlayerEvent {
+    Created,
+    Updated,
+    Deleted,
+    Login,
+}
+
+impl PlayerEvent {
+    pub const ALL: [PlayerEvent; 4] = [
+        PlayerEvent::Created,
+        PlayerEvent::Updated,
+        PlayerEvent::Deleted,
+        PlayerEvent::Login,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PlayerEvent::Created => "player.created",
+            PlayerEvent::Updated => "player.updated",
+            PlayerEvent::Deleted => "player.deleted",
+            PlayerEvent::Login => "player.login",
+        }
+    }
+}
+
+/// The OutboxEvent model represents a row from the `outbox` table in our database.
+#[allow(dead_code)] // Every column is mapped, even those the service does not read yet.
+#[derive(FromRow)]
+pub struct OutboxEvent {
+    pub id: Uuid,
+    pub sequence: i64,
+    pub event_type: String,
+    pub player_id: Uuid,
+    pub payload: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub next_attempt_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    pub dead_at: Option<DateTime<Utc>>,
+}
+
+/// The WebhookSubscription model represents a row from the `webhook_subscriptions` table in our
+/// database.
+#[allow(dead_code)] // Every column is mapped, even those the service does not read yet.
+#[derive(FromRow)]
+pub struct WebhookSubscription {
+    pub id: Uuid,
+    pub url: String,
+    pub events: Vec<String>,
+    pub secret: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The progress of a webhook delivery. Stored in the `status` column of `webhook_deliveries`.
+#[derive(Clone, Copy)]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    /// The delivery failed too many times, and is no longer retried.
+    Dead,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Dead => "dead",
+        }
+    }
+}
+
+/// The WebhookDelivery model represents a row from the `webhook_deliveries` table in our database.
+#[allow(dead_code)] // Every column is mapped, even those the service does not read yet.
+#[derive(FromRow)]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub subscription_id: Uuid,
+    pub event_id: Uuid,
+    pub event_type: String,
+    pub player_id: Uuid,
+    pub payload: serde_json::Value,
+    pub occurred_at: DateTime<Utc>,
+    pub status: String,
+    pub attempts: i32,
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub next_attempt_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
 }
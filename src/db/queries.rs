@@ -3,10 +3,37 @@
 //! # Notes
 //! * Some of these functions return Player structs - these contain **sensitive player data** (such
 //!   as hashed passwords) and therefore should NEVER be returned to the client as-is.
+//! * Queries against the tables which reference `players` live in their own submodules.
 
-use sqlx::PgPool;
+pub mod account_deletions;
+pub mod login_attempts;
+pub mod one_time_tokens;
+pub mod outbox;
+pub mod passkeys;
+pub mod rate_limit_buckets;
+pub mod recovery_codes;
+pub mod refresh_tokens;
+pub mod revoked_tokens;
+pub mod sessions;
+pub mod totp_credentials;
+pub mod username_history;
+pub mod webauthn_challenges;
+pub mod webhooks;
 
-use crate::{db::models::Player, jwt::AuthnTokenPayload};
+use chrono::{DateTime, Utc};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    db::{
+        models::{OneTimeToken, Player, TokenPurpose},
+        queries::{
+            outbox::{record_player_created, record_player_updated},
+            revoked_tokens::revoke_all_player_tokens_in,
+        },
+    },
+    jwt::AuthnTokenPayload,
+};
 
 /// Search for a single player by their username. This search is **case insensitive**, but it must
 /// otherwise be an exact match.
@@ -29,7 +56,7 @@ pub async fn get_player_by_username(
         Player,
         r#"
         SELECT * from players
-        WHERE username ILIKE $1
+        WHERE LOWER(username) = LOWER($1)
         "#,
         username
     )
@@ -37,6 +64,57 @@ pub async fn get_player_by_username(
     .await
 }
 
+/// Search for a single player by their email address. This search is **case insensitive**, but it
+/// must otherwise be an exact match.
+///
+/// # Notes
+/// * The return value of this function contains the hashed password and should **never** be
+///   returned to the client.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * email - The email address to be searched for.
+///
+/// # Returns
+/// A Player if it can be found, and an error if not.
+pub async fn get_player_by_email(pool: &PgPool, email: String) -> Result<Player, sqlx::Error> {
+    sqlx::query_as!(
+        Player,
+        r#"
+        SELECT * from players
+        WHERE LOWER(email) = LOWER($1)
+        "#,
+        email
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Search for a single player by their id.
+///
+/// # Notes
+/// * The return value of this function contains the hashed password and should **never** be
+///   returned to the client.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * id - The id of the player.
+///
+/// # Returns
+/// A Player if it can be found, and an error if not.
+pub async fn get_player_by_id(pool: &PgPool, id: Uuid) -> Result<Player, sqlx::Error> {
+    sqlx::query_as!(
+        Player,
+        r#"
+        SELECT * from players
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await
+}
+
 /// Search for a single player based on their authentication token payload.
 ///
 /// # Notes
@@ -75,12 +153,14 @@ pub async fn get_player_by_token(
 ///   password, as it will be inserted directly into the database.
 /// * The **most likely** cause of failure for this function is that the username and/or email
 ///   **already exist** in the database.
+/// * A `player.created` event is recorded along with the player.
 ///
 /// # Arguments
 /// * pool: The postgres connection pool.
 /// * username: The username of the new player.
 /// * email: The email address of the new player.
 /// * hash: The hashed password of the new player.
+/// * pepper_version: The version of the pepper the password was hashed with.
 ///
 /// # Returns
 /// The newly created player on success, and an error if not.
@@ -89,42 +169,386 @@ pub async fn create_new_player(
     username: String,
     email: String,
     hash: String,
+    pepper_version: Option<i32>,
 ) -> Result<Player, sqlx::Error> {
-    sqlx::query_as!(
+    let mut tx = pool.begin().await?;
+
+    let player = sqlx::query_as!(
         Player,
         r#"
-        INSERT INTO players (username, email, password)
-        VALUES ($1, $2, $3)
+        INSERT INTO players (username, email, password, password_pepper_version)
+        VALUES ($1, $2, $3, $4)
         RETURNING *;
         "#,
         username,
         email,
-        hash
+        hash,
+        pepper_version
     )
-    .fetch_one(pool)
-    .await
+    .fetch_one(&mut *tx)
+    .await?;
+
+    record_player_created(&mut tx, player.id).await?;
+
+    tx.commit().await?;
+    Ok(player)
+}
+
+/// Replace a player's password.
+///
+/// # Notes
+/// * This function does **not** hash the password internally! Do **not** pass in an unhashed
+///   password, as it will be inserted directly into the database.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * id - The id of the player.
+/// * hash - The hashed new password.
+/// * pepper_version - The version of the pepper the password was hashed with.
+pub async fn update_player_password(
+    pool: &PgPool,
+    id: Uuid,
+    hash: String,
+    pepper_version: Option<i32>,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    set_player_password(&mut tx, id, hash, pepper_version).await?;
+    tx.commit().await?;
+    Ok(())
 }
 
-/// Delete a single player account based on their authentication token payload.
+/// Replace a player's password after they have reset it, and revoke every session, access token
+/// and refresh token issued to them in the same transaction, so that the new password never works
+/// alongside a session which may have been stolen.
 ///
 /// # Notes
-/// * This function does **not** decode the authentication token.
+/// * This function does **not** hash the password internally! Do **not** pass in an unhashed
+///   password, as it will be inserted directly into the database.
 ///
 /// # Arguments
 /// * pool - The postgres connection pool.
-/// * payload - The decoded authentication token's payload.
-pub async fn delete_player_by_token(
+/// * id - The id of the player.
+/// * hash - The hashed new password.
+/// * pepper_version - The version of the pepper the password was hashed with.
+pub async fn reset_player_password(
     pool: &PgPool,
-    payload: AuthnTokenPayload,
+    id: Uuid,
+    hash: String,
+    pepper_version: Option<i32>,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    set_player_password(&mut tx, id, hash, pepper_version).await?;
+    revoke_all_player_tokens_in(&mut tx, id).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+async fn set_player_password(
+    conn: &mut PgConnection,
+    id: Uuid,
+    hash: String,
+    pepper_version: Option<i32>,
 ) -> Result<(), sqlx::Error> {
     sqlx::query!(
         r#"
-        DELETE FROM players
-        WHERE id = $1 AND username = $2 AND email = $3
+        UPDATE players
+        SET password = $2, password_pepper_version = $3
+        WHERE id = $1
         "#,
-        payload.sub,
-        payload.username,
-        payload.email
+        id,
+        hash,
+        pepper_version
+    )
+    .execute(&mut *conn)
+    .await?;
+    record_player_updated(conn, id, &["password"]).await
+}
+
+/// Replace a player's password hash with a new hash of the same password, such as one made with
+/// stronger parameters or a newer pepper.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * id - The id of the player.
+/// * old_hash - The hash being replaced. If the player's password has changed since it was read,
+///   nothing is updated.
+/// * new_hash - The new hash.
+/// * pepper_version - The version of the pepper the new hash was made with.
+///
+/// # Returns
+/// `true` if the hash was replaced, and `false` if the password had changed in the meantime.
+pub async fn rehash_player_password(
+    pool: &PgPool,
+    id: Uuid,
+    old_hash: &str,
+    new_hash: String,
+    pepper_version: Option<i32>,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE players
+        SET password = $3, password_pepper_version = $4
+        WHERE id = $1 AND password = $2
+        "#,
+        id,
+        old_hash,
+        new_hash,
+        pepper_version
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Mark a player's email address as verified. The original verification time is kept if it was
+/// already verified.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * id - The id of the player.
+pub async fn mark_email_verified(pool: &PgPool, id: Uuid) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE players
+        SET email_verified_at = now()
+        WHERE id = $1 AND email_verified_at IS NULL
+        "#,
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    if result.rows_affected() > 0 {
+        record_player_updated(&mut tx, id, &["email_verified"]).await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Replace a player's email address, using up the email change token which confirmed it. Since the
+/// player has just proven they own the new address, it is marked as verified. Any verification,
+/// password reset and magic links sent to the old address stop working, and every session, access
+/// token and refresh token (which carry the old address) is revoked. All of this happens in a
+/// single transaction, so the token is only used up if the address is actually changed.
+///
+/// # Notes
+/// * If the token has already been used or has expired (for example, because of a concurrent
+///   request), nothing is changed and `sqlx::Error::RowNotFound` is returned.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * token - The email change token being used.
+/// * email - The new email address.
+///
+/// # Returns
+/// An error if the query fails, including when another player already has the email address.
+pub async fn update_player_email(
+    pool: &PgPool,
+    token: &OneTimeToken,
+    email: String,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE one_time_tokens
+        SET used_at = now()
+        WHERE id = $1 AND used_at IS NULL AND expires_at > now()
+        RETURNING id
+        "#,
+        token.id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE players
+        SET email = $2, email_verified_at = now()
+        WHERE id = $1
+        "#,
+        token.player_id,
+        email
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE one_time_tokens
+        SET used_at = now()
+        WHERE player_id = $1 AND purpose = ANY($2) AND used_at IS NULL
+        "#,
+        token.player_id,
+        &[
+            TokenPurpose::EmailVerification.as_str(),
+            TokenPurpose::PasswordReset.as_str(),
+            TokenPurpose::MagicLink.as_str(),
+        ] as &[&str]
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    revoke_all_player_tokens_in(&mut tx, token.player_id).await?;
+    record_player_updated(&mut tx, token.player_id, &["email", "email_verified"]).await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Schedule a player's account to be purged, unless they restore it first. Every session, access
+/// token and refresh token issued to the player is revoked in the same transaction, so an account is
+/// never pending deletion while it is still signed in.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * id - The id of the player.
+/// * at - When the account should be purged.
+pub async fn schedule_player_deletion(
+    pool: &PgPool,
+    id: Uuid,
+    at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE players
+        SET deletion_scheduled_at = $2
+        WHERE id = $1
+        "#,
+        id,
+        at
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    revoke_all_player_tokens_in(&mut tx, id).await?;
+    record_player_updated(&mut tx, id, &["deletion_scheduled_at"]).await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Cancel the pending deletion of a player's account, as long as its grace period is not over. A
+/// `player.updated` event is only recorded if the account actually was pending deletion.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * id - The id of the player.
+///
+/// # Returns
+/// `true` if the account is no longer pending deletion (including if it already was not), and
+/// `false` if it does not exist or its grace period is over.
+pub async fn restore_player(pool: &PgPool, id: Uuid) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let restored = sqlx::query!(
+        r#"
+        WITH old AS (
+            SELECT id, deletion_scheduled_at FROM players
+            WHERE id = $1
+            FOR UPDATE
+        )
+        UPDATE players
+        SET deletion_scheduled_at = NULL
+        FROM old
+        WHERE players.id = old.id
+            AND (old.deletion_scheduled_at IS NULL OR old.deletion_scheduled_at > now())
+        RETURNING old.deletion_scheduled_at AS was_scheduled_at
+        "#,
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let was_pending = restored
+        .as_ref()
+        .is_some_and(|row| row.was_scheduled_at.is_some());
+    if was_pending {
+        record_player_updated(&mut tx, id, &["deletion_scheduled_at"]).await?;
+    }
+
+    tx.commit().await?;
+    Ok(restored.is_some())
+}
+
+/// Record that a player's bit wallet has been created.
+pub async fn mark_wallet_created(pool: &PgPool, id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE players
+        SET wallet_created_at = now()
+        WHERE id = $1 AND wallet_created_at IS NULL
+        "#,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Claim the players whose bit wallet has not been created yet, and is due to be attempted again,
+/// in the order they are due.
+///
+/// # Notes
+///
+/// * Each player is leased until `lease_until`, by moving their next attempt there, so that other
+///   replicas skip them while their wallet is being created. Players locked by another replica's
+///   claim are skipped too. Recording the outcome replaces the lease.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * limit - The most players to claim.
+/// * lease_until - When the claimed players may be claimed again.
+pub async fn claim_players_without_wallets(
+    pool: &PgPool,
+    limit: i64,
+    lease_until: DateTime<Utc>,
+) -> Result<Vec<Player>, sqlx::Error> {
+    sqlx::query_as!(
+        Player,
+        r#"
+        UPDATE players
+        SET wallet_next_attempt_at = $2
+        WHERE id IN (
+            SELECT id FROM players
+            WHERE wallet_created_at IS NULL AND wallet_next_attempt_at <= now()
+            ORDER BY wallet_next_attempt_at
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING *
+        "#,
+        limit,
+        lease_until
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Record a failed attempt at creating a player's bit wallet.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * id - The id of the player.
+/// * retry_at - When to try again.
+pub async fn fail_wallet_creation(
+    pool: &PgPool,
+    id: Uuid,
+    retry_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE players
+        SET wallet_attempts = wallet_attempts + 1, wallet_next_attempt_at = $2
+        WHERE id = $1
+        "#,
+        id,
+        retry_at
     )
     .execute(pool)
     .await?;
@@ -0,0 +1,301 @@
+//! Contains functions simplifying queries against the `account_deletions` and
+//! `account_deletion_steps` tables.
+
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::db::{
+    models::{AccountDeletion, AccountDeletionStep, DeletionStatus, DeletionStepStatus},
+    queries::outbox::record_player_deleted,
+};
+
+/// The account deletions which ended during a call to `finish_account_deletions`.
+pub struct FinishedDeletions {
+    /// The players who were deleted.
+    pub completed: Vec<Uuid>,
+    /// The players who were kept, because a service could not delete their data.
+    pub failed: Vec<Uuid>,
+}
+
+/// Start deleting every player whose grace period for deletion is over, and is not being deleted
+/// already, with a pending step for each service.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * services - The services which hold data about players.
+///
+/// # Returns
+/// The ids of the players whose deletion was started.
+pub async fn start_account_deletions(
+    pool: &PgPool,
+    services: &[&str],
+) -> Result<Vec<Uuid>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let player_ids = sqlx::query_scalar!(
+        r#"
+        INSERT INTO account_deletions (player_id, status)
+        SELECT id, $1 FROM players WHERE deletion_scheduled_at <= now()
+        ON CONFLICT (player_id) DO NOTHING
+        RETURNING player_id
+        "#,
+        DeletionStatus::InProgress.as_str()
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let services: Vec<String> = services.iter().map(|s| s.to_string()).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO account_deletion_steps (player_id, service, status)
+        SELECT player_id, service, $3
+        FROM UNNEST($1::uuid[]) AS player_id CROSS JOIN UNNEST($2::text[]) AS service
+        "#,
+        &player_ids,
+        &services,
+        DeletionStepStatus::Pending.as_str()
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(player_ids)
+}
+
+/// Claim the pending steps of account deletions in progress which are due to be attempted.
+///
+/// # Notes
+///
+/// * Each step is leased until `lease_until`, by moving its next attempt there, so that other
+///   replicas skip it while it is being attempted. Steps locked by another replica's claim are
+///   skipped too. Recording the outcome replaces the lease, and a step whose outcome is never
+///   recorded is attempted again once the lease is over.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * limit - The most steps to claim.
+/// * lease_until - When the claimed steps may be claimed again.
+pub async fn claim_due_deletion_steps(
+    pool: &PgPool,
+    limit: i64,
+    lease_until: DateTime<Utc>,
+) -> Result<Vec<AccountDeletionStep>, sqlx::Error> {
+    sqlx::query_as!(
+        AccountDeletionStep,
+        r#"
+        UPDATE account_deletion_steps s
+        SET next_attempt_at = $3
+        FROM (
+            SELECT s.player_id, s.service
+            FROM account_deletion_steps s
+            JOIN account_deletions d ON d.player_id = s.player_id
+            WHERE d.status = $1 AND s.status = $2 AND s.next_attempt_at <= now()
+            ORDER BY s.next_attempt_at
+            LIMIT $4
+            FOR UPDATE OF s SKIP LOCKED
+        ) due
+        WHERE s.player_id = due.player_id AND s.service = due.service
+        RETURNING s.player_id, s.service, s.status, s.attempts, s.last_error, s.next_attempt_at,
+            s.completed_at
+        "#,
+        DeletionStatus::InProgress.as_str(),
+        DeletionStepStatus::Pending.as_str(),
+        lease_until,
+        limit
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Record that a service has deleted a player's data.
+pub async fn complete_deletion_step(
+    pool: &PgPool,
+    player_id: Uuid,
+    service: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE account_deletion_steps
+        SET status = $3, attempts = attempts + 1, last_error = NULL, completed_at = now()
+        WHERE player_id = $1 AND service = $2
+        "#,
+        player_id,
+        service,
+        DeletionStepStatus::Completed.as_str()
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Record a failed attempt at a step.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * player_id - The id of the player being deleted.
+/// * service - The service which failed.
+/// * error - Why it failed.
+/// * retry_at - When to try again, or `None` to give up on the step.
+pub async fn fail_deletion_step(
+    pool: &PgPool,
+    player_id: Uuid,
+    service: &str,
+    error: &str,
+    retry_at: Option<DateTime<Utc>>,
+) -> Result<(), sqlx::Error> {
+    let status = match retry_at {
+        Some(_) => DeletionStepStatus::Pending,
+        None => DeletionStepStatus::Failed,
+    };
+    sqlx::query!(
+        r#"
+        UPDATE account_deletion_steps
+        SET status = $3, attempts = attempts + 1, last_error = $4,
+            next_attempt_at = COALESCE($5, next_attempt_at)
+        WHERE player_id = $1 AND service = $2
+        "#,
+        player_id,
+        service,
+        status.as_str(),
+        error,
+        retry_at
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// End the account deletions in progress which can no longer change. Those with a failed step are
+/// marked failed, and those whose steps have all completed are marked completed, and their players
+/// are deleted (along with everything which references them) with a `player.deleted` event each.
+pub async fn finish_account_deletions(pool: &PgPool) -> Result<FinishedDeletions, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let failed = sqlx::query_scalar!(
+        r#"
+        UPDATE account_deletions d
+        SET status = $2, finished_at = now()
+        WHERE d.status = $1 AND EXISTS (
+            SELECT 1 FROM account_deletion_steps s
+            WHERE s.player_id = d.player_id AND s.status = $3
+        )
+        RETURNING player_id
+        "#,
+        DeletionStatus::InProgress.as_str(),
+        DeletionStatus::Failed.as_str(),
+        DeletionStepStatus::Failed.as_str()
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let completed = sqlx::query_scalar!(
+        r#"
+        UPDATE account_deletions d
+        SET status = $2, finished_at = now()
+        WHERE d.status = $1 AND NOT EXISTS (
+            SELECT 1 FROM account_deletion_steps s
+            WHERE s.player_id = d.player_id AND s.status <> $3
+        )
+        RETURNING player_id
+        "#,
+        DeletionStatus::InProgress.as_str(),
+        DeletionStatus::Completed.as_str(),
+        DeletionStepStatus::Completed.as_str()
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let deleted = sqlx::query!(
+        "DELETE FROM players WHERE id = ANY($1) RETURNING id, username",
+        &completed
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    for player in deleted {
+        record_player_deleted(&mut tx, player.id, &player.username).await?;
+    }
+    tx.commit().await?;
+    Ok(FinishedDeletions { completed, failed })
+}
+
+/// List the latest account deletions, most recently started first.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * status - Only list deletions with this status, if it is given.
+/// * limit - The most deletions to return.
+pub async fn get_account_deletions(
+    pool: &PgPool,
+    status: Option<DeletionStatus>,
+    limit: i64,
+) -> Result<Vec<AccountDeletion>, sqlx::Error> {
+    sqlx::query_as!(
+        AccountDeletion,
+        r#"
+        SELECT * FROM account_deletions
+        WHERE ($1::text IS NULL OR status = $1)
+        ORDER BY started_at DESC
+        LIMIT $2
+        "#,
+        status.map(|s| s.as_str()),
+        limit
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Get every step of some account deletions.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * player_ids - The ids of the players being deleted.
+pub async fn get_account_deletion_steps(
+    pool: &PgPool,
+    player_ids: &[Uuid],
+) -> Result<Vec<AccountDeletionStep>, sqlx::Error> {
+    sqlx::query_as!(
+        AccountDeletionStep,
+        r#"
+        SELECT player_id, service, status, attempts, last_error, next_attempt_at, completed_at
+        FROM account_deletion_steps
+        WHERE player_id = ANY($1)
+        ORDER BY player_id, service
+        "#,
+        player_ids
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Resume a failed account deletion, so that its failed steps are attempted again straight away with
+/// their attempts reset. Steps which already completed are not repeated.
+///
+/// # Returns
+/// `true` if the deletion was resumed, and `false` if there is no such failed deletion.
+pub async fn retry_account_deletion(pool: &PgPool, player_id: Uuid) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query!(
+        r#"
+        UPDATE account_deletions
+        SET status = $2, finished_at = NULL
+        WHERE player_id = $1 AND status = $3
+        "#,
+        player_id,
+        DeletionStatus::InProgress.as_str(),
+        DeletionStatus::Failed.as_str()
+    )
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+    sqlx::query!(
+        r#"
+        UPDATE account_deletion_steps
+        SET status = $2, attempts = 0, next_attempt_at = now()
+        WHERE player_id = $1 AND status = $3
+        "#,
+        player_id,
+        DeletionStepStatus::Pending.as_str(),
+        DeletionStepStatus::Failed.as_str()
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(true)
+}
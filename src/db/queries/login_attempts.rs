@@ -0,0 +1,87 @@
+//! Contains functions simplifying queries against the `login_attempts` table.
+
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::db::models::LoginOutcome;
+
+/// The failed login attempts for an account since its last successful login.
+pub struct FailureStreak {
+    pub failures: i64,
+    pub last_failure_at: Option<DateTime<Utc>>,
+}
+
+/// Record a password login attempt.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * player_id - The id of the player with the username, if there is one.
+/// * username - The username which was entered.
+/// * ip_address - The IP address the attempt came from.
+/// * user_agent - The user agent of the client.
+/// * outcome - The result of the attempt.
+pub async fn record_login_attempt(
+    pool: &PgPool,
+    player_id: Option<Uuid>,
+    username: &str,
+    ip_address: Option<String>,
+    user_agent: Option<String>,
+    outcome: LoginOutcome,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO login_attempts (player_id, username, ip_address, user_agent, outcome)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        player_id,
+        username,
+        ip_address,
+        user_agent,
+        outcome.as_str()
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Count the failed login attempts for an account since its last successful login. Attempts
+/// rejected because the account was locked are not counted.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * player_id - The id of the player with the username, if there is one. Their attempts are
+///   counted whatever username they were made with, so renaming the account or changing the case of
+///   the username does not start a new streak.
+/// * username - The username which was entered. Only used (ignoring case) when no player has it.
+pub async fn get_failure_streak(
+    pool: &PgPool,
+    player_id: Option<Uuid>,
+    username: &str,
+) -> Result<FailureStreak, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        WITH attempts AS (
+            SELECT outcome, attempted_at FROM login_attempts
+            WHERE player_id = $1 OR ($1::uuid IS NULL AND LOWER(username) = LOWER($2))
+        )
+        SELECT COUNT(*) AS "failures!", MAX(attempted_at) AS last_failure_at
+        FROM attempts
+        WHERE outcome = $3
+            AND attempted_at > COALESCE(
+                (SELECT MAX(attempted_at) FROM attempts WHERE outcome = $4),
+                '-infinity'
+            )
+        "#,
+        player_id,
+        username,
+        LoginOutcome::Failed.as_str(),
+        LoginOutcome::Succeeded.as_str()
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(FailureStreak {
+        failures: row.failures,
+        last_failure_at: row.last_failure_at,
+    })
+}
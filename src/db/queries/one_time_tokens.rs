@@ -0,0 +1,168 @@
+//! Contains functions simplifying queries against the `one_time_tokens` table.
+//!
+//! # Notes
+//! * None of these functions hash the token internally! Always pass in the result of
+//!   `tokens::hash_token`, never the raw token.
+
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::db::models::{OneTimeToken, TokenPurpose};
+
+/// Store a new one time token. Any unused tokens the player already has for the same purpose are
+/// invalidated, so only the most recently emailed token works.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * player_id - The id of the player the token belongs to.
+/// * purpose - What the token can be used for.
+/// * token_hash - The hashed token.
+/// * expires_at - The moment the token is no longer valid.
+/// * email - The new email address for email change tokens, or the address a magic link is sent to.
+///
+/// # Returns
+/// The newly created token on success, and an error if not.
+pub async fn create_one_time_token(
+    pool: &PgPool,
+    player_id: Uuid,
+    purpose: TokenPurpose,
+    token_hash: String,
+    expires_at: DateTime<Utc>,
+    email: Option<String>,
+) -> Result<OneTimeToken, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE one_time_tokens
+        SET used_at = now()
+        WHERE player_id = $1 AND purpose = $2 AND used_at IS NULL
+        "#,
+        player_id,
+        purpose.as_str()
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let token = sqlx::query_as!(
+        OneTimeToken,
+        r#"
+        INSERT INTO one_time_tokens (player_id, purpose, token_hash, expires_at, email)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING *;
+        "#,
+        player_id,
+        purpose.as_str(),
+        token_hash,
+        expires_at,
+        email
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(token)
+}
+
+/// Find the most recently created token a player has for a given purpose, whether or not it has been
+/// used.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * player_id - The id of the player the token belongs to.
+/// * purpose - What the token can be used for.
+///
+/// # Returns
+/// The latest token if there is one, and an error if the query fails.
+pub async fn get_latest_one_time_token(
+    pool: &PgPool,
+    player_id: Uuid,
+    purpose: TokenPurpose,
+) -> Result<Option<OneTimeToken>, sqlx::Error> {
+    sqlx::query_as!(
+        OneTimeToken,
+        r#"
+        SELECT * FROM one_time_tokens
+        WHERE player_id = $1 AND purpose = $2
+        ORDER BY created_at DESC
+        LIMIT 1
+        "#,
+        player_id,
+        purpose.as_str()
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Find a one time token which could still be used, without using it up.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * purpose - What the token is being used for.
+/// * token_hash - The hashed token.
+///
+/// # Returns
+/// The token on success, and an error if there is no such valid token.
+pub async fn get_valid_one_time_token(
+    pool: &PgPool,
+    purpose: TokenPurpose,
+    token_hash: String,
+) -> Result<OneTimeToken, sqlx::Error> {
+    sqlx::query_as!(
+        OneTimeToken,
+        r#"
+        SELECT * FROM one_time_tokens
+        WHERE token_hash = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > now()
+        "#,
+        token_hash,
+        purpose.as_str()
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Use up a one time token. This succeeds at most once for each token, and only before it expires.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * purpose - What the token is being used for.
+/// * token_hash - The hashed token.
+///
+/// # Returns
+/// The token which was used on success, and an error if there is no such valid token.
+pub async fn consume_one_time_token(
+    pool: &PgPool,
+    purpose: TokenPurpose,
+    token_hash: String,
+) -> Result<OneTimeToken, sqlx::Error> {
+    sqlx::query_as!(
+        OneTimeToken,
+        r#"
+        UPDATE one_time_tokens
+        SET used_at = now()
+        WHERE token_hash = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > now()
+        RETURNING *;
+        "#,
+        token_hash,
+        purpose.as_str()
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Delete every token which has been used, or has expired.
+///
+/// # Returns
+/// The number of tokens deleted.
+pub async fn delete_finished_one_time_tokens(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM one_time_tokens
+        WHERE used_at IS NOT NULL OR expires_at < now()
+        "#
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
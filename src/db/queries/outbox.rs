@@ -0,0 +1,233 @@
+//! Contains functions simplifying queries against the `outbox` table.
+//!
+//! # Notes
+//! * The functions which record events take a connection rather than the pool, so that they can be
+//!   called inside the transaction which makes the change they describe.
+//! * Recording an event also queues its delivery to every webhook subscribed to it.
+
+use chrono::{DateTime, Utc};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::db::{
+    models::{OutboxEvent, PlayerEvent},
+    queries::webhooks::enqueue_webhook_deliveries,
+};
+
+/// Record an event carrying what other services may know about a player, as it is within the
+/// transaction.
+///
+/// # Arguments
+/// * conn - The connection the change was made on.
+/// * event_type - `Created` or `Updated`.
+/// * player_id - The id of the player.
+/// * changed - The names of the fields which changed, for `Updated`.
+async fn record_player_snapshot(
+    conn: &mut PgConnection,
+    event_type: PlayerEvent,
+    player_id: Uuid,
+    changed: Option<&[&str]>,
+) -> Result<(), sqlx::Error> {
+    let changed: Option<Vec<String>> =
+        changed.map(|fields| fields.iter().map(|f| f.to_string()).collect());
+    let event = sqlx::query!(
+        r#"
+        INSERT INTO outbox (event_type, player_id, payload)
+        SELECT $1, id, jsonb_build_object(
+            'id', id,
+            'username', username,
+            'email', email,
+            'email_verified', email_verified_at IS NOT NULL,
+            'created_at', created_at,
+            'deletion_scheduled_at', deletion_scheduled_at
+        ) || CASE WHEN $3::text[] IS NULL THEN '{}'::jsonb
+            ELSE jsonb_build_object('changed', $3::text[]) END
+        FROM players
+        WHERE id = $2
+        RETURNING id
+        "#,
+        event_type.as_str(),
+        player_id,
+        changed.as_deref()
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    if let Some(event) = event {
+        enqueue_webhook_deliveries(conn, event.id).await?;
+    }
+    Ok(())
+}
+
+/// Record a `player.created` event.
+pub async fn record_player_created(
+    conn: &mut PgConnection,
+    player_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    record_player_snapshot(conn, PlayerEvent::Created, player_id, None).await
+}
+
+/// Record a `player.updated` event.
+///
+/// # Arguments
+/// * conn - The connection the change was made on.
+/// * player_id - The id of the player.
+/// * changed - The names of the fields which changed. Secret fields (such as `password`) are named,
+///   but their values are never included.
+pub async fn record_player_updated(
+    conn: &mut PgConnection,
+    player_id: Uuid,
+    changed: &[&str],
+) -> Result<(), sqlx::Error> {
+    record_player_snapshot(conn, PlayerEvent::Updated, player_id, Some(changed)).await
+}
+
+/// Record a `player.deleted` event.
+pub async fn record_player_deleted(
+    conn: &mut PgConnection,
+    player_id: Uuid,
+    username: &str,
+) -> Result<(), sqlx::Error> {
+    let event = sqlx::query!(
+        r#"
+        INSERT INTO outbox (event_type, player_id, payload)
+        VALUES ($1, $2, jsonb_build_object('id', $2::uuid, 'username', $3::text))
+        RETURNING id
+        "#,
+        PlayerEvent::Deleted.as_str(),
+        player_id,
+        username
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    enqueue_webhook_deliveries(conn, event.id).await
+}
+
+/// Record a `player.login` event, for a new session.
+pub async fn record_player_login(
+    conn: &mut PgConnection,
+    player_id: Uuid,
+    session_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let event = sqlx::query!(
+        r#"
+        INSERT INTO outbox (event_type, player_id, payload)
+        VALUES ($1, $2, jsonb_build_object('id', $2::uuid, 'session_id', $3::uuid))
+        RETURNING id
+        "#,
+        PlayerEvent::Login.as_str(),
+        player_id,
+        session_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    enqueue_webhook_deliveries(conn, event.id).await
+}
+
+/// The key of the advisory lock held by the replica relaying events, so that only one replica
+/// relays them at a time and they stay in order.
+const RELAY_LOCK_KEY: i64 = 0x6f75_7462_6f78; // "outbox"
+
+/// Try to become the only relay of events until the transaction ends.
+///
+/// # Returns
+/// * `Ok(true)` if the lock was taken.
+/// * `Ok(false)` if another replica is relaying events.
+pub async fn lock_event_relay(conn: &mut PgConnection) -> Result<bool, sqlx::Error> {
+    let locked = sqlx::query_scalar!(
+        r#"SELECT pg_try_advisory_xact_lock($1) AS "locked!""#,
+        RELAY_LOCK_KEY
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(locked)
+}
+
+/// Get the oldest event which is due to be delivered. Nothing is returned while an older event is
+/// waiting to be retried, so that it is not overtaken. Dead events are skipped.
+///
+/// # Arguments
+/// * conn - The connection holding the relay lock (see `lock_event_relay`).
+pub async fn get_next_undelivered_event(
+    conn: &mut PgConnection,
+) -> Result<Option<OutboxEvent>, sqlx::Error> {
+    sqlx::query_as!(
+        OutboxEvent,
+        r#"
+        SELECT * FROM outbox
+        WHERE delivered_at IS NULL AND dead_at IS NULL
+            AND sequence < COALESCE((
+                SELECT min(sequence) FROM outbox
+                WHERE delivered_at IS NULL AND dead_at IS NULL AND next_attempt_at > now()
+            ), 9223372036854775807)
+        ORDER BY sequence
+        LIMIT 1
+        "#
+    )
+    .fetch_optional(conn)
+    .await
+}
+
+/// Record that an event has been delivered.
+pub async fn mark_event_delivered(conn: &mut PgConnection, id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE outbox
+        SET delivered_at = now(), attempts = attempts + 1, last_error = NULL
+        WHERE id = $1
+        "#,
+        id
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Record a failed attempt to deliver an event.
+///
+/// # Arguments
+/// * conn - The connection holding the relay lock (see `lock_event_relay`).
+/// * id - The id of the event.
+/// * error - Why it failed.
+/// * retry_at - When to try again, or `None` to give up and mark the event dead.
+pub async fn fail_event_delivery(
+    conn: &mut PgConnection,
+    id: Uuid,
+    error: &str,
+    retry_at: Option<DateTime<Utc>>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE outbox
+        SET attempts = attempts + 1, last_error = $2,
+            next_attempt_at = COALESCE($3, next_attempt_at),
+            dead_at = CASE WHEN $3::timestamptz IS NULL THEN now() END
+        WHERE id = $1
+        "#,
+        id,
+        error,
+        retry_at
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Delete the events which were delivered before a moment.
+///
+/// # Returns
+/// The number of events deleted.
+pub async fn delete_delivered_events(
+    pool: &PgPool,
+    before: DateTime<Utc>,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM outbox
+        WHERE delivered_at < $1
+        "#,
+        before
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
@@ -0,0 +1,123 @@
+//! Contains functions simplifying queries against the `passkeys` table.
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::db::models::Passkey;
+
+/// Store a passkey which has passed the registration ceremony.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * player_id - The id of the player the passkey belongs to.
+/// * credential_id - The base64url encoded credential id.
+/// * public_key - The COSE public key.
+/// * sign_count - The authenticator's signature counter.
+/// * name - The name the player gave the passkey.
+///
+/// # Returns
+/// The newly created passkey on success, and an error if not (such as when the credential is already
+/// registered).
+pub async fn create_passkey(
+    pool: &PgPool,
+    player_id: Uuid,
+    credential_id: String,
+    public_key: Vec<u8>,
+    sign_count: i64,
+    name: String,
+) -> Result<Passkey, sqlx::Error> {
+    sqlx::query_as!(
+        Passkey,
+        r#"
+        INSERT INTO passkeys (player_id, credential_id, public_key, sign_count, name)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING *;
+        "#,
+        player_id,
+        credential_id,
+        public_key,
+        sign_count,
+        name
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Find every passkey a player has registered, oldest first.
+pub async fn get_passkeys(pool: &PgPool, player_id: Uuid) -> Result<Vec<Passkey>, sqlx::Error> {
+    sqlx::query_as!(
+        Passkey,
+        r#"
+        SELECT * FROM passkeys
+        WHERE player_id = $1
+        ORDER BY created_at
+        "#,
+        player_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Search for a passkey by its base64url encoded credential id.
+pub async fn get_passkey_by_credential_id(
+    pool: &PgPool,
+    credential_id: &str,
+) -> Result<Option<Passkey>, sqlx::Error> {
+    sqlx::query_as!(
+        Passkey,
+        r#"
+        SELECT * FROM passkeys
+        WHERE credential_id = $1
+        "#,
+        credential_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Record that a passkey has been used to sign in.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * id - The id of the passkey.
+/// * sign_count - The authenticator's new signature counter.
+///
+/// # Returns
+/// `true` if the use was recorded, and `false` if the counter has already reached `sign_count` (so
+/// the same assertion is being used twice).
+pub async fn record_passkey_use(
+    pool: &PgPool,
+    id: Uuid,
+    sign_count: i64,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE passkeys
+        SET sign_count = $2, last_used_at = now()
+        WHERE id = $1 AND (sign_count < $2 OR (sign_count = 0 AND $2 = 0))
+        "#,
+        id,
+        sign_count
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Remove one of a player's passkeys.
+///
+/// # Returns
+/// `true` if the passkey was removed, and `false` if the player has no such passkey.
+pub async fn delete_passkey(pool: &PgPool, player_id: Uuid, id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM passkeys
+        WHERE id = $1 AND player_id = $2
+        "#,
+        id,
+        player_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
@@ -0,0 +1,77 @@
+//! Contains functions simplifying queries against the `rate_limit_buckets` table.
+
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use crate::rate_limit::Bucket;
+
+/// Update a bucket, holding a lock on it so that concurrent requests (from any replica) are
+/// counted one after another.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * key - The key of the bucket.
+/// * update - Given the bucket (or `None` if there is no such bucket yet), returns its new state
+///   along with a value to return.
+///
+/// # Returns
+/// The value returned by `update`, and an error if the bucket could not be read or written.
+pub async fn update_rate_limit_bucket<T>(
+    pool: &PgPool,
+    key: &str,
+    update: impl FnOnce(Option<Bucket>) -> (Bucket, T),
+) -> Result<T, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let bucket = sqlx::query!(
+        r#"
+        SELECT tokens, updated_at FROM rate_limit_buckets
+        WHERE key = $1
+        FOR UPDATE
+        "#,
+        key
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .map(|row| Bucket {
+        tokens: row.tokens,
+        updated_at: row.updated_at,
+    });
+
+    let (bucket, value) = update(bucket);
+
+    // Two replicas may both insert a bucket which did not exist yet. The later write wins, which
+    // costs at most one extra request.
+    sqlx::query!(
+        r#"
+        INSERT INTO rate_limit_buckets (key, tokens, updated_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (key) DO UPDATE SET tokens = EXCLUDED.tokens, updated_at = EXCLUDED.updated_at
+        "#,
+        key,
+        bucket.tokens,
+        bucket.updated_at
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(value)
+}
+
+/// Delete every bucket which has not been used since a given moment.
+pub async fn delete_idle_rate_limit_buckets(
+    pool: &PgPool,
+    idle_since: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM rate_limit_buckets
+        WHERE updated_at < $1
+        "#,
+        idle_since
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,99 @@
+//! Contains functions simplifying queries against the `recovery_codes` table.
+//!
+//! # Notes
+//! * None of these functions hash the codes internally! Always pass in the result of
+//!   `hashing::hash_password`, never the raw code.
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::db::models::RecoveryCode;
+
+/// Replace a player's recovery codes with a new set. Every code from the old set stops working.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * player_id - The id of the player.
+/// * code_hashes - The hashed codes.
+pub async fn replace_recovery_codes(
+    pool: &PgPool,
+    player_id: Uuid,
+    code_hashes: Vec<String>,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM recovery_codes
+        WHERE player_id = $1
+        "#,
+        player_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO recovery_codes (player_id, code_hash)
+        SELECT $1, code_hash FROM UNNEST($2::text[]) AS code_hash
+        "#,
+        player_id,
+        &code_hashes
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Find every recovery code a player has not used yet.
+pub async fn get_unused_recovery_codes(
+    pool: &PgPool,
+    player_id: Uuid,
+) -> Result<Vec<RecoveryCode>, sqlx::Error> {
+    sqlx::query_as!(
+        RecoveryCode,
+        r#"
+        SELECT * FROM recovery_codes
+        WHERE player_id = $1 AND used_at IS NULL
+        "#,
+        player_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Count the recovery codes a player has not used yet.
+pub async fn count_unused_recovery_codes(
+    pool: &PgPool,
+    player_id: Uuid,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM recovery_codes
+        WHERE player_id = $1 AND used_at IS NULL
+        "#,
+        player_id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Use up a recovery code.
+///
+/// # Returns
+/// `true` if the code was used, and `false` if it had already been used.
+pub async fn use_recovery_code(pool: &PgPool, id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE recovery_codes
+        SET used_at = now()
+        WHERE id = $1 AND used_at IS NULL
+        "#,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
@@ -0,0 +1,150 @@
+//! Contains functions simplifying queries against the `refresh_tokens` table.
+//!
+//! # Notes
+//! * None of these functions hash the refresh token internally! Always pass in the result of
+//!   `tokens::hash_token`, never the raw token.
+
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::db::models::RefreshToken;
+
+/// Store a new refresh token.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * player_id - The id of the player the token belongs to.
+/// * family_id - The token family. Pass a new id when starting a new session.
+/// * token_hash - The hashed refresh token.
+/// * expires_at - The moment the token is no longer valid.
+///
+/// # Returns
+/// The newly created refresh token on success, and an error if not.
+pub async fn create_refresh_token(
+    pool: &PgPool,
+    player_id: Uuid,
+    family_id: Uuid,
+    token_hash: String,
+    expires_at: DateTime<Utc>,
+) -> Result<RefreshToken, sqlx::Error> {
+    sqlx::query_as!(
+        RefreshToken,
+        r#"
+        INSERT INTO refresh_tokens (player_id, family_id, token_hash, expires_at)
+        VALUES ($1, $2, $3, $4)
+        RETURNING *;
+        "#,
+        player_id,
+        family_id,
+        token_hash,
+        expires_at
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Search for a single refresh token by its hash.
+///
+/// # Notes
+/// * The token returned may be expired, used or revoked. It is the caller's responsibility to
+///   check.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * token_hash - The hashed refresh token.
+///
+/// # Returns
+/// A RefreshToken if it can be found, and an error if not.
+pub async fn get_refresh_token_by_hash(
+    pool: &PgPool,
+    token_hash: String,
+) -> Result<RefreshToken, sqlx::Error> {
+    sqlx::query_as!(
+        RefreshToken,
+        r#"
+        SELECT * FROM refresh_tokens
+        WHERE token_hash = $1
+        "#,
+        token_hash
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Mark a refresh token as used and replace it with a new token in the same family. Both steps
+/// happen in a single transaction.
+///
+/// # Notes
+/// * If the old token has already been used or revoked (for example, by a concurrent request),
+///   nothing is changed and `sqlx::Error::RowNotFound` is returned.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * old - The refresh token being exchanged.
+/// * token_hash - The hash of the replacement token.
+/// * expires_at - The moment the replacement token is no longer valid.
+///
+/// # Returns
+/// The replacement refresh token on success, and an error if not.
+pub async fn rotate_refresh_token(
+    pool: &PgPool,
+    old: &RefreshToken,
+    token_hash: String,
+    expires_at: DateTime<Utc>,
+) -> Result<RefreshToken, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE refresh_tokens
+        SET used_at = now()
+        WHERE id = $1 AND used_at IS NULL AND revoked_at IS NULL
+        RETURNING id
+        "#,
+        old.id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let new = sqlx::query_as!(
+        RefreshToken,
+        r#"
+        INSERT INTO refresh_tokens (player_id, family_id, token_hash, expires_at)
+        VALUES ($1, $2, $3, $4)
+        RETURNING *;
+        "#,
+        old.player_id,
+        old.family_id,
+        token_hash,
+        expires_at
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(new)
+}
+
+/// Revoke every refresh token in a family. This is used when reuse of an old refresh token is
+/// detected, as the family must be assumed to be compromised.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * family_id - The family to revoke.
+pub async fn revoke_refresh_token_family(
+    pool: &PgPool,
+    family_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE refresh_tokens
+        SET revoked_at = now()
+        WHERE family_id = $1 AND revoked_at IS NULL
+        "#,
+        family_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
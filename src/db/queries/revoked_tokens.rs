@@ -0,0 +1,136 @@
+//! Contains functions simplifying queries against the `revoked_tokens` table, as well as the
+//! `tokens_revoked_at` column of the `players` table and the `revoked_at` column of the `sessions`
+//! table. Together these make up the access token revocation store.
+
+use chrono::{DateTime, Utc};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::jwt::AuthnTokenPayload;
+
+/// Check whether an access token has been revoked, either individually, because its session was
+/// revoked, or because every token issued to the player before a certain time was revoked.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * payload - The decoded authentication token's payload.
+///
+/// # Returns
+/// `true` if the token has been revoked, and an error if the check could not be made.
+pub async fn is_token_revoked(
+    pool: &PgPool,
+    payload: &AuthnTokenPayload,
+) -> Result<bool, sqlx::Error> {
+    // `iat` only has a precision of one second, so a token is only known to have been issued before
+    // `tokens_revoked_at` if the whole second ended before it. Tokens issued within that same second
+    // are still caught, because revoking every token also revokes every session.
+    let issued_by = DateTime::<Utc>::from_timestamp(payload.iat as i64 + 1, 0).unwrap_or_default();
+    sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (SELECT 1 FROM revoked_tokens WHERE jti = $1)
+            OR EXISTS (SELECT 1 FROM players WHERE id = $2 AND tokens_revoked_at >= $3)
+            OR EXISTS (SELECT 1 FROM sessions WHERE id = $4 AND revoked_at IS NOT NULL)
+            AS "revoked!"
+        "#,
+        payload.jti,
+        payload.sub,
+        issued_by,
+        payload.sid
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Revoke a single access token. Revocations of tokens which have since expired are cleaned up at
+/// the same time.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * payload - The decoded payload of the token to revoke.
+pub async fn revoke_token(pool: &PgPool, payload: &AuthnTokenPayload) -> Result<(), sqlx::Error> {
+    let expires_at = DateTime::<Utc>::from_timestamp(payload.exp as i64, 0).unwrap_or_default();
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM revoked_tokens
+        WHERE expires_at < now()
+        "#
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO revoked_tokens (jti, player_id, expires_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (jti) DO NOTHING
+        "#,
+        payload.jti,
+        payload.sub,
+        expires_at
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Revoke every session, access token and refresh token which has been issued to a player so far.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * player_id - The id of the player.
+pub async fn revoke_all_player_tokens(pool: &PgPool, player_id: Uuid) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    revoke_all_player_tokens_in(&mut tx, player_id).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Revoke every session, access token and refresh token which has been issued to a player so far,
+/// as part of a larger change. The revocation only takes effect if the transaction is committed.
+///
+/// # Arguments
+/// * conn - The transaction the change is being made in.
+/// * player_id - The id of the player.
+pub async fn revoke_all_player_tokens_in(
+    conn: &mut PgConnection,
+    player_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE players
+        SET tokens_revoked_at = now()
+        WHERE id = $1
+        "#,
+        player_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE sessions
+        SET revoked_at = now()
+        WHERE player_id = $1 AND revoked_at IS NULL
+        "#,
+        player_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE refresh_tokens
+        SET revoked_at = now()
+        WHERE player_id = $1 AND revoked_at IS NULL
+        "#,
+        player_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,183 @@
+//! Contains functions simplifying queries against the `sessions` table.
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::db::{models::Session, queries::outbox::record_player_login};
+
+/// Start a new session for a player, and record a `player.login` event.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * player_id - The id of the player signing in.
+/// * user_agent - The `User-Agent` of the client signing in, if known.
+/// * ip_address - The IP address of the client signing in, if known.
+///
+/// # Returns
+/// The newly created session on success, and an error if not.
+pub async fn create_session(
+    pool: &PgPool,
+    player_id: Uuid,
+    user_agent: Option<String>,
+    ip_address: Option<String>,
+) -> Result<Session, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let session = sqlx::query_as!(
+        Session,
+        r#"
+        INSERT INTO sessions (player_id, user_agent, ip_address)
+        VALUES ($1, $2, $3)
+        RETURNING *;
+        "#,
+        player_id,
+        user_agent,
+        ip_address
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    record_player_login(&mut tx, player_id, session.id).await?;
+
+    tx.commit().await?;
+    Ok(session)
+}
+
+/// List every session belonging to a player which has neither been revoked nor run out of valid
+/// refresh tokens, most recently seen first.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * player_id - The id of the player.
+pub async fn get_active_sessions(
+    pool: &PgPool,
+    player_id: Uuid,
+) -> Result<Vec<Session>, sqlx::Error> {
+    sqlx::query_as!(
+        Session,
+        r#"
+        SELECT * FROM sessions s
+        WHERE s.player_id = $1
+            AND s.revoked_at IS NULL
+            AND EXISTS (
+                SELECT 1 FROM refresh_tokens r
+                WHERE r.family_id = s.id
+                    AND r.used_at IS NULL
+                    AND r.revoked_at IS NULL
+                    AND r.expires_at > now()
+            )
+        ORDER BY s.last_seen_at DESC
+        "#,
+        player_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Record that a session has just been used. To avoid writing on every single request, this only
+/// updates sessions which have not been seen for over a minute.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * session_id - The id of the session.
+pub async fn touch_session(pool: &PgPool, session_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE sessions
+        SET last_seen_at = now()
+        WHERE id = $1 AND last_seen_at < now() - INTERVAL '1 minute'
+        "#,
+        session_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Revoke a session belonging to a player, along with every refresh token issued to it.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * player_id - The id of the player who owns the session.
+/// * session_id - The id of the session to revoke.
+///
+/// # Returns
+/// `true` if the session was revoked, or `false` if the player has no such active session.
+pub async fn revoke_session(
+    pool: &PgPool,
+    player_id: Uuid,
+    session_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let revoked = sqlx::query!(
+        r#"
+        UPDATE sessions
+        SET revoked_at = now()
+        WHERE id = $1 AND player_id = $2 AND revoked_at IS NULL
+        "#,
+        session_id,
+        player_id
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected()
+        > 0;
+
+    sqlx::query!(
+        r#"
+        UPDATE refresh_tokens
+        SET revoked_at = now()
+        WHERE family_id = $1 AND player_id = $2 AND revoked_at IS NULL
+        "#,
+        session_id,
+        player_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(revoked)
+}
+
+/// Revoke every session belonging to a player except one, along with every refresh token issued to
+/// them. This signs the player out everywhere but the session making the request.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * player_id - The id of the player who owns the sessions.
+/// * keep_session_id - The id of the session which should stay signed in.
+pub async fn revoke_other_sessions(
+    pool: &PgPool,
+    player_id: Uuid,
+    keep_session_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE sessions
+        SET revoked_at = now()
+        WHERE player_id = $1 AND id <> $2 AND revoked_at IS NULL
+        "#,
+        player_id,
+        keep_session_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE refresh_tokens
+        SET revoked_at = now()
+        WHERE player_id = $1 AND family_id <> $2 AND revoked_at IS NULL
+        "#,
+        player_id,
+        keep_session_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
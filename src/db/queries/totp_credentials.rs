@@ -0,0 +1,136 @@
+//! Contains functions simplifying queries against the `totp_credentials` table.
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::db::models::TotpCredential;
+
+/// Search for a player's TOTP credential.
+///
+/// # Returns
+/// The credential if the player has one, `None` if they do not, and an error if the query fails.
+pub async fn get_totp_credential(
+    pool: &PgPool,
+    player_id: Uuid,
+) -> Result<Option<TotpCredential>, sqlx::Error> {
+    sqlx::query_as!(
+        TotpCredential,
+        r#"
+        SELECT * FROM totp_credentials
+        WHERE player_id = $1
+        "#,
+        player_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Store a new secret for a player, which is pending until it is confirmed. Any secret which is
+/// already enabled keeps working until then.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * player_id - The id of the player.
+/// * secret - The base32 encoded secret.
+pub async fn set_pending_totp_secret(
+    pool: &PgPool,
+    player_id: Uuid,
+    secret: String,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO totp_credentials (player_id, pending_secret)
+        VALUES ($1, $2)
+        ON CONFLICT (player_id) DO UPDATE SET pending_secret = EXCLUDED.pending_secret
+        "#,
+        player_id,
+        secret
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Replace a player's secret with their pending secret, enabling two-factor authentication.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * player_id - The id of the player.
+/// * step - The time step of the code which confirmed the pending secret.
+///
+/// # Returns
+/// `true` if the pending secret was confirmed, and `false` if the player has no pending secret.
+pub async fn confirm_totp_secret(
+    pool: &PgPool,
+    player_id: Uuid,
+    step: i64,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE totp_credentials
+        SET secret = pending_secret, pending_secret = NULL, enabled_at = now(), last_used_step = $2
+        WHERE player_id = $1 AND pending_secret IS NOT NULL
+        "#,
+        player_id,
+        step
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Record that a code from the player's enabled secret has been used.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * player_id - The id of the player.
+/// * step - The time step of the code.
+///
+/// # Returns
+/// `true` if the code had not been used before, and `false` if it (or a later code) already has.
+pub async fn record_totp_step(
+    pool: &PgPool,
+    player_id: Uuid,
+    step: i64,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE totp_credentials
+        SET last_used_step = $2
+        WHERE player_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)
+        "#,
+        player_id,
+        step
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Remove a player's TOTP credential and recovery codes, disabling two-factor authentication.
+pub async fn delete_totp_credential(pool: &PgPool, player_id: Uuid) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM totp_credentials
+        WHERE player_id = $1
+        "#,
+        player_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM recovery_codes
+        WHERE player_id = $1
+        "#,
+        player_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
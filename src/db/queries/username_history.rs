@@ -0,0 +1,121 @@
+//! Contains functions simplifying queries against the `username_history` table, which records every
+//! username a player has given up.
+
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::db::{
+    models::Player,
+    queries::{outbox::record_player_updated, revoked_tokens::revoke_all_player_tokens_in},
+};
+
+/// Check whether a username is unavailable, either because a player currently has it, or because a
+/// player gave it up recently enough that it is still reserved for them. This check is **case
+/// insensitive**.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * username - The username to check.
+/// * player_id - The player who wants the username, if they already exist. Their own current and
+///   previous usernames are not counted against them.
+/// * reserved_since - Usernames given up after this moment are still reserved.
+pub async fn is_username_taken(
+    pool: &PgPool,
+    username: &str,
+    player_id: Option<Uuid>,
+    reserved_since: DateTime<Utc>,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT
+            EXISTS (
+                SELECT 1 FROM players
+                WHERE LOWER(username) = LOWER($1) AND id IS DISTINCT FROM $2
+            )
+            OR EXISTS (
+                SELECT 1 FROM username_history
+                WHERE LOWER(username) = LOWER($1)
+                    AND player_id IS DISTINCT FROM $2
+                    AND changed_at > $3
+            ) AS "taken!"
+        "#,
+        username,
+        player_id,
+        reserved_since
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Find when a player last changed their username.
+///
+/// # Returns
+/// The time of the most recent rename, or `None` if the player has never renamed themselves.
+pub async fn get_last_username_change(
+    pool: &PgPool,
+    player_id: Uuid,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT MAX(changed_at) FROM username_history
+        WHERE player_id = $1
+        "#,
+        player_id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Rename a player, recording their old username in their history.
+///
+/// # Notes
+/// * Every access token names the player, so this also revokes every session, access token and
+///   refresh token issued to them before now, in the same transaction.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * player_id - The id of the player.
+/// * username - The new username.
+///
+/// # Returns
+/// The renamed player on success, and an error if not.
+pub async fn change_username(
+    pool: &PgPool,
+    player_id: Uuid,
+    username: String,
+) -> Result<Player, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO username_history (player_id, username)
+        SELECT id, username FROM players
+        WHERE id = $1
+        "#,
+        player_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let player = sqlx::query_as!(
+        Player,
+        r#"
+        UPDATE players
+        SET username = $2
+        WHERE id = $1
+        RETURNING *;
+        "#,
+        player_id,
+        username
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    revoke_all_player_tokens_in(&mut tx, player_id).await?;
+
+    record_player_updated(&mut tx, player_id, &["username"]).await?;
+
+    tx.commit().await?;
+    Ok(player)
+}
@@ -0,0 +1,86 @@
+//! Contains functions simplifying queries against the `webauthn_challenges` table.
+//!
+//! # Notes
+//! * None of these functions hash the challenge internally! Always pass in the result of
+//!   `tokens::hash_token`, never the raw challenge.
+
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::db::models::{Ceremony, WebauthnChallenge};
+
+/// Store a new challenge.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * player_id - The id of the player registering a passkey, or `None` for a sign in.
+/// * ceremony - The ceremony the challenge is for.
+/// * challenge_hash - The hashed challenge.
+/// * expires_at - The moment the challenge is no longer valid.
+pub async fn create_webauthn_challenge(
+    pool: &PgPool,
+    player_id: Option<Uuid>,
+    ceremony: Ceremony,
+    challenge_hash: String,
+    expires_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO webauthn_challenges (player_id, ceremony, challenge_hash, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        player_id,
+        ceremony.as_str(),
+        challenge_hash,
+        expires_at
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Use up a challenge. This succeeds at most once for each challenge, and only before it expires.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * ceremony - The ceremony the challenge is being used for.
+/// * challenge_hash - The hashed challenge.
+///
+/// # Returns
+/// The challenge which was used on success, and an error if there is no such valid challenge.
+pub async fn consume_webauthn_challenge(
+    pool: &PgPool,
+    ceremony: Ceremony,
+    challenge_hash: String,
+) -> Result<WebauthnChallenge, sqlx::Error> {
+    sqlx::query_as!(
+        WebauthnChallenge,
+        r#"
+        UPDATE webauthn_challenges
+        SET used_at = now()
+        WHERE challenge_hash = $1 AND ceremony = $2 AND used_at IS NULL AND expires_at > now()
+        RETURNING *;
+        "#,
+        challenge_hash,
+        ceremony.as_str()
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Delete every challenge which has been used, or has expired.
+///
+/// # Returns
+/// The number of challenges deleted.
+pub async fn delete_finished_webauthn_challenges(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM webauthn_challenges
+        WHERE used_at IS NOT NULL OR expires_at < now()
+        "#
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
@@ -0,0 +1,307 @@
+//! Contains functions simplifying queries against the `webhook_subscriptions` and
+//! `webhook_deliveries` tables.
+
+use chrono::{DateTime, Utc};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::db::models::{DeliveryStatus, WebhookDelivery, WebhookSubscription};
+
+/// A delivery which is due to be attempted, along with where to send it and how to sign it.
+pub struct DueWebhookDelivery {
+    pub id: Uuid,
+    pub event_id: Uuid,
+    pub event_type: String,
+    pub player_id: Uuid,
+    pub payload: serde_json::Value,
+    pub occurred_at: DateTime<Utc>,
+    pub attempts: i32,
+    pub url: String,
+    pub secret: String,
+}
+
+/// Create a webhook subscription.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * url - Where to send the events.
+/// * events - The types of the events to send.
+/// * secret - The secret deliveries are signed with.
+pub async fn create_webhook_subscription(
+    pool: &PgPool,
+    url: String,
+    events: Vec<String>,
+    secret: String,
+) -> Result<WebhookSubscription, sqlx::Error> {
+    sqlx::query_as!(
+        WebhookSubscription,
+        r#"
+        INSERT INTO webhook_subscriptions (url, events, secret)
+        VALUES ($1, $2, $3)
+        RETURNING *;
+        "#,
+        url,
+        &events,
+        secret
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// List every webhook subscription, oldest first.
+pub async fn get_webhook_subscriptions(
+    pool: &PgPool,
+) -> Result<Vec<WebhookSubscription>, sqlx::Error> {
+    sqlx::query_as!(
+        WebhookSubscription,
+        r#"
+        SELECT * FROM webhook_subscriptions
+        ORDER BY created_at
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Search for a webhook subscription by its id.
+pub async fn get_webhook_subscription(
+    pool: &PgPool,
+    id: Uuid,
+) -> Result<WebhookSubscription, sqlx::Error> {
+    sqlx::query_as!(
+        WebhookSubscription,
+        r#"
+        SELECT * FROM webhook_subscriptions
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Delete a webhook subscription, along with its deliveries.
+///
+/// # Returns
+/// `true` if the subscription was deleted, and `false` if it does not exist.
+pub async fn delete_webhook_subscription(pool: &PgPool, id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM webhook_subscriptions
+        WHERE id = $1
+        "#,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Queue an event from the outbox for delivery to every webhook subscribed to its type.
+///
+/// # Arguments
+/// * conn - The connection the event was recorded on.
+/// * event_id - The id of the event in `outbox`.
+pub async fn enqueue_webhook_deliveries(
+    conn: &mut PgConnection,
+    event_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO webhook_deliveries
+            (subscription_id, event_id, event_type, player_id, payload, occurred_at, status)
+        SELECT s.id, o.id, o.event_type, o.player_id, o.payload, o.created_at, $2
+        FROM outbox o
+        JOIN webhook_subscriptions s ON o.event_type = ANY(s.events)
+        WHERE o.id = $1
+        "#,
+        event_id,
+        DeliveryStatus::Pending.as_str()
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Claim the pending deliveries which are due to be attempted, oldest first.
+///
+/// # Notes
+///
+/// * Each delivery is leased until `lease_until`, by moving its next attempt there, so that other
+///   replicas skip it while it is being attempted. Deliveries locked by another replica's claim are
+///   skipped too. Recording the outcome replaces the lease, and a delivery whose outcome is never
+///   recorded is attempted again once the lease is over.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * limit - The most deliveries to claim.
+/// * lease_until - When the claimed deliveries may be claimed again.
+pub async fn claim_due_webhook_deliveries(
+    pool: &PgPool,
+    limit: i64,
+    lease_until: DateTime<Utc>,
+) -> Result<Vec<DueWebhookDelivery>, sqlx::Error> {
+    sqlx::query_as!(
+        DueWebhookDelivery,
+        r#"
+        UPDATE webhook_deliveries d
+        SET next_attempt_at = $3
+        FROM webhook_subscriptions s
+        WHERE s.id = d.subscription_id AND d.id IN (
+            SELECT id FROM webhook_deliveries
+            WHERE status = $1 AND next_attempt_at <= now()
+            ORDER BY created_at
+            LIMIT $2
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING d.id, d.event_id, d.event_type, d.player_id, d.payload, d.occurred_at, d.attempts,
+            s.url, s.secret
+        "#,
+        DeliveryStatus::Pending.as_str(),
+        limit,
+        lease_until
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Record that a delivery succeeded.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * id - The id of the delivery.
+/// * status_code - The status code the webhook responded with.
+pub async fn complete_webhook_delivery(
+    pool: &PgPool,
+    id: Uuid,
+    status_code: i32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE webhook_deliveries
+        SET status = $2, attempts = attempts + 1, last_status_code = $3, last_error = NULL,
+            delivered_at = now()
+        WHERE id = $1
+        "#,
+        id,
+        DeliveryStatus::Delivered.as_str(),
+        status_code
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Record a failed attempt at a delivery.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * id - The id of the delivery.
+/// * status_code - The status code the webhook responded with, if it responded.
+/// * error - Why it failed.
+/// * retry_at - When to try again, or `None` to give up on the delivery.
+pub async fn fail_webhook_delivery(
+    pool: &PgPool,
+    id: Uuid,
+    status_code: Option<i32>,
+    error: &str,
+    retry_at: Option<DateTime<Utc>>,
+) -> Result<(), sqlx::Error> {
+    let status = match retry_at {
+        Some(_) => DeliveryStatus::Pending,
+        None => DeliveryStatus::Dead,
+    };
+    sqlx::query!(
+        r#"
+        UPDATE webhook_deliveries
+        SET status = $2, attempts = attempts + 1, last_status_code = $3, last_error = $4,
+            next_attempt_at = COALESCE($5, next_attempt_at)
+        WHERE id = $1
+        "#,
+        id,
+        status.as_str(),
+        status_code,
+        error,
+        retry_at
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// List the latest deliveries to a webhook, newest first.
+///
+/// # Arguments
+/// * pool - The postgres connection pool.
+/// * subscription_id - The id of the subscription.
+/// * status - Only list deliveries with this status, if it is given.
+/// * limit - The most deliveries to return.
+pub async fn get_webhook_deliveries(
+    pool: &PgPool,
+    subscription_id: Uuid,
+    status: Option<DeliveryStatus>,
+    limit: i64,
+) -> Result<Vec<WebhookDelivery>, sqlx::Error> {
+    sqlx::query_as!(
+        WebhookDelivery,
+        r#"
+        SELECT * FROM webhook_deliveries
+        WHERE subscription_id = $1 AND ($2::text IS NULL OR status = $2)
+        ORDER BY created_at DESC
+        LIMIT $3
+        "#,
+        subscription_id,
+        status.map(|s| s.as_str()),
+        limit
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Revive a dead delivery, so that it is attempted again straight away with its attempts reset.
+///
+/// # Returns
+/// `true` if the delivery was revived, and `false` if there is no such dead delivery.
+pub async fn revive_webhook_delivery(
+    pool: &PgPool,
+    subscription_id: Uuid,
+    id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE webhook_deliveries
+        SET status = $3, attempts = 0, next_attempt_at = now()
+        WHERE id = $1 AND subscription_id = $2 AND status = $4
+        "#,
+        id,
+        subscription_id,
+        DeliveryStatus::Pending.as_str(),
+        DeliveryStatus::Dead.as_str()
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Delete the deliveries which were delivered or are dead, and were created before a moment. They
+/// carry a copy of the event, which holds details about the player.
+///
+/// # Returns
+/// The number of deliveries deleted.
+pub async fn delete_finished_webhook_deliveries(
+    pool: &PgPool,
+    before: DateTime<Utc>,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM webhook_deliveries
+        WHERE status IN ($1, $2) AND created_at < $3
+        "#,
+        DeliveryStatus::Delivered.as_str(),
+        DeliveryStatus::Dead.as_str(),
+        before
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
@@ -1,8 +1,18 @@
 //! This module holds handlers for all the HTTP routes controlled by the player microservice.
 
+pub mod account_deletions;
 pub mod authentication;
 pub mod creation;
 pub mod deletion;
 pub mod documentation;
+pub mod email;
 mod helper;
+pub mod jwks;
+pub mod passkeys;
+pub mod password;
+pub mod recovery_codes;
 pub mod responses;
+pub mod sessions;
+pub mod totp;
+pub mod username;
+pub mod webhooks;
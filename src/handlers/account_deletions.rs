@@ -0,0 +1,161 @@
+//! This module holds the handlers which let operators follow the deletion of accounts whose grace
+//! period is over (see `account_deletion`), and resume deletions which failed.
+//!
+//! # Notes
+//!
+//! - These routes are for operators rather than players. They are authenticated with the bearer
+//!   token in `ADMIN_API_KEY`, and are disabled if it is not set (see `helper::authorize_admin`).
+
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    db::{
+        models::{AccountDeletion, AccountDeletionStep, DeletionStatus, DeletionStepStatus},
+        queries::account_deletions::{
+            get_account_deletion_steps, get_account_deletions, retry_account_deletion,
+        },
+    },
+    handlers::{helper::authorize_admin, responses::message},
+};
+
+/// The most deletions listed at once.
+const DELETION_LOG_LIMIT: i64 = 100;
+
+#[derive(Deserialize)]
+pub struct DeletionLogQuery {
+    status: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct DeletionStepInfo {
+    service: String,
+    status: String,
+    attempts: i32,
+    last_error: Option<String>,
+    /// When the step will next be attempted, if it is pending.
+    next_attempt_at: Option<DateTime<Utc>>,
+    completed_at: Option<DateTime<Utc>>,
+}
+
+impl From<AccountDeletionStep> for DeletionStepInfo {
+    fn from(step: AccountDeletionStep) -> Self {
+        let pending = step.status == DeletionStepStatus::Pending.as_str();
+        DeletionStepInfo {
+            service: step.service,
+            status: step.status,
+            attempts: step.attempts,
+            last_error: step.last_error,
+            next_attempt_at: pending.then_some(step.next_attempt_at),
+            completed_at: step.completed_at,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct AccountDeletionInfo {
+    player_id: Uuid,
+    status: String,
+    started_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+    steps: Vec<DeletionStepInfo>,
+}
+
+/// List the latest account deletions, most recently started first, with the progress of each
+/// service. Optionally only those with a status (`in_progress`, `completed` or `failed`).
+pub async fn handle_list_account_deletions(
+    State(pool): State<PgPool>,
+    headers: HeaderMap,
+    Query(query): Query<DeletionLogQuery>,
+) -> Response {
+    if let Err((status, e)) = authorize_admin(headers) {
+        return (status, Json(e)).into_response();
+    }
+
+    let status = match query.status.as_deref() {
+        None => None,
+        Some("in_progress") => Some(DeletionStatus::InProgress),
+        Some("completed") => Some(DeletionStatus::Completed),
+        Some("failed") => Some(DeletionStatus::Failed),
+        Some(_) => {
+            return message(
+                StatusCode::BAD_REQUEST,
+                "Status must be in_progress, completed or failed.",
+            )
+        }
+    };
+
+    let failure = || {
+        message(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Account deletions could not be fetched.",
+        )
+    };
+    let deletions: Vec<AccountDeletion> =
+        match get_account_deletions(&pool, status, DELETION_LOG_LIMIT).await {
+            Ok(d) => d,
+            Err(_) => return failure(),
+        };
+    let player_ids: Vec<Uuid> = deletions.iter().map(|d| d.player_id).collect();
+    let mut steps: HashMap<Uuid, Vec<DeletionStepInfo>> = HashMap::new();
+    match get_account_deletion_steps(&pool, &player_ids).await {
+        Ok(s) => {
+            for step in s {
+                steps
+                    .entry(step.player_id)
+                    .or_default()
+                    .push(DeletionStepInfo::from(step));
+            }
+        }
+        Err(_) => return failure(),
+    }
+
+    let deletions: Vec<AccountDeletionInfo> = deletions
+        .into_iter()
+        .map(|deletion| AccountDeletionInfo {
+            steps: steps.remove(&deletion.player_id).unwrap_or_default(),
+            player_id: deletion.player_id,
+            status: deletion.status,
+            started_at: deletion.started_at,
+            finished_at: deletion.finished_at,
+        })
+        .collect();
+    (StatusCode::OK, Json(deletions)).into_response()
+}
+
+/// Resume a failed account deletion. The services which failed are asked again straight away, and
+/// the player is deleted once they all succeed.
+pub async fn handle_account_deletion_retry(
+    State(pool): State<PgPool>,
+    headers: HeaderMap,
+    Path(player_id): Path<Uuid>,
+) -> Response {
+    if let Err((status, e)) = authorize_admin(headers) {
+        return (status, Json(e)).into_response();
+    }
+
+    match retry_account_deletion(&pool, player_id).await {
+        Ok(true) => message(
+            StatusCode::ACCEPTED,
+            "Account deletion will be attempted again.",
+        ),
+        Ok(false) => message(
+            StatusCode::NOT_FOUND,
+            "Failed account deletion could not be found.",
+        ),
+        Err(_) => message(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Account deletion could not be retried.",
+        ),
+    }
+}
@@ -1,2 +1,8 @@
 pub mod login;
+pub mod logout;
+pub mod magic_link;
+pub mod mfa;
+pub mod passkey;
+pub mod refresh;
+pub mod restoration;
 pub mod token;
@@ -1,17 +1,32 @@
 use axum::{
     extract::State,
-    http::StatusCode,
+    http::{header::RETRY_AFTER, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
+use chrono::Utc;
 use serde::Deserialize;
 use sqlx::PgPool;
+use uuid::Uuid;
 
 use crate::{
-    db::queries::get_player_by_username,
-    handlers::responses::{MessageResponse, TokenResponse},
+    db::{
+        models::LoginOutcome,
+        models::Player,
+        queries::{
+            get_player_by_username,
+            login_attempts::{get_failure_streak, record_login_attempt},
+            rehash_player_password,
+            totp_credentials::get_totp_credential,
+        },
+    },
+    handlers::{
+        authentication::{mfa::start_mfa_challenge, restoration::finish_login},
+        helper::ClientInfo,
+        responses::{MessageResponse, MfaRequiredResponse},
+    },
     hashing,
-    jwt::{encode_authn_token, AuthnTokenReqs},
+    lockout::LockoutPolicy,
 };
 
 /// The expected request body shape for the login request.
@@ -21,32 +36,242 @@ pub struct ReqBody {
     password: String,
 }
 
-pub async fn handle_login(State(pool): State<PgPool>, Json(body): Json<ReqBody>) -> Response {
+fn locked_out(retry_after: i64) -> Response {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(RETRY_AFTER, retry_after.to_string())],
+        Json(MessageResponse::new(
+            "Too many failed login attempts. Please try again later.",
+        )),
+    )
+        .into_response()
+}
+
+/// Check how long the account is locked for, if at all. The account is the player's, if a player has
+/// the username, and the username itself if not.
+///
+/// # Returns
+///
+/// * `Ok(Some(i64))` containing the number of seconds until the account is unlocked.
+/// * `Ok(None)` if it is not locked.
+/// * `Err(sqlx::Error)` if the login attempts could not be queried.
+async fn lockout_remaining(
+    pool: &PgPool,
+    policy: &LockoutPolicy,
+    player_id: Option<Uuid>,
+    username: &str,
+) -> Result<Option<i64>, sqlx::Error> {
+    let streak = get_failure_streak(pool, player_id, username).await?;
+    Ok(policy.retry_after(streak.failures, streak.last_failure_at, Utc::now()))
+}
+
+/// Replace a player's password hash if it was made with outdated parameters or an old pepper, while
+/// the raw password is at hand. Failures are logged, since the old hash still works.
+async fn upgrade_password_hash(pool: &PgPool, player: &Player, password: &str) {
+    if !hashing::needs_rehash(&player.password, player.password_pepper_version) {
+        return;
+    }
+    let pepper_version = hashing::current_pepper_version();
+    let result = match hashing::hash_password(password, pepper_version) {
+        Ok(hash) => rehash_player_password(pool, player.id, &player.password, hash, pepper_version)
+            .await
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    if let Err(e) = result {
+        eprintln!(
+            "Failed to rehash the password of player {}: {}",
+            player.id, e
+        );
+    }
+}
+
+/// Log in using a username and password.
+///
+/// Every attempt is recorded. After too many consecutive failures, the account is locked for a while
+/// (see `lockout`), and attempts are rejected with `429 Too Many Requests` without checking the
+/// password. This happens whether or not a player has the username. The username must match the
+/// player's exactly, apart from case.
+pub async fn handle_login(
+    State(pool): State<PgPool>,
+    client: ClientInfo,
+    Json(body): Json<ReqBody>,
+) -> Response {
     let authn_failed = (
         StatusCode::UNAUTHORIZED,
         Json(MessageResponse::new("Authentication failed.")),
     )
         .into_response();
 
-    let player = get_player_by_username(&pool, body.username).await;
+    let policy = LockoutPolicy::from_env();
+    let player = get_player_by_username(&pool, body.username.clone())
+        .await
+        .ok();
+    let player_id = player.as_ref().map(|p| p.id);
+    let record = |outcome| {
+        record_login_attempt(
+            &pool,
+            player_id,
+            &body.username,
+            client.ip_address.clone(),
+            client.user_agent.clone(),
+            outcome,
+        )
+    };
 
-    let player = match player {
-        Ok(p) => p,
+    match lockout_remaining(&pool, &policy, player_id, &body.username).await {
+        Ok(None) => (),
+        Ok(Some(wait)) => {
+            let _ = record(LoginOutcome::Locked).await;
+            return locked_out(wait);
+        }
         Err(_) => return authn_failed,
+    }
+
+    // Argon2 runs whether or not the player exists, so that response times do not reveal which
+    // usernames exist.
+    let pw_match = hashing::verify_password_or_dummy(
+        &body.password,
+        player
+            .as_ref()
+            .map(|p| (p.password.as_str(), p.password_pepper_version)),
+    );
+    let player = match player {
+        Some(p) if pw_match => p,
+        _ => {
+            if record(LoginOutcome::Failed).await.is_err() {
+                return authn_failed;
+            }
+            // Let the client know straight away if this failure locked the username.
+            return match lockout_remaining(&pool, &policy, player_id, &body.username).await {
+                Ok(Some(wait)) => locked_out(wait),
+                _ => authn_failed,
+            };
+        }
     };
 
-    let pw_match = match hashing::verify_password(&body.password, &player.password) {
-        Ok(b) => b,
+    if record(LoginOutcome::Succeeded).await.is_err() {
+        return authn_failed;
+    }
+
+    upgrade_password_hash(&pool, &player, &body.password).await;
+
+    let mfa_enabled = match get_totp_credential(&pool, player.id).await {
+        Ok(credential) => credential.is_some_and(|c| c.secret.is_some()),
         Err(_) => return authn_failed,
     };
 
-    let token_reqs = match pw_match {
-        true => AuthnTokenReqs::new(player.id, player.username, player.email),
-        false => return authn_failed,
-    };
+    if mfa_enabled {
+        return match start_mfa_challenge(&pool, player.id).await {
+            Ok(mfa_token) => {
+                (StatusCode::OK, Json(MfaRequiredResponse::new(mfa_token))).into_response()
+            }
+            Err(_) => authn_failed,
+        };
+    }
 
-    match encode_authn_token(token_reqs) {
-        Ok(token) => (StatusCode::OK, Json(TokenResponse::new(token))).into_response(),
+    match finish_login(&pool, player, client).await {
+        Ok(response) => response,
         Err(_) => authn_failed,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        http::{header::RETRY_AFTER, Method, StatusCode},
+        Router,
+    };
+    use serde_json::json;
+    use sqlx::PgPool;
+
+    use crate::test_utils::{create_test_player, request, send, test_app};
+
+    async fn login(app: &Router, password: &str) -> (StatusCode, Option<String>) {
+        login_as(app, "b1gd3vd0g", password).await
+    }
+
+    async fn login_as(
+        app: &Router,
+        username: &str,
+        password: &str,
+    ) -> (StatusCode, Option<String>) {
+        let body = json!({ "username": username, "password": password });
+        let (status, headers, _) =
+            send(app, request(Method::POST, "/authn", None, Some(body))).await;
+        let retry_after = headers
+            .get(RETRY_AFTER)
+            .map(|value| value.to_str().unwrap().to_string());
+        (status, retry_after)
+    }
+
+    /// Move every recorded login attempt into the past, rather than waiting for a lock to end.
+    async fn rewind(pool: &PgPool, seconds: f64) {
+        sqlx::query(
+            "UPDATE login_attempts SET attempted_at = attempted_at - make_interval(secs => $1)",
+        )
+        .bind(seconds)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    #[sqlx::test]
+    async fn test_login_lockout(pool: PgPool) {
+        let app = test_app(pool.clone());
+        create_test_player(&pool, "b1gd3vd0g", "CorrectHorse!42").await;
+        let locked = |seconds: &str| (StatusCode::TOO_MANY_REQUESTS, Some(seconds.to_string()));
+
+        for _ in 0..4 {
+            assert_eq!(login(&app, "wrong").await.0, StatusCode::UNAUTHORIZED);
+        }
+        // The fifth failure locks the username, and says for how long.
+        assert_eq!(login(&app, "wrong").await, locked("60"));
+        // While it is locked, even the right password is rejected.
+        assert_eq!(
+            login(&app, "CorrectHorse!42").await.0,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+
+        // Once the lock is over, the next failure locks it again, for twice as long.
+        rewind(&pool, 61.0).await;
+        assert_eq!(login(&app, "wrong").await, locked("120"));
+
+        // A successful login after the lock resets the count.
+        rewind(&pool, 121.0).await;
+        assert_eq!(login(&app, "CorrectHorse!42").await.0, StatusCode::OK);
+        assert_eq!(login(&app, "wrong").await.0, StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn test_login_lockout_username_variants(pool: PgPool) {
+        let app = test_app(pool.clone());
+        create_test_player(&pool, "b1gd3vd0g", "CorrectHorse!42").await;
+
+        // Wildcards are not usernames, so they cannot reach the account.
+        for username in ["b1gd3vd0_", "b1gd3vd0%", "%1gd3vd0g"] {
+            assert_eq!(
+                login_as(&app, username, "CorrectHorse!42").await.0,
+                StatusCode::UNAUTHORIZED
+            );
+        }
+
+        // Failures count against the account, however the username's case is typed.
+        for username in ["b1gd3vd0g", "B1GD3VD0G", "B1gd3vd0g", "b1gD3vd0g"] {
+            assert_eq!(
+                login_as(&app, username, "wrong").await.0,
+                StatusCode::UNAUTHORIZED
+            );
+        }
+        assert_eq!(
+            login_as(&app, "B1GD3VD0g", "wrong").await.0,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        for username in ["b1gd3vd0g", "B1GD3VD0G", "b1gd3vd0_", "%1gd3vd0g"] {
+            assert_ne!(
+                login_as(&app, username, "CorrectHorse!42").await.0,
+                StatusCode::OK
+            );
+        }
+    }
+}
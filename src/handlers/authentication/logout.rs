@@ -0,0 +1,58 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use sqlx::PgPool;
+
+use crate::{
+    db::queries::{
+        revoked_tokens::{revoke_all_player_tokens, revoke_token},
+        sessions::revoke_session,
+    },
+    handlers::{helper::authenticate, responses::MessageResponse},
+};
+
+fn revocation_failure() -> Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(MessageResponse::new("Tokens could not be revoked.")),
+    )
+        .into_response()
+}
+
+/// Revoke the bearer token, and end the session it belongs to so that its refresh token can no
+/// longer be used either.
+pub async fn handle_logout(State(pool): State<PgPool>, headers: HeaderMap) -> Response {
+    let payload = match authenticate(&pool, headers).await {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::UNAUTHORIZED, Json(e)).into_response(),
+    };
+
+    if revoke_session(&pool, payload.sub, payload.sid)
+        .await
+        .is_err()
+    {
+        return revocation_failure();
+    }
+
+    match revoke_token(&pool, &payload).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(_) => revocation_failure(),
+    }
+}
+
+/// Revoke every session, access token and refresh token which has been issued to the bearer,
+/// signing them out everywhere.
+pub async fn handle_logout_all(State(pool): State<PgPool>, headers: HeaderMap) -> Response {
+    let payload = match authenticate(&pool, headers).await {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::UNAUTHORIZED, Json(e)).into_response(),
+    };
+
+    match revoke_all_player_tokens(&pool, payload.sub).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(_) => revocation_failure(),
+    }
+}
@@ -0,0 +1,160 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::{
+    db::{
+        models::TokenPurpose,
+        queries::{
+            get_player_by_email, get_player_by_id, mark_email_verified,
+            one_time_tokens::{consume_one_time_token, create_one_time_token},
+            totp_credentials::get_totp_credential,
+        },
+    },
+    handlers::{
+        authentication::{mfa::start_mfa_challenge, restoration::finish_login},
+        helper::ClientInfo,
+        responses::{MessageResponse, MfaRequiredResponse},
+    },
+    mailer::{frontend_link, send_in_background, Email},
+    tokens::{generate_token, hash_token},
+};
+
+/// How long a magic link remains valid after it is sent.
+const MAGIC_LINK_LIFETIME_MINUTES: i64 = 15;
+
+/// The expected request body shape for the magic link request.
+#[derive(Deserialize)]
+pub struct RequestReqBody {
+    email: String,
+}
+
+/// The expected request body shape for the magic link redemption request.
+#[derive(Deserialize)]
+pub struct RedeemReqBody {
+    token: String,
+}
+
+/// Email a link which signs the player with the given email address in, without their password.
+///
+/// The response is always `202 Accepted`, whether or not such a player exists, so that this
+/// endpoint cannot be used to discover which email addresses are registered.
+pub async fn handle_magic_link_request(
+    State(pool): State<PgPool>,
+    Json(body): Json<RequestReqBody>,
+) -> Response {
+    let accepted = (
+        StatusCode::ACCEPTED,
+        Json(MessageResponse::new(
+            "If an account with that email exists, a sign in link has been sent.",
+        )),
+    )
+        .into_response();
+
+    let player = match get_player_by_email(&pool, body.email).await {
+        Ok(p) => p,
+        Err(_) => return accepted,
+    };
+
+    let token = generate_token();
+    let expires_at = Utc::now() + Duration::minutes(MAGIC_LINK_LIFETIME_MINUTES);
+    let created = create_one_time_token(
+        &pool,
+        player.id,
+        TokenPurpose::MagicLink,
+        hash_token(&token),
+        expires_at,
+        Some(player.email.clone()),
+    )
+    .await;
+
+    if created.is_ok() {
+        send_in_background(Email::new(
+            &player.email,
+            "Sign in to Bit Casino",
+            format!(
+                "Hi {},\n\nFollow this link within {} minutes to sign in:\n\n{}\n\nIf you did not ask to sign in, you can ignore this email.",
+                player.username,
+                MAGIC_LINK_LIFETIME_MINUTES,
+                frontend_link("/authn/magic-link", &token)
+            ),
+        ));
+    }
+
+    accepted
+}
+
+/// Exchange the token from a magic link for a token pair. Following the link proves the player owns
+/// their email address, so it is marked as verified.
+///
+/// A magic link only replaces the password, so a player with two-factor authentication enabled gets
+/// an `MfaRequiredResponse` instead, exactly as if they had entered their password.
+pub async fn handle_magic_link_redemption(
+    State(pool): State<PgPool>,
+    client: ClientInfo,
+    Json(body): Json<RedeemReqBody>,
+) -> Response {
+    let invalid_link = (
+        StatusCode::BAD_REQUEST,
+        Json(MessageResponse::new("Sign in link is invalid or expired.")),
+    )
+        .into_response();
+
+    let token =
+        match consume_one_time_token(&pool, TokenPurpose::MagicLink, hash_token(&body.token)).await
+        {
+            Ok(t) => t,
+            Err(_) => return invalid_link,
+        };
+
+    let player = match get_player_by_id(&pool, token.player_id).await {
+        Ok(p) => p,
+        Err(_) => return invalid_link,
+    };
+
+    // A link sent before the player changed their email address no longer works.
+    if token.email.as_deref() != Some(player.email.as_str()) {
+        return invalid_link;
+    }
+
+    let authn_failed = (
+        StatusCode::UNAUTHORIZED,
+        Json(MessageResponse::new("Authentication failed.")),
+    )
+        .into_response();
+
+    if mark_email_verified(&pool, player.id).await.is_err() {
+        return authn_failed;
+    }
+
+    let mfa_enabled = match get_totp_credential(&pool, player.id).await {
+        Ok(credential) => credential.is_some_and(|c| c.secret.is_some()),
+        Err(_) => return authn_failed,
+    };
+
+    if mfa_enabled {
+        return match start_mfa_challenge(&pool, player.id).await {
+            Ok(mfa_token) => {
+                (StatusCode::OK, Json(MfaRequiredResponse::new(mfa_token))).into_response()
+            }
+            Err(_) => authn_failed,
+        };
+    }
+
+    // Fetch the player again, so that the access token shows their email address as verified.
+    let player = match get_player_by_id(&pool, player.id).await {
+        Ok(p) => p,
+        Err(_) => return authn_failed,
+    };
+
+    match finish_login(&pool, player, client).await {
+        Ok(response) => response,
+        Err(_) => authn_failed,
+    }
+}
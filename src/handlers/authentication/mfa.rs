@@ -0,0 +1,99 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    db::{
+        models::TokenPurpose,
+        queries::{
+            get_player_by_id,
+            one_time_tokens::{consume_one_time_token, create_one_time_token},
+        },
+    },
+    handlers::{
+        authentication::restoration::finish_login,
+        helper::{check_second_factor, ClientInfo},
+        responses::MessageResponse,
+    },
+    tokens::{generate_token, hash_token},
+};
+
+/// How long a player has to enter a code after entering their password.
+const MFA_TOKEN_LIFETIME_MINUTES: i64 = 5;
+
+/// The expected request body shape for the second step of the login request.
+#[derive(Deserialize)]
+pub struct ReqBody {
+    mfa_token: String,
+    code: String,
+}
+
+/// Start the second step of signing in, for a player who has entered their password correctly but
+/// has two-factor authentication enabled.
+///
+/// # Returns
+///
+/// * `Ok(String)` containing the token which must be presented along with a code.
+/// * `Err(sqlx::Error)` if the token could not be stored.
+pub async fn start_mfa_challenge(pool: &PgPool, player_id: Uuid) -> Result<String, sqlx::Error> {
+    let token = generate_token();
+    create_one_time_token(
+        pool,
+        player_id,
+        TokenPurpose::MfaPending,
+        hash_token(&token),
+        Utc::now() + Duration::minutes(MFA_TOKEN_LIFETIME_MINUTES),
+        None,
+    )
+    .await?;
+    Ok(token)
+}
+
+/// Finish signing in by exchanging the token from the first step, along with a code from the
+/// player's authenticator app (or one of their recovery codes), for a token pair.
+///
+/// Each token from the first step can only be presented once, so a wrong code means the player must
+/// enter their password again. This keeps codes from being guessed.
+pub async fn handle_mfa_login(
+    State(pool): State<PgPool>,
+    client: ClientInfo,
+    Json(body): Json<ReqBody>,
+) -> Response {
+    let authn_failed = (
+        StatusCode::UNAUTHORIZED,
+        Json(MessageResponse::new("Authentication failed.")),
+    )
+        .into_response();
+
+    let token =
+        match consume_one_time_token(&pool, TokenPurpose::MfaPending, hash_token(&body.mfa_token))
+            .await
+        {
+            Ok(t) => t,
+            Err(_) => return authn_failed,
+        };
+
+    if !matches!(
+        check_second_factor(&pool, token.player_id, &body.code).await,
+        Ok(true)
+    ) {
+        return authn_failed;
+    }
+
+    let player = match get_player_by_id(&pool, token.player_id).await {
+        Ok(p) => p,
+        Err(_) => return authn_failed,
+    };
+
+    match finish_login(&pool, player, client).await {
+        Ok(response) => response,
+        Err(_) => authn_failed,
+    }
+}
@@ -0,0 +1,165 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    db::{
+        models::Ceremony,
+        queries::{
+            get_player_by_id,
+            passkeys::{get_passkey_by_credential_id, record_passkey_use},
+            webauthn_challenges::consume_webauthn_challenge,
+        },
+    },
+    handlers::{
+        authentication::restoration::finish_login,
+        helper::ClientInfo,
+        passkeys::{ceremony_timeout, issue_challenge},
+        responses::MessageResponse,
+    },
+    tokens::hash_token,
+    webauthn::{decode_base64url, encode_base64url, verify_assertion, ClientData, RelyingParty},
+};
+
+/// The options to pass to `navigator.credentials.get()`, once the challenge has been decoded.
+///
+/// # Notes
+/// * No credentials are listed, so the browser offers every passkey the player has for this site.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestOptions {
+    challenge: String,
+    rp_id: String,
+    timeout: i64,
+    user_verification: &'static str,
+}
+
+/// The authenticator's response to `navigator.credentials.get()`, base64url encoded.
+#[derive(Deserialize)]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    #[serde(rename = "authenticatorData")]
+    authenticator_data: String,
+    signature: String,
+    #[serde(rename = "userHandle")]
+    user_handle: Option<String>,
+}
+
+/// The expected request body shape for the passkey sign in request.
+#[derive(Deserialize)]
+pub struct ReqBody {
+    id: String,
+    response: AssertionResponse,
+}
+
+/// Start signing in with a passkey, by issuing the options for `navigator.credentials.get()`.
+pub async fn handle_passkey_login_options(State(pool): State<PgPool>) -> Response {
+    match issue_challenge(&pool, None, Ceremony::Authentication).await {
+        Ok(challenge) => (
+            StatusCode::OK,
+            Json(RequestOptions {
+                challenge,
+                rp_id: RelyingParty::from_env().id,
+                timeout: ceremony_timeout(),
+                user_verification: "required",
+            }),
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(MessageResponse::new("Sign in could not be started.")),
+        )
+            .into_response(),
+    }
+}
+
+/// Finish signing in with a passkey, by exchanging the authenticator's response to the challenge
+/// from the options request for a token pair.
+///
+/// Passkeys require user verification, so they count as two factors on their own, and no code is
+/// asked for even when the player has two-factor authentication enabled.
+pub async fn handle_passkey_login(
+    State(pool): State<PgPool>,
+    client: ClientInfo,
+    Json(body): Json<ReqBody>,
+) -> Response {
+    let authn_failed = (
+        StatusCode::UNAUTHORIZED,
+        Json(MessageResponse::new("Authentication failed.")),
+    )
+        .into_response();
+
+    let response = body.response;
+    let (credential_id, client_data_json, authenticator_data, signature) = match (
+        decode_base64url(&body.id),
+        decode_base64url(&response.client_data_json),
+        decode_base64url(&response.authenticator_data),
+        decode_base64url(&response.signature),
+    ) {
+        (Ok(i), Ok(c), Ok(a), Ok(s)) => (i, c, a, s),
+        _ => return authn_failed,
+    };
+    let client_data = match ClientData::parse(&client_data_json) {
+        Ok(c) => c,
+        Err(_) => return authn_failed,
+    };
+
+    if consume_webauthn_challenge(
+        &pool,
+        Ceremony::Authentication,
+        hash_token(&client_data.challenge),
+    )
+    .await
+    .is_err()
+    {
+        return authn_failed;
+    }
+
+    let passkey = match get_passkey_by_credential_id(&pool, &encode_base64url(&credential_id)).await
+    {
+        Ok(Some(p)) => p,
+        _ => return authn_failed,
+    };
+
+    // The user handle is the id of the player the authenticator created the passkey for.
+    if let Some(user_handle) = response.user_handle {
+        if decode_base64url(&user_handle).ok().as_deref() != Some(passkey.player_id.as_bytes()) {
+            return authn_failed;
+        }
+    }
+
+    let sign_count = match verify_assertion(
+        &RelyingParty::from_env(),
+        &passkey.public_key,
+        passkey.sign_count as u32,
+        &client_data_json,
+        &authenticator_data,
+        &signature,
+    ) {
+        Ok(count) => count,
+        Err(_) => return authn_failed,
+    };
+
+    if !matches!(
+        record_passkey_use(&pool, passkey.id, sign_count as i64).await,
+        Ok(true)
+    ) {
+        return authn_failed;
+    }
+
+    let player = match get_player_by_id(&pool, passkey.player_id).await {
+        Ok(p) => p,
+        Err(_) => return authn_failed,
+    };
+
+    match finish_login(&pool, player, client).await {
+        Ok(response) => response,
+        Err(_) => authn_failed,
+    }
+}
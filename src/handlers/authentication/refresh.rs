@@ -0,0 +1,151 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::{
+    db::queries::{
+        get_player_by_id,
+        refresh_tokens::{
+            get_refresh_token_by_hash, revoke_refresh_token_family, rotate_refresh_token,
+        },
+        sessions::touch_session,
+    },
+    handlers::{
+        helper::refresh_token_expiry,
+        responses::{MessageResponse, TokenResponse},
+    },
+    jwt::{encode_authn_token, AuthnTokenReqs},
+    tokens::{generate_token, hash_token},
+};
+
+/// The expected request body shape for the refresh request.
+#[derive(Deserialize)]
+pub struct ReqBody {
+    refresh_token: String,
+}
+
+/// Exchange a refresh token for a new access token and a new refresh token.
+///
+/// Each refresh token can only be exchanged once. If a token which has already been exchanged is
+/// presented again, it must have been stolen, so every token in its family is revoked.
+pub async fn handle_token_refresh(
+    State(pool): State<PgPool>,
+    Json(body): Json<ReqBody>,
+) -> Response {
+    let refresh_failed = (
+        StatusCode::UNAUTHORIZED,
+        Json(MessageResponse::new("Refresh token is invalid or expired.")),
+    )
+        .into_response();
+
+    let old = match get_refresh_token_by_hash(&pool, hash_token(&body.refresh_token)).await {
+        Ok(t) => t,
+        Err(_) => return refresh_failed,
+    };
+
+    if old.used_at.is_some() || old.revoked_at.is_some() {
+        let _ = revoke_refresh_token_family(&pool, old.family_id).await;
+        return refresh_failed;
+    }
+
+    if old.expires_at <= Utc::now() {
+        return refresh_failed;
+    }
+
+    let player = match get_player_by_id(&pool, old.player_id).await {
+        Ok(p) => p,
+        Err(_) => return refresh_failed,
+    };
+
+    let refresh_token = generate_token();
+    if rotate_refresh_token(
+        &pool,
+        &old,
+        hash_token(&refresh_token),
+        refresh_token_expiry(),
+    )
+    .await
+    .is_err()
+    {
+        // Another request exchanged this token first; treat it the same as a replay.
+        let _ = revoke_refresh_token_family(&pool, old.family_id).await;
+        return refresh_failed;
+    }
+
+    let _ = touch_session(&pool, old.family_id).await;
+
+    match encode_authn_token(AuthnTokenReqs::new(
+        player.id,
+        player.username,
+        player.email,
+        player.email_verified_at.is_some(),
+        old.family_id,
+    )) {
+        Ok(token) => (
+            StatusCode::OK,
+            Json(TokenResponse::new(token, refresh_token)),
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(MessageResponse::token_creation_failure()),
+        )
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        http::{Method, StatusCode},
+        Router,
+    };
+    use serde_json::{json, Value};
+    use sqlx::PgPool;
+
+    use crate::test_utils::{create_test_player, request, send, test_app};
+
+    async fn refresh(app: &Router, refresh_token: &Value) -> (StatusCode, Value) {
+        let body = json!({ "refresh_token": refresh_token });
+        let (status, _, body) = send(
+            app,
+            request(Method::POST, "/authn/refresh", None, Some(body)),
+        )
+        .await;
+        (status, body)
+    }
+
+    #[sqlx::test]
+    async fn test_refresh_token_reuse_revokes_family(pool: PgPool) {
+        let app = test_app(pool.clone());
+        create_test_player(&pool, "b1gd3vd0g", "CorrectHorse!42").await;
+        let body = json!({ "username": "b1gd3vd0g", "password": "CorrectHorse!42" });
+        let (status, _, login) =
+            send(&app, request(Method::POST, "/authn", None, Some(body))).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, rotated) = refresh(&app, &login["refresh_token"]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_ne!(rotated["refresh_token"], login["refresh_token"]);
+
+        // Presenting the rotated token again means it was stolen, so the whole family is revoked,
+        // including the token which replaced it.
+        let (status, _) = refresh(&app, &login["refresh_token"]).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = refresh(&app, &rotated["refresh_token"]).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // The session is no longer listed, since it has no refresh token left.
+        let token = rotated["token"].as_str().unwrap();
+        let (status, _, sessions) =
+            send(&app, request(Method::GET, "/sessions", Some(token), None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(sessions, json!([]));
+    }
+}
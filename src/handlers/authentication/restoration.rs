@@ -0,0 +1,192 @@
+//! This module holds the handler which allows a player to restore their account while it is pending
+//! deletion, and the step every way of signing in finishes with, which offers it.
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::{
+    db::{
+        models::{Player, TokenPurpose},
+        queries::{
+            get_player_by_id,
+            one_time_tokens::{consume_one_time_token, create_one_time_token},
+            restore_player,
+        },
+    },
+    handlers::{
+        helper::{issue_token_pair, ClientInfo},
+        responses::{MessageResponse, RestorationRequiredResponse},
+    },
+    tokens::{generate_token, hash_token},
+};
+
+/// How long a player has to choose to restore their account after signing in.
+const RESTORE_TOKEN_LIFETIME_MINUTES: i64 = 15;
+
+/// The expected request body shape for the account restoration request.
+#[derive(Deserialize)]
+pub struct ReqBody {
+    restore_token: String,
+}
+
+/// Finish signing a player in, once they have proven who they are (including their second factor).
+///
+/// A player whose account is pending deletion is not given a token pair. Instead, they are offered
+/// a token which restores their account.
+///
+/// # Returns
+///
+/// * `Ok(Response)` containing either a `TokenResponse` or a `RestorationRequiredResponse`.
+/// * `Err(MessageResponse)` if the tokens could not be issued.
+pub async fn finish_login(
+    pool: &PgPool,
+    player: Player,
+    client: ClientInfo,
+) -> Result<Response, MessageResponse> {
+    let deletion_scheduled_at = match player.deletion_scheduled_at {
+        Some(at) => at,
+        None => {
+            let tokens = issue_token_pair(pool, player, client).await?;
+            return Ok((StatusCode::OK, Json(tokens)).into_response());
+        }
+    };
+
+    let restore_token = generate_token();
+    create_one_time_token(
+        pool,
+        player.id,
+        TokenPurpose::AccountRestoration,
+        hash_token(&restore_token),
+        Utc::now() + Duration::minutes(RESTORE_TOKEN_LIFETIME_MINUTES),
+        None,
+    )
+    .await
+    .map_err(|_| MessageResponse::new("Account restoration could not be offered."))?;
+
+    Ok((
+        StatusCode::OK,
+        Json(RestorationRequiredResponse::new(
+            deletion_scheduled_at,
+            restore_token,
+        )),
+    )
+        .into_response())
+}
+
+fn account_deleted() -> Response {
+    (
+        StatusCode::GONE,
+        Json(MessageResponse::new("Account has already been deleted.")),
+    )
+        .into_response()
+}
+
+/// Restore an account which is pending deletion, using the token offered when the player signed in,
+/// and sign them in.
+pub async fn handle_account_restoration(
+    State(pool): State<PgPool>,
+    client: ClientInfo,
+    Json(body): Json<ReqBody>,
+) -> Response {
+    let token = match consume_one_time_token(
+        &pool,
+        TokenPurpose::AccountRestoration,
+        hash_token(&body.restore_token),
+    )
+    .await
+    {
+        Ok(t) => t,
+        Err(_) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(MessageResponse::new("Restore token is invalid or expired.")),
+            )
+                .into_response()
+        }
+    };
+
+    let restoration_failed = || {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(MessageResponse::new("Account could not be restored.")),
+        )
+            .into_response()
+    };
+
+    match restore_player(&pool, token.player_id).await {
+        Ok(true) => (),
+        // The grace period is over. The account is kept until the purge finishes, and for good if it
+        // fails, but it can no longer be restored by signing in.
+        Ok(false) => {
+            return match get_player_by_id(&pool, token.player_id).await {
+                Ok(_) => (
+                    StatusCode::CONFLICT,
+                    Json(MessageResponse::new(
+                        "Account deletion is already under way. Please contact support to keep your account.",
+                    )),
+                )
+                    .into_response(),
+                Err(sqlx::Error::RowNotFound) => account_deleted(),
+                Err(_) => restoration_failed(),
+            }
+        }
+        Err(_) => return restoration_failed(),
+    }
+
+    let player = match get_player_by_id(&pool, token.player_id).await {
+        Ok(p) => p,
+        Err(_) => return account_deleted(),
+    };
+
+    match issue_token_pair(&pool, player, client).await {
+        Ok(tokens) => (StatusCode::OK, Json(tokens)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(e)).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::test_utils::{create_test_player, request, send, test_app};
+
+    #[sqlx::test]
+    async fn test_restoration_after_grace_period(pool: PgPool) {
+        let app = test_app(pool.clone());
+        let player = create_test_player(&pool, "b1gd3vd0g", "CorrectHorse!42").await;
+        sqlx::query("UPDATE players SET deletion_scheduled_at = now() - interval '1 day'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let token = generate_token();
+        create_one_time_token(
+            &pool,
+            player.id,
+            TokenPurpose::AccountRestoration,
+            hash_token(&token),
+            Utc::now() + Duration::minutes(1),
+            None,
+        )
+        .await
+        .unwrap();
+
+        // The account still exists while it is being purged, so it is not reported as deleted.
+        let body = json!({ "restore_token": token });
+        let (status, _, _) = send(
+            &app,
+            request(Method::POST, "/authn/restore", None, Some(body)),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+}
@@ -11,8 +11,7 @@ use uuid::Uuid;
 
 use crate::{
     db::queries::get_player_by_token,
-    handlers::{helper::extract_authn_token, responses::MessageResponse},
-    jwt::decode_authn_token,
+    handlers::{helper::authenticate, responses::MessageResponse},
 };
 
 #[derive(Serialize)]
@@ -20,29 +19,21 @@ pub struct SafePlayerInfo {
     id: Uuid,
     username: String,
     email: String,
+    email_verified: bool,
     created_at: DateTime<Utc>,
+    /// Whether the player's bit wallet is still being created.
+    wallet_pending: bool,
 }
 
 pub async fn handle_fetch_player_by_token(
     State(pool): State<PgPool>,
     headers: HeaderMap,
 ) -> Response {
-    let authn_token = match extract_authn_token(headers) {
-        Ok(token) => token,
+    let payload = match authenticate(&pool, headers).await {
+        Ok(p) => p,
         Err(e) => return (StatusCode::UNAUTHORIZED, Json(e)).into_response(),
     };
 
-    let payload = match decode_authn_token(authn_token) {
-        Ok(p) => p.claims,
-        Err(_) => {
-            return (
-                StatusCode::UNAUTHORIZED,
-                Json(MessageResponse::token_auth_failure()),
-            )
-                .into_response()
-        }
-    };
-
     let player = get_player_by_token(&pool, payload).await;
 
     match player {
@@ -52,7 +43,9 @@ pub async fn handle_fetch_player_by_token(
                 id: p.id,
                 username: p.username,
                 email: p.email,
+                email_verified: p.email_verified_at.is_some(),
                 created_at: p.created_at,
+                wallet_pending: p.wallet_created_at.is_none(),
             }),
         )
             .into_response(),
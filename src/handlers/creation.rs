@@ -1,17 +1,33 @@
+//! This module holds the handler which registers new players.
+//!
+//! # Environment
+//!
+//! * `REVEAL_REGISTRATION_CONFLICTS` - (Optional) Set to `true` to say whether it was the username
+//!   or the email address which is already taken, when registration fails with a conflict. This
+//!   makes registration reveal which usernames and email addresses exist, so it defaults to `false`.
+
+use std::env;
+
 use axum::{
     extract::State,
     http::StatusCode,
     response::{IntoResponse, Response},
     Json,
 };
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 
 use crate::{
-    db::queries::create_new_player,
-    handlers::responses::{MessageResponse, TokenResponse},
+    db::queries::{create_new_player, mark_wallet_created, username_history::is_username_taken},
+    handlers::{
+        email::verification::send_verification_email,
+        helper::{issue_token_pair, ClientInfo},
+        responses::MessageResponse,
+        username::username_reservation_period,
+    },
     hashing,
-    jwt::{encode_authn_token, AuthnTokenReqs},
+    password_strength::{self, check_password_strength},
     requests::currency::create_bit_wallet,
     validators::{validate_email, validate_password, validate_username},
 };
@@ -29,13 +45,46 @@ pub struct InvalidRequestBodyResponse {
     username: bool,
     password: bool,
     email: bool,
+    /// Why the password was rejected, if it has the right characters but is too weak.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    password_reasons: Vec<String>,
+}
+
+/// Build the response for a registration which conflicts with an existing player.
+///
+/// # Arguments
+///
+/// * `field` - Which field is already taken (`Username` or `Email`), if it is known. It is only
+///   shown if `REVEAL_REGISTRATION_CONFLICTS` is set.
+fn conflict(field: Option<&str>) -> Response {
+    let reveal = env::var("REVEAL_REGISTRATION_CONFLICTS").is_ok_and(|value| value == "true");
+    let message = match field {
+        Some(field) if reveal => format!("{} already exists.", field),
+        _ => String::from("Username or email already exists."),
+    };
+    (StatusCode::CONFLICT, Json(MessageResponse::new(&message))).into_response()
+}
+
+/// Find which field of a new player collided with an existing player, from the unique constraint
+/// the insert violated.
+fn conflicting_field(error: &sqlx::Error) -> Option<&'static str> {
+    let constraint = error.as_database_error()?.constraint()?;
+    if constraint.contains("username") {
+        Some("Username")
+    } else if constraint.contains("email") {
+        Some("Email")
+    } else {
+        None
+    }
 }
 
 pub async fn handle_player_creation(
     State(pool): State<PgPool>,
+    client: ClientInfo,
     Json(body): Json<ReqBody>,
 ) -> Response {
-    let hash = match hashing::hash_password(&body.password) {
+    let pepper_version = hashing::current_pepper_version();
+    let hash = match hashing::hash_password(&body.password, pepper_version) {
         Ok(hash) => hash,
         Err(_) => {
             return (
@@ -46,49 +95,60 @@ pub async fn handle_player_creation(
         }
     };
 
-    let val = InvalidRequestBodyResponse {
+    let mut val = InvalidRequestBodyResponse {
         username: validate_username(&body.username),
         email: validate_email(&body.email),
         password: validate_password(&body.password),
+        password_reasons: Vec::new(),
     };
+    if val.password {
+        if let Err(weaknesses) =
+            check_password_strength(&body.password, &body.username, &body.email)
+        {
+            val.password = false;
+            val.password_reasons = password_strength::reasons(&weaknesses);
+        }
+    }
     if !(val.username && val.password && val.email) {
         return (StatusCode::BAD_REQUEST, Json(val)).into_response();
     }
 
-    let player = create_new_player(&pool, body.username, body.email, hash).await;
+    let reserved_since = Utc::now() - username_reservation_period();
+    match is_username_taken(&pool, &body.username, None, reserved_since).await {
+        Ok(false) => (),
+        Ok(true) => return conflict(Some("Username")),
+        Err(_) => return conflict(None),
+    }
 
-    let token_reqs = match player {
-        Ok(p) => AuthnTokenReqs::new(p.id, p.username, p.email),
-        Err(_) => {
-            return (
-                StatusCode::CONFLICT,
-                Json(MessageResponse::new("Username or email already exists.")),
-            )
-                .into_response()
-        }
+    let player = create_new_player(&pool, body.username, body.email, hash, pepper_version).await;
+
+    let player = match player {
+        Ok(p) => p,
+        Err(e) => return conflict(conflicting_field(&e)),
     };
 
-    let token = match encode_authn_token(token_reqs) {
-        Ok(tok) => tok,
-        Err(_) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(MessageResponse::token_creation_failure()),
-            )
-                .into_response()
-        }
+    if send_verification_email(&pool, &player).await.is_err() {
+        eprintln!(
+            "Failed to send a verification email to player {}",
+            player.id
+        );
+    }
+
+    let player_id = player.id;
+    let tokens = match issue_token_pair(&pool, player, client).await {
+        Ok(tokens) => tokens,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(e)).into_response(),
     };
 
-    match create_bit_wallet(token.clone()).await {
-        Ok(()) => return (StatusCode::CREATED, Json(TokenResponse::new(token))).into_response(),
-        Err(_) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(MessageResponse::new(
-                    "Player created, but wallet could not be initialized. This should not happen!",
-                )),
-            )
-                .into_response()
-        }
+    // The player is registered either way. If the wallet cannot be created now, it is left pending,
+    // and `wallet_reconciliation` keeps trying.
+    let wallet_created = match create_bit_wallet(tokens.token.clone()).await {
+        Ok(()) => mark_wallet_created(&pool, player_id).await.is_ok(),
+        Err(_) => false,
+    };
+    if !wallet_created {
+        eprintln!("The wallet of player {} is pending", player_id);
     }
+
+    (StatusCode::CREATED, Json(tokens)).into_response()
 }
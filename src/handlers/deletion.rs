@@ -4,39 +4,88 @@ use axum::{
     response::{IntoResponse, Response},
     Json,
 };
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 
 use crate::{
-    db::queries::delete_player_by_token,
-    handlers::{helper::extract_authn_token, responses::MessageResponse},
-    jwt::decode_authn_token,
+    account_deletion::grace_period,
+    db::queries::{get_player_by_token, schedule_player_deletion},
+    handlers::{helper::authenticate, responses::MessageResponse},
+    hashing,
 };
 
-pub async fn handle_player_deletion(State(pool): State<PgPool>, headers: HeaderMap) -> Response {
-    let authn_token = match extract_authn_token(headers) {
-        Ok(token) => token,
+/// The expected request body shape for the deletion request.
+#[derive(Deserialize)]
+pub struct ReqBody {
+    password: String,
+}
+
+#[derive(Serialize)]
+pub struct DeletionScheduledResponse {
+    message: String,
+    deletion_scheduled_at: DateTime<Utc>,
+}
+
+/// Delete the bearer's account. The password must be provided as well as the token.
+///
+/// The account is not deleted straight away. It is scheduled for deletion after a grace period (see
+/// `account_deletion`), and every session is signed out. Signing in before then offers to restore
+/// it.
+pub async fn handle_player_deletion(
+    State(pool): State<PgPool>,
+    headers: HeaderMap,
+    Json(body): Json<ReqBody>,
+) -> Response {
+    let payload = match authenticate(&pool, headers).await {
+        Ok(p) => p,
         Err(e) => return (StatusCode::UNAUTHORIZED, Json(e)).into_response(),
     };
 
-    let payload = match decode_authn_token(authn_token) {
-        Ok(p) => p.claims,
+    let player = match get_player_by_token(&pool, payload).await {
+        Ok(p) => p,
         Err(_) => {
             return (
-                StatusCode::UNAUTHORIZED,
-                Json(MessageResponse::token_auth_failure()),
+                StatusCode::NOT_FOUND,
+                Json(MessageResponse::new("Player could not be found.")),
             )
                 .into_response()
         }
     };
 
-    let deletion = delete_player_by_token(&pool, payload).await;
+    match hashing::verify_password(
+        &body.password,
+        &player.password,
+        player.password_pepper_version,
+    ) {
+        Ok(true) => (),
+        _ => {
+            return (
+                StatusCode::FORBIDDEN,
+                Json(MessageResponse::new("Password is incorrect.")),
+            )
+                .into_response()
+        }
+    }
 
-    match deletion {
-        Ok(_) => StatusCode::NO_CONTENT.into_response(),
-        Err(_) => (
-            StatusCode::NOT_FOUND,
-            Json(MessageResponse::new("Player could not be found.")),
+    let deletion_scheduled_at = Utc::now() + grace_period();
+    if schedule_player_deletion(&pool, player.id, deletion_scheduled_at)
+        .await
+        .is_err()
+    {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(MessageResponse::new("Account could not be deleted.")),
         )
-            .into_response(),
+            .into_response();
     }
+
+    (
+        StatusCode::ACCEPTED,
+        Json(DeletionScheduledResponse {
+            message: String::from("Account will be deleted. Sign in before then to restore it."),
+            deletion_scheduled_at,
+        }),
+    )
+        .into_response()
 }
@@ -12,13 +12,11 @@ use crate::handlers::responses::MessageResponse;
 
 pub async fn handle_serve_documentation() -> Response {
     match fs::read_to_string("public/docs.html") {
-        Ok(html) => return (StatusCode::OK, Html(html)).into_response(),
-        Err(_) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(MessageResponse::new("Failed to find docs.html")),
-            )
-                .into_response();
-        }
+        Ok(html) => (StatusCode::OK, Html(html)).into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(MessageResponse::new("Failed to find docs.html")),
+        )
+            .into_response(),
     }
 }
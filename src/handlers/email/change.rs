@@ -0,0 +1,313 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::{
+    db::{
+        models::TokenPurpose,
+        queries::{
+            get_player_by_email, get_player_by_id, get_player_by_token,
+            one_time_tokens::{create_one_time_token, get_valid_one_time_token},
+            update_player_email,
+        },
+    },
+    handlers::{
+        helper::{authenticate, issue_token_pair, ClientInfo},
+        responses::MessageResponse,
+    },
+    hashing,
+    mailer::{frontend_link, send_in_background, Email},
+    tokens::{generate_token, hash_token},
+    validators::validate_email,
+};
+
+/// How long an email change confirmation link remains valid after it is sent.
+const CHANGE_TOKEN_LIFETIME_HOURS: i64 = 24;
+
+/// The expected request body shape for the email change request.
+#[derive(Deserialize)]
+pub struct ChangeReqBody {
+    password: String,
+    new_email: String,
+}
+
+/// The expected request body shape for the email change confirmation request.
+#[derive(Deserialize)]
+pub struct ConfirmReqBody {
+    token: String,
+}
+
+/// Start changing the bearer's email address. A confirmation link is sent to the new address, and a
+/// notice is sent to the old one. The address is only changed once the link is followed.
+pub async fn handle_email_change_request(
+    State(pool): State<PgPool>,
+    headers: HeaderMap,
+    Json(body): Json<ChangeReqBody>,
+) -> Response {
+    let payload = match authenticate(&pool, headers).await {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::UNAUTHORIZED, Json(e)).into_response(),
+    };
+
+    let player = match get_player_by_token(&pool, payload).await {
+        Ok(p) => p,
+        Err(_) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(MessageResponse::new("Player could not be found.")),
+            )
+                .into_response()
+        }
+    };
+
+    match hashing::verify_password(
+        &body.password,
+        &player.password,
+        player.password_pepper_version,
+    ) {
+        Ok(true) => (),
+        _ => {
+            return (
+                StatusCode::FORBIDDEN,
+                Json(MessageResponse::new("Password is incorrect.")),
+            )
+                .into_response()
+        }
+    }
+
+    if !validate_email(&body.new_email) || body.new_email.eq_ignore_ascii_case(&player.email) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(MessageResponse::new("New email is invalid.")),
+        )
+            .into_response();
+    }
+
+    if get_player_by_email(&pool, body.new_email.clone())
+        .await
+        .is_ok()
+    {
+        return (
+            StatusCode::CONFLICT,
+            Json(MessageResponse::new("Email already exists.")),
+        )
+            .into_response();
+    }
+
+    let token = generate_token();
+    let expires_at = Utc::now() + Duration::hours(CHANGE_TOKEN_LIFETIME_HOURS);
+    if create_one_time_token(
+        &pool,
+        player.id,
+        TokenPurpose::EmailChange,
+        hash_token(&token),
+        expires_at,
+        Some(body.new_email.clone()),
+    )
+    .await
+    .is_err()
+    {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(MessageResponse::new("Email change could not be started.")),
+        )
+            .into_response();
+    }
+
+    send_in_background(Email::new(
+        &body.new_email,
+        "Confirm your new Bit Casino email address",
+        format!(
+            "Hi {},\n\nFollow this link within {} hours, while signed in, to make this your Bit Casino email address:\n\n{}\n\nIf you did not ask to change your email address, you can ignore this email.",
+            player.username,
+            CHANGE_TOKEN_LIFETIME_HOURS,
+            frontend_link("/email/confirm", &token)
+        ),
+    ));
+    send_in_background(Email::new(
+        &player.email,
+        "Your Bit Casino email address is being changed",
+        format!(
+            "Hi {},\n\nSomeone asked to change the email address on your Bit Casino account to {}. The change will only happen once the link sent to that address is followed.\n\nIf this was not you, change your password right away.",
+            player.username, body.new_email
+        ),
+    ));
+
+    (
+        StatusCode::ACCEPTED,
+        Json(MessageResponse::new(
+            "A confirmation link has been sent to the new email address.",
+        )),
+    )
+        .into_response()
+}
+
+/// Confirm an email change using the token from a confirmation link. The link alone is not enough:
+/// the bearer must be signed in as the player who asked for the change.
+///
+/// Every existing token carries the old email address, so all of them are revoked along with the
+/// change (see `update_player_email`), and a new token pair carrying the new address is issued in
+/// place of the bearer's session.
+pub async fn handle_email_change_confirmation(
+    State(pool): State<PgPool>,
+    headers: HeaderMap,
+    client: ClientInfo,
+    Json(body): Json<ConfirmReqBody>,
+) -> Response {
+    let payload = match authenticate(&pool, headers).await {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::UNAUTHORIZED, Json(e)).into_response(),
+    };
+
+    let invalid = || {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(MessageResponse::new(
+                "Confirmation token is invalid or expired.",
+            )),
+        )
+            .into_response()
+    };
+
+    let token =
+        match get_valid_one_time_token(&pool, TokenPurpose::EmailChange, hash_token(&body.token))
+            .await
+        {
+            Ok(t) if t.player_id == payload.sub => t,
+            _ => return invalid(),
+        };
+
+    let email = match token.email.clone() {
+        Some(email) => email,
+        None => return invalid(),
+    };
+    match update_player_email(&pool, &token, email).await {
+        Ok(()) => (),
+        Err(sqlx::Error::RowNotFound) => return invalid(),
+        Err(e)
+            if e.as_database_error()
+                .is_some_and(|e| e.is_unique_violation()) =>
+        {
+            return (
+                StatusCode::CONFLICT,
+                Json(MessageResponse::new("Email already exists.")),
+            )
+                .into_response()
+        }
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(MessageResponse::new("Email could not be changed.")),
+            )
+                .into_response()
+        }
+    }
+
+    let player = match get_player_by_id(&pool, token.player_id).await {
+        Ok(p) => p,
+        Err(_) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(MessageResponse::new("Player could not be found.")),
+            )
+                .into_response()
+        }
+    };
+
+    match issue_token_pair(&pool, player, client).await {
+        Ok(tokens) => (StatusCode::OK, Json(tokens)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(e)).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use chrono::{Duration, Utc};
+    use serde_json::json;
+    use sqlx::PgPool;
+
+    use crate::{
+        db::{models::TokenPurpose, queries::one_time_tokens::create_one_time_token},
+        jwt::decode_authn_token,
+        test_utils::{create_test_player, request, send, test_app},
+        tokens::{generate_token, hash_token},
+    };
+
+    #[sqlx::test]
+    async fn test_email_change_confirmation_reissues_tokens(pool: PgPool) {
+        let app = test_app(pool.clone());
+        let player = create_test_player(&pool, "b1gd3vd0g", "CorrectHorse!42").await;
+        create_test_player(&pool, "someone", "CorrectHorse!42").await;
+        let login = |username: &str| {
+            let body = json!({ "username": username, "password": "CorrectHorse!42" });
+            request(Method::POST, "/authn", None, Some(body))
+        };
+        let (_, _, tokens) = send(&app, login("b1gd3vd0g")).await;
+        let old = tokens["token"].as_str().unwrap();
+        let (_, _, tokens) = send(&app, login("someone")).await;
+        let someone = tokens["token"].as_str().unwrap();
+
+        let token = generate_token();
+        create_one_time_token(
+            &pool,
+            player.id,
+            TokenPurpose::EmailChange,
+            hash_token(&token),
+            Utc::now() + Duration::hours(1),
+            Some(String::from("new@bigdevdog.com")),
+        )
+        .await
+        .unwrap();
+        // A password reset link sent to the old address.
+        create_one_time_token(
+            &pool,
+            player.id,
+            TokenPurpose::PasswordReset,
+            hash_token(&generate_token()),
+            Utc::now() + Duration::hours(1),
+            None,
+        )
+        .await
+        .unwrap();
+        let confirm = |bearer| {
+            let body = json!({ "token": token });
+            request(Method::POST, "/email/confirm", bearer, Some(body))
+        };
+
+        // The link alone, or the link with another player's token, changes nothing.
+        let (status, _, _) = send(&app, confirm(None)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _, _) = send(&app, confirm(Some(someone))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, _, tokens) = send(&app, confirm(Some(old))).await;
+        assert_eq!(status, StatusCode::OK);
+        let new = tokens["token"].as_str().unwrap();
+        let claims = decode_authn_token(String::from(new)).unwrap().claims;
+        assert_eq!(claims.email, "new@bigdevdog.com");
+        assert!(tokens["refresh_token"].is_string());
+
+        // The old token carries the old address, so it no longer works, but the new one does.
+        let (status, _, _) = send(&app, request(Method::GET, "/authn", Some(old), None)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _, body) = send(&app, request(Method::GET, "/authn", Some(new), None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["email"], "new@bigdevdog.com");
+
+        // Links sent to the old address no longer work either.
+        let unused: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM one_time_tokens WHERE purpose = 'password_reset' AND used_at IS NULL",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(unused, 0);
+    }
+}
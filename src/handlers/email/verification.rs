@@ -0,0 +1,174 @@
+use axum::{
+    extract::State,
+    http::{header::RETRY_AFTER, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::{
+    db::{
+        models::{Player, TokenPurpose},
+        queries::{
+            get_player_by_id, mark_email_verified,
+            one_time_tokens::{
+                consume_one_time_token, create_one_time_token, get_latest_one_time_token,
+            },
+        },
+    },
+    handlers::{helper::authenticate, responses::MessageResponse},
+    mailer::{frontend_link, send_in_background, Email},
+    tokens::{generate_token, hash_token},
+};
+
+/// How long an email verification link remains valid after it is sent.
+const VERIFICATION_TOKEN_LIFETIME_HOURS: i64 = 24;
+
+/// How long a player must wait before another verification email can be sent to them.
+const RESEND_COOLDOWN_SECONDS: i64 = 60;
+
+/// The expected request body shape for the email verification request.
+#[derive(Deserialize)]
+pub struct VerifyReqBody {
+    token: String,
+}
+
+/// Email a verification link to a player. Any link which was previously sent to them stops working.
+///
+/// # Arguments
+///
+/// * `pool` - The postgres connection pool.
+/// * `player` - The player whose email address should be verified.
+pub async fn send_verification_email(pool: &PgPool, player: &Player) -> Result<(), sqlx::Error> {
+    let token = generate_token();
+    let expires_at = Utc::now() + Duration::hours(VERIFICATION_TOKEN_LIFETIME_HOURS);
+    create_one_time_token(
+        pool,
+        player.id,
+        TokenPurpose::EmailVerification,
+        hash_token(&token),
+        expires_at,
+        None,
+    )
+    .await?;
+
+    send_in_background(Email::new(
+        &player.email,
+        "Verify your Bit Casino email address",
+        format!(
+            "Hi {},\n\nFollow this link within {} hours to verify your email address:\n\n{}\n\nIf you did not create a Bit Casino account, you can ignore this email.",
+            player.username,
+            VERIFICATION_TOKEN_LIFETIME_HOURS,
+            frontend_link("/email/verify", &token)
+        ),
+    ));
+    Ok(())
+}
+
+/// Verify a player's email address using the token from a verification link.
+///
+/// Tokens which were issued before the address was verified still carry `email_verified: false`,
+/// so clients should refresh their token afterwards.
+pub async fn handle_email_verification(
+    State(pool): State<PgPool>,
+    Json(body): Json<VerifyReqBody>,
+) -> Response {
+    let token = match consume_one_time_token(
+        &pool,
+        TokenPurpose::EmailVerification,
+        hash_token(&body.token),
+    )
+    .await
+    {
+        Ok(t) => t,
+        Err(_) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(MessageResponse::new(
+                    "Verification token is invalid or expired.",
+                )),
+            )
+                .into_response()
+        }
+    };
+
+    match mark_email_verified(&pool, token.player_id).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(MessageResponse::new("Email could not be verified.")),
+        )
+            .into_response(),
+    }
+}
+
+/// Send the bearer a new email verification link. Only one link can be sent every
+/// `RESEND_COOLDOWN_SECONDS`.
+pub async fn handle_resend_verification(
+    State(pool): State<PgPool>,
+    headers: HeaderMap,
+) -> Response {
+    let payload = match authenticate(&pool, headers).await {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::UNAUTHORIZED, Json(e)).into_response(),
+    };
+
+    let player = match get_player_by_id(&pool, payload.sub).await {
+        Ok(p) => p,
+        Err(_) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(MessageResponse::new("Player could not be found.")),
+            )
+                .into_response()
+        }
+    };
+
+    if player.email_verified_at.is_some() {
+        return (
+            StatusCode::CONFLICT,
+            Json(MessageResponse::new("Email is already verified.")),
+        )
+            .into_response();
+    }
+
+    let send_failure = || {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(MessageResponse::new(
+                "Verification email could not be sent.",
+            )),
+        )
+            .into_response()
+    };
+
+    let latest =
+        match get_latest_one_time_token(&pool, player.id, TokenPurpose::EmailVerification).await {
+            Ok(t) => t,
+            Err(_) => return send_failure(),
+        };
+    if let Some(latest) = latest {
+        let wait = RESEND_COOLDOWN_SECONDS - (Utc::now() - latest.created_at).num_seconds();
+        if wait > 0 {
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(RETRY_AFTER, wait.to_string())],
+                Json(MessageResponse::new(
+                    "A verification email was sent recently. Please wait before asking for another.",
+                )),
+            )
+                .into_response();
+        }
+    }
+
+    match send_verification_email(&pool, &player).await {
+        Ok(()) => (
+            StatusCode::ACCEPTED,
+            Json(MessageResponse::new("A verification email has been sent.")),
+        )
+            .into_response(),
+        Err(_) => send_failure(),
+    }
+}
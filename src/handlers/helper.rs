@@ -1,6 +1,65 @@
-use axum::http::HeaderMap;
+use std::{convert::Infallible, env, net::SocketAddr};
 
-use crate::handlers::responses::MessageResponse;
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header::USER_AGENT, request::Parts, HeaderMap, StatusCode},
+};
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    db::{
+        models::Player,
+        queries::{
+            recovery_codes::{get_unused_recovery_codes, use_recovery_code},
+            refresh_tokens::create_refresh_token,
+            revoked_tokens::is_token_revoked,
+            sessions::{create_session, touch_session},
+            totp_credentials::{get_totp_credential, record_totp_step},
+        },
+    },
+    handlers::responses::{MessageResponse, TokenResponse},
+    hashing,
+    jwt::{decode_authn_token, encode_authn_token, AuthnTokenPayload, AuthnTokenReqs},
+    rate_limit::{client_ip, trusted_proxy_hops},
+    recovery_codes,
+    tokens::{generate_token, hash_token},
+    totp,
+};
+
+/// How long a refresh token remains valid after it is issued.
+const REFRESH_TOKEN_LIFETIME_DAYS: i64 = 30;
+
+/// Details about the client making a request, which are recorded when a session is started.
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(address)| address.ip());
+        let ip_address =
+            client_ip(&parts.headers, peer, trusted_proxy_hops()).map(|ip| ip.to_string());
+        Ok(ClientInfo {
+            user_agent,
+            ip_address,
+        })
+    }
+}
 
 pub fn extract_authn_token(headers: HeaderMap) -> Result<String, MessageResponse> {
     let error = Err(MessageResponse::token_auth_failure());
@@ -17,6 +76,191 @@ pub fn extract_authn_token(headers: HeaderMap) -> Result<String, MessageResponse
 
     match token {
         Some(token) => Ok(String::from(token)),
-        None => return error,
+        None => error,
+    }
+}
+
+/// Authenticate a request using the bearer token in its `Authorization` header. The token must be
+/// correctly signed, unexpired, and must not have been revoked.
+///
+/// # Arguments
+///
+/// * `pool` - The postgres connection pool.
+/// * `headers` - The headers of the request.
+///
+/// # Returns
+///
+/// * `Ok(AuthnTokenPayload)` containing the decoded token's payload.
+/// * `Err(MessageResponse)` if the token is missing, invalid or revoked.
+pub async fn authenticate(
+    pool: &PgPool,
+    headers: HeaderMap,
+) -> Result<AuthnTokenPayload, MessageResponse> {
+    let token = extract_authn_token(headers)?;
+
+    let payload = match decode_authn_token(token) {
+        Ok(p) => p.claims,
+        Err(_) => return Err(MessageResponse::token_auth_failure()),
+    };
+
+    match is_token_revoked(pool, &payload).await {
+        Ok(false) => {
+            let _ = touch_session(pool, payload.sid).await;
+            Ok(payload)
+        }
+        _ => Err(MessageResponse::token_auth_failure()),
+    }
+}
+
+/// Check that a request to one of the operator routes (rather than the player routes) carries
+/// `ADMIN_API_KEY` as its bearer token. The operator routes are disabled if it is not set.
+///
+/// # Returns
+///
+/// * `Ok(())` if it does.
+/// * `Err((StatusCode, MessageResponse))` to respond with, if not.
+pub fn authorize_admin(headers: HeaderMap) -> Result<(), (StatusCode, MessageResponse)> {
+    let key = match env::var("ADMIN_API_KEY") {
+        Ok(key) if !key.is_empty() => key,
+        _ => {
+            return Err((
+                StatusCode::FORBIDDEN,
+                MessageResponse::new("Operator routes are disabled."),
+            ))
+        }
+    };
+    match extract_authn_token(headers) {
+        // Compare the hashes, so the time taken does not reveal how much of the key matched.
+        Ok(token) if hash_token(&token) == hash_token(&key) => Ok(()),
+        _ => Err((
+            StatusCode::UNAUTHORIZED,
+            MessageResponse::token_auth_failure(),
+        )),
     }
 }
+
+/// Check a code from a player's authenticator app against their enabled TOTP secret. Each code is
+/// only accepted once.
+///
+/// # Arguments
+///
+/// * `pool` - The postgres connection pool.
+/// * `player_id` - The id of the player.
+/// * `code` - The code provided by the player.
+///
+/// # Returns
+///
+/// * `Ok(true)` if the code is valid and has not been used before.
+/// * `Ok(false)` if it is not, or the player does not have two-factor authentication enabled.
+/// * `Err(sqlx::Error)` if the database could not be queried.
+pub async fn check_totp_code(
+    pool: &PgPool,
+    player_id: Uuid,
+    code: &str,
+) -> Result<bool, sqlx::Error> {
+    let secret = match get_totp_credential(pool, player_id).await? {
+        Some(credential) => match credential.secret {
+            Some(secret) => secret,
+            None => return Ok(false),
+        },
+        None => return Ok(false),
+    };
+    match totp::verify_code(&secret, code, Utc::now().timestamp() as u64) {
+        Some(step) => record_totp_step(pool, player_id, step as i64).await,
+        None => Ok(false),
+    }
+}
+
+/// Check one of a player's recovery codes. Each code is only accepted once.
+///
+/// # Returns
+///
+/// * `Ok(true)` if the code is one of the player's unused recovery codes.
+/// * `Ok(false)` if it is not.
+/// * `Err(sqlx::Error)` if the database could not be queried.
+pub async fn check_recovery_code(
+    pool: &PgPool,
+    player_id: Uuid,
+    code: &str,
+) -> Result<bool, sqlx::Error> {
+    // Every check costs an Argon2 hash per unused code, so do not bother with obvious mismatches.
+    let code = match recovery_codes::normalize(code) {
+        Some(code) => code,
+        None => return Ok(false),
+    };
+    for recovery_code in get_unused_recovery_codes(pool, player_id).await? {
+        if let Ok(true) = hashing::verify_password(&code, &recovery_code.code_hash, None) {
+            return use_recovery_code(pool, recovery_code.id).await;
+        }
+    }
+    Ok(false)
+}
+
+/// Check a code which proves a player's second factor: either a code from their authenticator app,
+/// or one of their recovery codes. Each code is only accepted once.
+///
+/// # Returns
+///
+/// * `Ok(true)` if the code is valid and has not been used before.
+/// * `Ok(false)` if it is not, or the player does not have two-factor authentication enabled.
+/// * `Err(sqlx::Error)` if the database could not be queried.
+pub async fn check_second_factor(
+    pool: &PgPool,
+    player_id: Uuid,
+    code: &str,
+) -> Result<bool, sqlx::Error> {
+    if check_totp_code(pool, player_id, code).await? {
+        return Ok(true);
+    }
+    check_recovery_code(pool, player_id, code).await
+}
+
+/// Calculate the expiration time of a refresh token issued right now.
+pub fn refresh_token_expiry() -> DateTime<Utc> {
+    Utc::now() + Duration::days(REFRESH_TOKEN_LIFETIME_DAYS)
+}
+
+/// Start a new session for a player who has just proven their identity, issuing an access token
+/// along with a refresh token. The refresh token starts a new token family.
+///
+/// # Arguments
+///
+/// * `pool` - The postgres connection pool.
+/// * `player` - The player to issue the tokens for.
+/// * `client` - Details about the client the session is being started on.
+///
+/// # Returns
+///
+/// * `Ok(TokenResponse)` containing both tokens.
+/// * `Err(MessageResponse)` if the session or either token could not be created.
+pub async fn issue_token_pair(
+    pool: &PgPool,
+    player: Player,
+    client: ClientInfo,
+) -> Result<TokenResponse, MessageResponse> {
+    let session = create_session(pool, player.id, client.user_agent, client.ip_address)
+        .await
+        .map_err(|_| MessageResponse::token_creation_failure())?;
+
+    let token = encode_authn_token(AuthnTokenReqs::new(
+        player.id,
+        player.username,
+        player.email,
+        player.email_verified_at.is_some(),
+        session.id,
+    ))
+    .map_err(|_| MessageResponse::token_creation_failure())?;
+
+    let refresh_token = generate_token();
+    create_refresh_token(
+        pool,
+        player.id,
+        session.id,
+        hash_token(&refresh_token),
+        refresh_token_expiry(),
+    )
+    .await
+    .map_err(|_| MessageResponse::token_creation_failure())?;
+
+    Ok(TokenResponse::new(token, refresh_token))
+}
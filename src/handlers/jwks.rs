@@ -0,0 +1,19 @@
+//! This handler serves the public keys used to verify authentication tokens, so that the other
+//! casino services can verify tokens without holding any signing material.
+
+use axum::{
+    http::{header::CACHE_CONTROL, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use crate::jwt::public_jwks;
+
+pub async fn handle_serve_jwks() -> Response {
+    (
+        StatusCode::OK,
+        [(CACHE_CONTROL, "public, max-age=300")],
+        Json(public_jwks()),
+    )
+        .into_response()
+}
@@ -0,0 +1,361 @@
+//! This module holds the handlers which allow a player to register passkeys, see which passkeys they
+//! have, and remove them.
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    db::{
+        models::{Ceremony, Passkey},
+        queries::{
+            passkeys::{create_passkey, delete_passkey, get_passkeys},
+            webauthn_challenges::{consume_webauthn_challenge, create_webauthn_challenge},
+        },
+    },
+    handlers::{helper::authenticate, responses::MessageResponse},
+    tokens::{generate_token, hash_token},
+    webauthn::{
+        decode_base64url, encode_base64url, verify_registration, ClientData, RelyingParty,
+        SUPPORTED_ALGORITHMS,
+    },
+};
+
+/// How long a player has to complete a ceremony after its challenge is issued.
+const CHALLENGE_LIFETIME_MINUTES: i64 = 5;
+/// The longest name a passkey can be given.
+const MAX_NAME_LENGTH: usize = 50;
+
+/// Issue a challenge for a WebAuthn ceremony.
+///
+/// # Arguments
+///
+/// * `player_id` - The id of the player registering a passkey, or `None` for a sign in.
+///
+/// # Returns
+///
+/// * `Ok(String)` containing the base64url encoded challenge.
+/// * `Err(sqlx::Error)` if the challenge could not be stored.
+pub async fn issue_challenge(
+    pool: &PgPool,
+    player_id: Option<Uuid>,
+    ceremony: Ceremony,
+) -> Result<String, sqlx::Error> {
+    let challenge = generate_token();
+    create_webauthn_challenge(
+        pool,
+        player_id,
+        ceremony,
+        hash_token(&challenge),
+        Utc::now() + Duration::minutes(CHALLENGE_LIFETIME_MINUTES),
+    )
+    .await?;
+    Ok(challenge)
+}
+
+/// How long the browser should wait for the player, in milliseconds.
+pub fn ceremony_timeout() -> i64 {
+    Duration::minutes(CHALLENGE_LIFETIME_MINUTES).num_milliseconds()
+}
+
+#[derive(Serialize)]
+pub struct RelyingPartyEntity {
+    id: String,
+    name: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserEntity {
+    id: String,
+    name: String,
+    display_name: String,
+}
+
+#[derive(Serialize)]
+pub struct CredentialParameters {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    alg: i64,
+}
+
+#[derive(Serialize)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    id: String,
+}
+
+impl CredentialDescriptor {
+    pub fn new(credential_id: String) -> Self {
+        CredentialDescriptor {
+            kind: "public-key",
+            id: credential_id,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    resident_key: &'static str,
+    user_verification: &'static str,
+}
+
+/// The options to pass to `navigator.credentials.create()`, once the base64url values have been
+/// decoded.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreationOptions {
+    challenge: String,
+    rp: RelyingPartyEntity,
+    user: UserEntity,
+    pub_key_cred_params: Vec<CredentialParameters>,
+    timeout: i64,
+    exclude_credentials: Vec<CredentialDescriptor>,
+    authenticator_selection: AuthenticatorSelection,
+    attestation: &'static str,
+}
+
+/// The authenticator's response to `navigator.credentials.create()`, base64url encoded.
+#[derive(Deserialize)]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    #[serde(rename = "attestationObject")]
+    attestation_object: String,
+}
+
+/// The expected request body shape for the registration request.
+#[derive(Deserialize)]
+pub struct RegistrationReqBody {
+    id: String,
+    response: AttestationResponse,
+    name: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct PasskeyInfo {
+    id: Uuid,
+    name: String,
+    created_at: DateTime<Utc>,
+    last_used_at: Option<DateTime<Utc>>,
+}
+
+impl From<Passkey> for PasskeyInfo {
+    fn from(passkey: Passkey) -> Self {
+        PasskeyInfo {
+            id: passkey.id,
+            name: passkey.name,
+            created_at: passkey.created_at,
+            last_used_at: passkey.last_used_at,
+        }
+    }
+}
+
+fn passkey_failure() -> Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(MessageResponse::new("Passkey could not be registered.")),
+    )
+        .into_response()
+}
+
+/// Start registering a passkey for the bearer, by issuing the options for
+/// `navigator.credentials.create()`.
+pub async fn handle_passkey_registration_options(
+    State(pool): State<PgPool>,
+    headers: HeaderMap,
+) -> Response {
+    let payload = match authenticate(&pool, headers).await {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::UNAUTHORIZED, Json(e)).into_response(),
+    };
+
+    let existing = match get_passkeys(&pool, payload.sub).await {
+        Ok(passkeys) => passkeys,
+        Err(_) => return passkey_failure(),
+    };
+
+    let challenge = match issue_challenge(&pool, Some(payload.sub), Ceremony::Registration).await {
+        Ok(c) => c,
+        Err(_) => return passkey_failure(),
+    };
+
+    let rp = RelyingParty::from_env();
+    (
+        StatusCode::OK,
+        Json(CreationOptions {
+            challenge,
+            rp: RelyingPartyEntity {
+                id: rp.id,
+                name: rp.name,
+            },
+            user: UserEntity {
+                id: encode_base64url(payload.sub.as_bytes()),
+                name: payload.username.clone(),
+                display_name: payload.username,
+            },
+            pub_key_cred_params: SUPPORTED_ALGORITHMS
+                .iter()
+                .map(|&alg| CredentialParameters {
+                    kind: "public-key",
+                    alg,
+                })
+                .collect(),
+            timeout: ceremony_timeout(),
+            exclude_credentials: existing
+                .into_iter()
+                .map(|p| CredentialDescriptor::new(p.credential_id))
+                .collect(),
+            authenticator_selection: AuthenticatorSelection {
+                resident_key: "required",
+                user_verification: "required",
+            },
+            attestation: "none",
+        }),
+    )
+        .into_response()
+}
+
+/// Finish registering a passkey for the bearer, by verifying the authenticator's response to the
+/// challenge from the options request.
+pub async fn handle_passkey_registration(
+    State(pool): State<PgPool>,
+    headers: HeaderMap,
+    Json(body): Json<RegistrationReqBody>,
+) -> Response {
+    let payload = match authenticate(&pool, headers).await {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::UNAUTHORIZED, Json(e)).into_response(),
+    };
+
+    let name = body
+        .name
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .unwrap_or("Passkey")
+        .to_string();
+    if name.chars().count() > MAX_NAME_LENGTH {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(MessageResponse::new("Passkey name is invalid.")),
+        )
+            .into_response();
+    }
+
+    let registration_failed = (
+        StatusCode::BAD_REQUEST,
+        Json(MessageResponse::new("Passkey could not be verified.")),
+    )
+        .into_response();
+
+    let (client_data_json, attestation_object, credential_id) = match (
+        decode_base64url(&body.response.client_data_json),
+        decode_base64url(&body.response.attestation_object),
+        decode_base64url(&body.id),
+    ) {
+        (Ok(c), Ok(a), Ok(i)) => (c, a, i),
+        _ => return registration_failed,
+    };
+    let client_data = match ClientData::parse(&client_data_json) {
+        Ok(c) => c,
+        Err(_) => return registration_failed,
+    };
+
+    match consume_webauthn_challenge(
+        &pool,
+        Ceremony::Registration,
+        hash_token(&client_data.challenge),
+    )
+    .await
+    {
+        Ok(challenge) if challenge.player_id == Some(payload.sub) => (),
+        _ => return registration_failed,
+    }
+
+    let credential = match verify_registration(
+        &RelyingParty::from_env(),
+        &client_data_json,
+        &attestation_object,
+    ) {
+        Ok(c) if c.credential_id == credential_id => c,
+        _ => return registration_failed,
+    };
+
+    match create_passkey(
+        &pool,
+        payload.sub,
+        encode_base64url(&credential.credential_id),
+        credential.public_key,
+        credential.sign_count as i64,
+        name,
+    )
+    .await
+    {
+        Ok(passkey) => (StatusCode::CREATED, Json(PasskeyInfo::from(passkey))).into_response(),
+        Err(_) => (
+            StatusCode::CONFLICT,
+            Json(MessageResponse::new("Passkey is already registered.")),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn handle_list_passkeys(State(pool): State<PgPool>, headers: HeaderMap) -> Response {
+    let payload = match authenticate(&pool, headers).await {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::UNAUTHORIZED, Json(e)).into_response(),
+    };
+
+    match get_passkeys(&pool, payload.sub).await {
+        Ok(passkeys) => (
+            StatusCode::OK,
+            Json(
+                passkeys
+                    .into_iter()
+                    .map(PasskeyInfo::from)
+                    .collect::<Vec<PasskeyInfo>>(),
+            ),
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(MessageResponse::new("Passkeys could not be fetched.")),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn handle_passkey_deletion(
+    State(pool): State<PgPool>,
+    headers: HeaderMap,
+    Path(passkey_id): Path<Uuid>,
+) -> Response {
+    let payload = match authenticate(&pool, headers).await {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::UNAUTHORIZED, Json(e)).into_response(),
+    };
+
+    match delete_passkey(&pool, payload.sub, passkey_id).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(MessageResponse::new("Passkey could not be found.")),
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(MessageResponse::new("Passkey could not be removed.")),
+        )
+            .into_response(),
+    }
+}
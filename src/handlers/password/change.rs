@@ -0,0 +1,129 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::{
+    db::queries::{get_player_by_token, sessions::revoke_other_sessions, update_player_password},
+    handlers::{
+        helper::authenticate,
+        responses::{MessageResponse, WeakPasswordResponse},
+    },
+    hashing,
+    password_strength::check_password_strength,
+    validators::validate_password,
+};
+
+/// The expected request body shape for the password change request.
+#[derive(Deserialize)]
+pub struct ReqBody {
+    current_password: String,
+    new_password: String,
+    /// Whether every session other than the one making the request should be signed out.
+    #[serde(default)]
+    sign_out_other_sessions: bool,
+}
+
+/// Change the bearer's password. The current password must be provided as well as the token, so a
+/// stolen token alone is not enough to take over the account.
+pub async fn handle_password_change(
+    State(pool): State<PgPool>,
+    headers: HeaderMap,
+    Json(body): Json<ReqBody>,
+) -> Response {
+    let payload = match authenticate(&pool, headers).await {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::UNAUTHORIZED, Json(e)).into_response(),
+    };
+    let session_id = payload.sid;
+
+    let player = match get_player_by_token(&pool, payload).await {
+        Ok(p) => p,
+        Err(_) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(MessageResponse::new("Player could not be found.")),
+            )
+                .into_response()
+        }
+    };
+
+    match hashing::verify_password(
+        &body.current_password,
+        &player.password,
+        player.password_pepper_version,
+    ) {
+        Ok(true) => (),
+        _ => {
+            return (
+                StatusCode::FORBIDDEN,
+                Json(MessageResponse::new("Current password is incorrect.")),
+            )
+                .into_response()
+        }
+    }
+
+    if !validate_password(&body.new_password) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(MessageResponse::new("New password is invalid.")),
+        )
+            .into_response();
+    }
+
+    if let Err(weaknesses) =
+        check_password_strength(&body.new_password, &player.username, &player.email)
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(WeakPasswordResponse::new(
+                "New password is too weak.",
+                &weaknesses,
+            )),
+        )
+            .into_response();
+    }
+
+    let pepper_version = hashing::current_pepper_version();
+    let hash = match hashing::hash_password(&body.new_password, pepper_version) {
+        Ok(hash) => hash,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(MessageResponse::new("Password could not be hashed.")),
+            )
+                .into_response()
+        }
+    };
+
+    if update_player_password(&pool, player.id, hash, pepper_version)
+        .await
+        .is_err()
+    {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(MessageResponse::new("Password could not be updated.")),
+        )
+            .into_response();
+    }
+
+    if body.sign_out_other_sessions
+        && revoke_other_sessions(&pool, player.id, session_id)
+            .await
+            .is_err()
+    {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(MessageResponse::new(
+                "Password updated, but other sessions could not be signed out.",
+            )),
+        )
+            .into_response();
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
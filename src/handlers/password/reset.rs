@@ -0,0 +1,170 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::{
+    db::{
+        models::TokenPurpose,
+        queries::{
+            get_player_by_email, get_player_by_id,
+            one_time_tokens::{
+                consume_one_time_token, create_one_time_token, get_valid_one_time_token,
+            },
+            reset_player_password,
+        },
+    },
+    handlers::responses::{MessageResponse, WeakPasswordResponse},
+    hashing,
+    mailer::{frontend_link, send_in_background, Email},
+    password_strength::check_password_strength,
+    tokens::{generate_token, hash_token},
+    validators::validate_password,
+};
+
+/// How long a password reset link remains valid after it is sent.
+const RESET_TOKEN_LIFETIME_MINUTES: i64 = 30;
+
+/// The expected request body shape for the forgotten password request.
+#[derive(Deserialize)]
+pub struct ForgotReqBody {
+    email: String,
+}
+
+/// The expected request body shape for the password reset request.
+#[derive(Deserialize)]
+pub struct ResetReqBody {
+    token: String,
+    password: String,
+}
+
+/// Email a password reset link to the player with the given email address.
+///
+/// The response is always `202 Accepted`, whether or not such a player exists, so that this
+/// endpoint cannot be used to discover which email addresses are registered.
+pub async fn handle_forgot_password(
+    State(pool): State<PgPool>,
+    Json(body): Json<ForgotReqBody>,
+) -> Response {
+    let accepted = (
+        StatusCode::ACCEPTED,
+        Json(MessageResponse::new(
+            "If an account with that email exists, a password reset link has been sent.",
+        )),
+    )
+        .into_response();
+
+    let player = match get_player_by_email(&pool, body.email).await {
+        Ok(p) => p,
+        Err(_) => return accepted,
+    };
+
+    let token = generate_token();
+    let expires_at = Utc::now() + Duration::minutes(RESET_TOKEN_LIFETIME_MINUTES);
+    let created = create_one_time_token(
+        &pool,
+        player.id,
+        TokenPurpose::PasswordReset,
+        hash_token(&token),
+        expires_at,
+        None,
+    )
+    .await;
+
+    if created.is_ok() {
+        send_in_background(Email::new(
+            &player.email,
+            "Reset your Bit Casino password",
+            format!(
+                "Hi {},\n\nFollow this link within {} minutes to choose a new password:\n\n{}\n\nIf you did not ask to reset your password, you can ignore this email.",
+                player.username,
+                RESET_TOKEN_LIFETIME_MINUTES,
+                frontend_link("/password/reset", &token)
+            ),
+        ));
+    }
+
+    accepted
+}
+
+/// Choose a new password using the token from a password reset link. On success, every existing
+/// session is signed out.
+pub async fn handle_password_reset(
+    State(pool): State<PgPool>,
+    Json(body): Json<ResetReqBody>,
+) -> Response {
+    if !validate_password(&body.password) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(MessageResponse::new("Password is invalid.")),
+        )
+            .into_response();
+    }
+
+    let invalid_token = || {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(MessageResponse::new("Reset token is invalid or expired.")),
+        )
+            .into_response()
+    };
+
+    // The password is checked against the player's username and email address before the token is
+    // used up, so that they can try again with a stronger password.
+    let player =
+        match get_valid_one_time_token(&pool, TokenPurpose::PasswordReset, hash_token(&body.token))
+            .await
+        {
+            Ok(token) => match get_player_by_id(&pool, token.player_id).await {
+                Ok(player) => player,
+                Err(_) => return invalid_token(),
+            },
+            Err(_) => return invalid_token(),
+        };
+    if let Err(weaknesses) =
+        check_password_strength(&body.password, &player.username, &player.email)
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(WeakPasswordResponse::new(
+                "Password is too weak.",
+                &weaknesses,
+            )),
+        )
+            .into_response();
+    }
+
+    let pepper_version = hashing::current_pepper_version();
+    let hash = match hashing::hash_password(&body.password, pepper_version) {
+        Ok(hash) => hash,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(MessageResponse::new("Password could not be hashed.")),
+            )
+                .into_response()
+        }
+    };
+
+    let token =
+        match consume_one_time_token(&pool, TokenPurpose::PasswordReset, hash_token(&body.token))
+            .await
+        {
+            Ok(t) => t,
+            Err(_) => return invalid_token(),
+        };
+
+    match reset_player_password(&pool, token.player_id, hash, pepper_version).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(MessageResponse::new("Password could not be updated.")),
+        )
+            .into_response(),
+    }
+}
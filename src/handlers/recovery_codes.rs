@@ -0,0 +1,154 @@
+//! This module holds the handlers which allow a player to manage the recovery codes they can use in
+//! place of a code from their authenticator app.
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    db::queries::{
+        get_player_by_token,
+        recovery_codes::{count_unused_recovery_codes, replace_recovery_codes},
+        totp_credentials::get_totp_credential,
+    },
+    handlers::{helper::authenticate, responses::MessageResponse},
+    hashing,
+    recovery_codes::{generate_recovery_codes, normalize},
+};
+
+/// The expected request body shape for the regeneration request.
+#[derive(Deserialize)]
+pub struct ReqBody {
+    password: String,
+}
+
+/// This is returned whenever a new set of recovery codes is generated. It is the only time the codes
+/// are ever shown.
+#[derive(Serialize)]
+pub struct RecoveryCodesResponse {
+    recovery_codes: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodesStatus {
+    remaining: i64,
+}
+
+fn recovery_codes_failure() -> MessageResponse {
+    MessageResponse::new("Recovery codes could not be generated.")
+}
+
+/// Give a player a new set of recovery codes, replacing any they already have.
+///
+/// # Returns
+///
+/// * `Ok(RecoveryCodesResponse)` containing the new codes.
+/// * `Err(MessageResponse)` if the codes could not be hashed or stored.
+pub async fn issue_recovery_codes(
+    pool: &PgPool,
+    player_id: Uuid,
+) -> Result<RecoveryCodesResponse, MessageResponse> {
+    let codes = generate_recovery_codes();
+    let hashes = codes
+        .iter()
+        .map(|code| hashing::hash_password(&normalize(code).unwrap_or_default(), None))
+        .collect::<Result<Vec<String>, _>>()
+        .map_err(|_| recovery_codes_failure())?;
+
+    replace_recovery_codes(pool, player_id, hashes)
+        .await
+        .map_err(|_| recovery_codes_failure())?;
+
+    Ok(RecoveryCodesResponse {
+        recovery_codes: codes,
+    })
+}
+
+/// Show how many unused recovery codes the bearer has left.
+pub async fn handle_recovery_codes_status(
+    State(pool): State<PgPool>,
+    headers: HeaderMap,
+) -> Response {
+    let payload = match authenticate(&pool, headers).await {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::UNAUTHORIZED, Json(e)).into_response(),
+    };
+
+    match count_unused_recovery_codes(&pool, payload.sub).await {
+        Ok(remaining) => (StatusCode::OK, Json(RecoveryCodesStatus { remaining })).into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(MessageResponse::new("Recovery codes could not be counted.")),
+        )
+            .into_response(),
+    }
+}
+
+/// Replace the bearer's recovery codes with a new set. The bearer must enter their password again.
+pub async fn handle_recovery_codes_regeneration(
+    State(pool): State<PgPool>,
+    headers: HeaderMap,
+    Json(body): Json<ReqBody>,
+) -> Response {
+    let payload = match authenticate(&pool, headers).await {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::UNAUTHORIZED, Json(e)).into_response(),
+    };
+
+    let player = match get_player_by_token(&pool, payload).await {
+        Ok(p) => p,
+        Err(_) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(MessageResponse::new("Player could not be found.")),
+            )
+                .into_response()
+        }
+    };
+
+    match hashing::verify_password(
+        &body.password,
+        &player.password,
+        player.password_pepper_version,
+    ) {
+        Ok(true) => (),
+        _ => {
+            return (
+                StatusCode::FORBIDDEN,
+                Json(MessageResponse::new("Password is incorrect.")),
+            )
+                .into_response()
+        }
+    }
+
+    match get_totp_credential(&pool, player.id).await {
+        Ok(Some(credential)) if credential.secret.is_some() => (),
+        Ok(_) => {
+            return (
+                StatusCode::CONFLICT,
+                Json(MessageResponse::new(
+                    "Two-factor authentication is not enabled.",
+                )),
+            )
+                .into_response()
+        }
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(recovery_codes_failure()),
+            )
+                .into_response()
+        }
+    }
+
+    match issue_recovery_codes(&pool, player.id).await {
+        Ok(codes) => (StatusCode::OK, Json(codes)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(e)).into_response(),
+    }
+}
@@ -1,14 +1,81 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
 use serde::Serialize;
 
-/// This is returned from both the registration request and the sign in request.
+use crate::password_strength::{self, Weakness};
+
+/// This is returned from the registration request, the sign in request and the refresh request.
 #[derive(Serialize)]
 pub struct TokenResponse {
     pub token: String,
+    pub refresh_token: String,
 }
 
 impl TokenResponse {
-    pub fn new(token: String) -> Self {
-        TokenResponse { token: token }
+    pub fn new(token: String, refresh_token: String) -> Self {
+        TokenResponse {
+            token,
+            refresh_token,
+        }
+    }
+}
+
+/// This is returned from the sign in request instead of a `TokenResponse` when the player has
+/// two-factor authentication enabled. The `mfa_token` must be exchanged, along with a code from
+/// their authenticator app, for a `TokenResponse`.
+#[derive(Serialize)]
+pub struct MfaRequiredResponse {
+    pub mfa_required: bool,
+    pub mfa_token: String,
+}
+
+impl MfaRequiredResponse {
+    pub fn new(mfa_token: String) -> Self {
+        MfaRequiredResponse {
+            mfa_required: true,
+            mfa_token,
+        }
+    }
+}
+
+/// This is returned from any sign in request instead of a `TokenResponse` when the player's account
+/// is pending deletion. The `restore_token` can be exchanged for a `TokenResponse`, which also
+/// restores the account.
+#[derive(Serialize)]
+pub struct RestorationRequiredResponse {
+    pub deletion_pending: bool,
+    pub deletion_scheduled_at: DateTime<Utc>,
+    pub restore_token: String,
+}
+
+impl RestorationRequiredResponse {
+    pub fn new(deletion_scheduled_at: DateTime<Utc>, restore_token: String) -> Self {
+        RestorationRequiredResponse {
+            deletion_pending: true,
+            deletion_scheduled_at,
+            restore_token,
+        }
+    }
+}
+
+/// This is returned when a new password is rejected for being too weak, with every reason it was
+/// rejected.
+#[derive(Serialize)]
+pub struct WeakPasswordResponse {
+    pub message: String,
+    pub reasons: Vec<String>,
+}
+
+impl WeakPasswordResponse {
+    pub fn new(message: &str, weaknesses: &[Weakness]) -> Self {
+        WeakPasswordResponse {
+            message: String::from(message),
+            reasons: password_strength::reasons(weaknesses),
+        }
     }
 }
 
@@ -32,3 +99,8 @@ impl MessageResponse {
         MessageResponse::new("Error creating authentication token.")
     }
 }
+
+/// Build a response with a status code and a `MessageResponse`.
+pub fn message(status: StatusCode, message: &str) -> Response {
+    (status, Json(MessageResponse::new(message))).into_response()
+}
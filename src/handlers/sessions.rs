@@ -0,0 +1,86 @@
+//! This module holds the handlers which allow a player to see where they are signed in, and to sign
+//! out of any one of those sessions remotely.
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    db::queries::sessions::{get_active_sessions, revoke_session},
+    handlers::{helper::authenticate, responses::MessageResponse},
+};
+
+#[derive(Serialize)]
+pub struct SessionInfo {
+    id: Uuid,
+    user_agent: Option<String>,
+    ip_address: Option<String>,
+    created_at: DateTime<Utc>,
+    last_seen_at: DateTime<Utc>,
+    /// Whether this is the session the request was made from.
+    current: bool,
+}
+
+pub async fn handle_list_sessions(State(pool): State<PgPool>, headers: HeaderMap) -> Response {
+    let payload = match authenticate(&pool, headers).await {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::UNAUTHORIZED, Json(e)).into_response(),
+    };
+
+    match get_active_sessions(&pool, payload.sub).await {
+        Ok(sessions) => (
+            StatusCode::OK,
+            Json(
+                sessions
+                    .into_iter()
+                    .map(|s| SessionInfo {
+                        current: s.id == payload.sid,
+                        id: s.id,
+                        user_agent: s.user_agent,
+                        ip_address: s.ip_address,
+                        created_at: s.created_at,
+                        last_seen_at: s.last_seen_at,
+                    })
+                    .collect::<Vec<SessionInfo>>(),
+            ),
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(MessageResponse::new("Sessions could not be fetched.")),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn handle_session_revocation(
+    State(pool): State<PgPool>,
+    headers: HeaderMap,
+    Path(session_id): Path<Uuid>,
+) -> Response {
+    let payload = match authenticate(&pool, headers).await {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::UNAUTHORIZED, Json(e)).into_response(),
+    };
+
+    match revoke_session(&pool, payload.sub, session_id).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(MessageResponse::new("Session could not be found.")),
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(MessageResponse::new("Session could not be revoked.")),
+        )
+            .into_response(),
+    }
+}
@@ -0,0 +1,176 @@
+//! This module holds the handlers which allow a player to enroll an authenticator app for two-factor
+//! authentication, and to disable it again.
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    db::queries::totp_credentials::{
+        confirm_totp_secret, delete_totp_credential, get_totp_credential, set_pending_totp_secret,
+    },
+    handlers::{
+        helper::{authenticate, check_second_factor},
+        recovery_codes::issue_recovery_codes,
+        responses::MessageResponse,
+    },
+    totp,
+};
+
+/// The expected request body shape for the enrollment request. A code is only required when
+/// replacing an authenticator app which is already enabled.
+#[derive(Deserialize)]
+pub struct EnrollReqBody {
+    code: Option<String>,
+}
+
+/// The expected request body shape for the confirmation and disable requests.
+#[derive(Deserialize)]
+pub struct CodeReqBody {
+    code: String,
+}
+
+#[derive(Serialize)]
+pub struct EnrollmentResponse {
+    secret: String,
+    otpauth_uri: String,
+}
+
+fn invalid_code() -> Response {
+    (
+        StatusCode::FORBIDDEN,
+        Json(MessageResponse::new("Code is invalid.")),
+    )
+        .into_response()
+}
+
+fn totp_failure() -> Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(MessageResponse::new(
+            "Two-factor authentication could not be updated.",
+        )),
+    )
+        .into_response()
+}
+
+/// Generate a new TOTP secret for the bearer. It is not used until it is confirmed with a code.
+///
+/// If the bearer already has two-factor authentication enabled, a fresh code from their current
+/// authenticator app (or a recovery code) is required, and the current secret keeps working until
+/// the new one is confirmed.
+pub async fn handle_totp_enrollment(
+    State(pool): State<PgPool>,
+    headers: HeaderMap,
+    body: Option<Json<EnrollReqBody>>,
+) -> Response {
+    let payload = match authenticate(&pool, headers).await {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::UNAUTHORIZED, Json(e)).into_response(),
+    };
+
+    let enabled = match get_totp_credential(&pool, payload.sub).await {
+        Ok(credential) => credential.is_some_and(|c| c.secret.is_some()),
+        Err(_) => return totp_failure(),
+    };
+
+    if enabled {
+        let code = body.and_then(|Json(body)| body.code).unwrap_or_default();
+        match check_second_factor(&pool, payload.sub, &code).await {
+            Ok(true) => (),
+            Ok(false) => return invalid_code(),
+            Err(_) => return totp_failure(),
+        }
+    }
+
+    let secret = totp::generate_secret();
+    if set_pending_totp_secret(&pool, payload.sub, secret.clone())
+        .await
+        .is_err()
+    {
+        return totp_failure();
+    }
+
+    (
+        StatusCode::OK,
+        Json(EnrollmentResponse {
+            otpauth_uri: totp::otpauth_uri(&secret, &payload.username),
+            secret,
+        }),
+    )
+        .into_response()
+}
+
+/// Enable two-factor authentication for the bearer, by proving that their authenticator app has the
+/// secret generated by the enrollment request. A new set of recovery codes is returned.
+pub async fn handle_totp_confirmation(
+    State(pool): State<PgPool>,
+    headers: HeaderMap,
+    Json(body): Json<CodeReqBody>,
+) -> Response {
+    let payload = match authenticate(&pool, headers).await {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::UNAUTHORIZED, Json(e)).into_response(),
+    };
+
+    let pending = match get_totp_credential(&pool, payload.sub).await {
+        Ok(credential) => credential.and_then(|c| c.pending_secret),
+        Err(_) => return totp_failure(),
+    };
+    let pending = match pending {
+        Some(secret) => secret,
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(MessageResponse::new("There is no enrollment to confirm.")),
+            )
+                .into_response()
+        }
+    };
+
+    let step = match totp::verify_code(&pending, &body.code, Utc::now().timestamp() as u64) {
+        Some(step) => step,
+        None => return invalid_code(),
+    };
+
+    match confirm_totp_secret(&pool, payload.sub, step as i64).await {
+        Ok(true) => (),
+        Ok(false) => return invalid_code(),
+        Err(_) => return totp_failure(),
+    }
+
+    match issue_recovery_codes(&pool, payload.sub).await {
+        Ok(codes) => (StatusCode::OK, Json(codes)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(e)).into_response(),
+    }
+}
+
+/// Disable two-factor authentication for the bearer. A fresh code from their authenticator app (or a
+/// recovery code) is required.
+pub async fn handle_totp_disable(
+    State(pool): State<PgPool>,
+    headers: HeaderMap,
+    Json(body): Json<CodeReqBody>,
+) -> Response {
+    let payload = match authenticate(&pool, headers).await {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::UNAUTHORIZED, Json(e)).into_response(),
+    };
+
+    match check_second_factor(&pool, payload.sub, &body.code).await {
+        Ok(true) => (),
+        Ok(false) => return invalid_code(),
+        Err(_) => return totp_failure(),
+    }
+
+    match delete_totp_credential(&pool, payload.sub).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(_) => totp_failure(),
+    }
+}
@@ -0,0 +1,137 @@
+//! This module holds the handler which allows a player to change their username.
+//!
+//! # Environment
+//!
+//! * `USERNAME_RESERVATION_DAYS` - (Optional) How long a username stays reserved for its previous
+//!   owner after a rename. Defaults to 90.
+//! * `USERNAME_CHANGE_COOLDOWN_DAYS` - (Optional) How long a player must wait between renames.
+//!   Defaults to 30.
+
+use axum::{
+    extract::State,
+    http::{header::RETRY_AFTER, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::{
+    config::from_env,
+    db::queries::{
+        get_player_by_token,
+        username_history::{change_username, get_last_username_change, is_username_taken},
+    },
+    handlers::{
+        helper::{authenticate, issue_token_pair, ClientInfo},
+        responses::MessageResponse,
+    },
+    validators::validate_username,
+};
+
+/// The expected request body shape for the username change request.
+#[derive(Deserialize)]
+pub struct ReqBody {
+    username: String,
+}
+
+/// How long a username stays reserved for its previous owner after a rename.
+pub fn username_reservation_period() -> Duration {
+    Duration::days(from_env("USERNAME_RESERVATION_DAYS", 90))
+}
+
+/// How long a player must wait between renames.
+fn username_change_cooldown() -> Duration {
+    Duration::days(from_env("USERNAME_CHANGE_COOLDOWN_DAYS", 30))
+}
+
+/// Change the bearer's username.
+///
+/// Every access token names the player, so every session is signed out and a new token pair is
+/// returned in place of the current one.
+pub async fn handle_username_change(
+    State(pool): State<PgPool>,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Json(body): Json<ReqBody>,
+) -> Response {
+    let payload = match authenticate(&pool, headers).await {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::UNAUTHORIZED, Json(e)).into_response(),
+    };
+
+    let player = match get_player_by_token(&pool, payload).await {
+        Ok(p) => p,
+        Err(_) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(MessageResponse::new("Player could not be found.")),
+            )
+                .into_response()
+        }
+    };
+
+    if !validate_username(&body.username) || body.username == player.username {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(MessageResponse::new("New username is invalid.")),
+        )
+            .into_response();
+    }
+
+    let rename_failure = || {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(MessageResponse::new("Username could not be changed.")),
+        )
+            .into_response()
+    };
+
+    let last_change = match get_last_username_change(&pool, player.id).await {
+        Ok(t) => t,
+        Err(_) => return rename_failure(),
+    };
+    if let Some(last_change) = last_change {
+        let wait = (last_change + username_change_cooldown() - Utc::now()).num_seconds();
+        if wait > 0 {
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(RETRY_AFTER, wait.to_string())],
+                Json(MessageResponse::new(
+                    "Username was changed too recently. Please wait before changing it again.",
+                )),
+            )
+                .into_response();
+        }
+    }
+
+    let reserved_since = Utc::now() - username_reservation_period();
+    match is_username_taken(&pool, &body.username, Some(player.id), reserved_since).await {
+        Ok(false) => (),
+        Ok(true) => {
+            return (
+                StatusCode::CONFLICT,
+                Json(MessageResponse::new("Username already exists.")),
+            )
+                .into_response()
+        }
+        Err(_) => return rename_failure(),
+    }
+
+    let player = match change_username(&pool, player.id, body.username).await {
+        Ok(p) => p,
+        Err(_) => {
+            return (
+                StatusCode::CONFLICT,
+                Json(MessageResponse::new("Username already exists.")),
+            )
+                .into_response()
+        }
+    };
+
+    match issue_token_pair(&pool, player, client).await {
+        Ok(tokens) => (StatusCode::OK, Json(tokens)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(e)).into_response(),
+    }
+}
@@ -0,0 +1,285 @@
+//! This module holds the handlers which manage webhook subscriptions, for other services to be told
+//! about player events (see `webhooks`).
+//!
+//! # Notes
+//!
+//! - These routes are for operators rather than players. They are authenticated with the bearer
+//!   token in `ADMIN_API_KEY`, and are disabled if it is not set (see `helper::authorize_admin`).
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    db::{
+        models::{DeliveryStatus, PlayerEvent, WebhookDelivery, WebhookSubscription},
+        queries::webhooks::{
+            create_webhook_subscription, delete_webhook_subscription, get_webhook_deliveries,
+            get_webhook_subscription, get_webhook_subscriptions, revive_webhook_delivery,
+        },
+    },
+    handlers::{helper::authorize_admin, responses::message},
+    tokens::generate_token,
+};
+
+/// The most deliveries listed at once.
+const DELIVERY_LOG_LIMIT: i64 = 100;
+
+/// The shortest secret a subscription may choose.
+const MIN_SECRET_LENGTH: usize = 16;
+
+/// The expected request body shape for the webhook subscription request.
+#[derive(Deserialize)]
+pub struct ReqBody {
+    url: String,
+    events: Vec<String>,
+    /// A secret to sign deliveries with. One is generated if it is not given.
+    secret: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct DeliveryLogQuery {
+    status: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct WebhookInfo {
+    id: Uuid,
+    url: String,
+    events: Vec<String>,
+    created_at: DateTime<Utc>,
+}
+
+impl From<WebhookSubscription> for WebhookInfo {
+    fn from(subscription: WebhookSubscription) -> Self {
+        WebhookInfo {
+            id: subscription.id,
+            url: subscription.url,
+            events: subscription.events,
+            created_at: subscription.created_at,
+        }
+    }
+}
+
+/// A new subscription. This is the only time its secret is shown.
+#[derive(Serialize)]
+pub struct WebhookCreatedResponse {
+    #[serde(flatten)]
+    webhook: WebhookInfo,
+    secret: String,
+}
+
+#[derive(Serialize)]
+pub struct DeliveryInfo {
+    id: Uuid,
+    event_id: Uuid,
+    event_type: String,
+    status: String,
+    attempts: i32,
+    last_status_code: Option<i32>,
+    last_error: Option<String>,
+    created_at: DateTime<Utc>,
+    /// When the delivery will next be attempted, if it is pending.
+    next_attempt_at: Option<DateTime<Utc>>,
+    delivered_at: Option<DateTime<Utc>>,
+}
+
+impl From<WebhookDelivery> for DeliveryInfo {
+    fn from(delivery: WebhookDelivery) -> Self {
+        let pending = delivery.status == DeliveryStatus::Pending.as_str();
+        DeliveryInfo {
+            id: delivery.id,
+            event_id: delivery.event_id,
+            event_type: delivery.event_type,
+            status: delivery.status,
+            attempts: delivery.attempts,
+            last_status_code: delivery.last_status_code,
+            last_error: delivery.last_error,
+            created_at: delivery.created_at,
+            next_attempt_at: pending.then_some(delivery.next_attempt_at),
+            delivered_at: delivery.delivered_at,
+        }
+    }
+}
+
+/// Check the fields of a new subscription.
+///
+/// # Returns
+///
+/// * `Ok(())` if they are valid.
+/// * `Err(&str)` describing the first problem, if not.
+fn validate_subscription(body: &ReqBody) -> Result<(), &'static str> {
+    match Url::parse(&body.url) {
+        Ok(url) if url.scheme() == "https" || url.scheme() == "http" => (),
+        _ => return Err("URL must be an absolute http(s) URL."),
+    }
+    if body.events.is_empty() {
+        return Err("At least one event must be chosen.");
+    }
+    let known = PlayerEvent::ALL.map(|e| e.as_str());
+    if !body.events.iter().all(|e| known.contains(&e.as_str())) {
+        return Err(
+            "Events must be player.created, player.updated, player.deleted or player.login.",
+        );
+    }
+    if body
+        .secret
+        .as_ref()
+        .is_some_and(|secret| secret.len() < MIN_SECRET_LENGTH)
+    {
+        return Err("Secret must be at least 16 characters.");
+    }
+    Ok(())
+}
+
+/// Subscribe a webhook to player events.
+pub async fn handle_webhook_creation(
+    State(pool): State<PgPool>,
+    headers: HeaderMap,
+    Json(body): Json<ReqBody>,
+) -> Response {
+    if let Err((status, e)) = authorize_admin(headers) {
+        return (status, Json(e)).into_response();
+    }
+    if let Err(reason) = validate_subscription(&body) {
+        return message(StatusCode::BAD_REQUEST, reason);
+    }
+
+    let mut events = body.events;
+    events.sort();
+    events.dedup();
+    let secret = body.secret.unwrap_or_else(generate_token);
+
+    match create_webhook_subscription(&pool, body.url, events, secret).await {
+        Ok(subscription) => (
+            StatusCode::CREATED,
+            Json(WebhookCreatedResponse {
+                secret: subscription.secret.clone(),
+                webhook: WebhookInfo::from(subscription),
+            }),
+        )
+            .into_response(),
+        Err(_) => message(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Webhook could not be created.",
+        ),
+    }
+}
+
+pub async fn handle_list_webhooks(State(pool): State<PgPool>, headers: HeaderMap) -> Response {
+    if let Err((status, e)) = authorize_admin(headers) {
+        return (status, Json(e)).into_response();
+    }
+
+    match get_webhook_subscriptions(&pool).await {
+        Ok(subscriptions) => (
+            StatusCode::OK,
+            Json(
+                subscriptions
+                    .into_iter()
+                    .map(WebhookInfo::from)
+                    .collect::<Vec<WebhookInfo>>(),
+            ),
+        )
+            .into_response(),
+        Err(_) => message(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Webhooks could not be fetched.",
+        ),
+    }
+}
+
+/// Unsubscribe a webhook. Its pending deliveries are dropped, along with its delivery log.
+pub async fn handle_webhook_deletion(
+    State(pool): State<PgPool>,
+    headers: HeaderMap,
+    Path(webhook_id): Path<Uuid>,
+) -> Response {
+    if let Err((status, e)) = authorize_admin(headers) {
+        return (status, Json(e)).into_response();
+    }
+
+    match delete_webhook_subscription(&pool, webhook_id).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => message(StatusCode::NOT_FOUND, "Webhook could not be found."),
+        Err(_) => message(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Webhook could not be deleted.",
+        ),
+    }
+}
+
+/// List the latest deliveries to a webhook, newest first, optionally only those with a status
+/// (`pending`, `delivered` or `dead`).
+pub async fn handle_list_webhook_deliveries(
+    State(pool): State<PgPool>,
+    headers: HeaderMap,
+    Path(webhook_id): Path<Uuid>,
+    Query(query): Query<DeliveryLogQuery>,
+) -> Response {
+    if let Err((status, e)) = authorize_admin(headers) {
+        return (status, Json(e)).into_response();
+    }
+
+    let status = match query.status.as_deref() {
+        None => None,
+        Some("pending") => Some(DeliveryStatus::Pending),
+        Some("delivered") => Some(DeliveryStatus::Delivered),
+        Some("dead") => Some(DeliveryStatus::Dead),
+        Some(_) => {
+            return message(
+                StatusCode::BAD_REQUEST,
+                "Status must be pending, delivered or dead.",
+            )
+        }
+    };
+
+    if get_webhook_subscription(&pool, webhook_id).await.is_err() {
+        return message(StatusCode::NOT_FOUND, "Webhook could not be found.");
+    }
+
+    match get_webhook_deliveries(&pool, webhook_id, status, DELIVERY_LOG_LIMIT).await {
+        Ok(deliveries) => (
+            StatusCode::OK,
+            Json(
+                deliveries
+                    .into_iter()
+                    .map(DeliveryInfo::from)
+                    .collect::<Vec<DeliveryInfo>>(),
+            ),
+        )
+            .into_response(),
+        Err(_) => message(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Deliveries could not be fetched.",
+        ),
+    }
+}
+
+/// Attempt a dead delivery again, as if it were new.
+pub async fn handle_webhook_delivery_retry(
+    State(pool): State<PgPool>,
+    headers: HeaderMap,
+    Path((webhook_id, delivery_id)): Path<(Uuid, Uuid)>,
+) -> Response {
+    if let Err((status, e)) = authorize_admin(headers) {
+        return (status, Json(e)).into_response();
+    }
+
+    match revive_webhook_delivery(&pool, webhook_id, delivery_id).await {
+        Ok(true) => message(StatusCode::ACCEPTED, "Delivery will be attempted again."),
+        Ok(false) => message(StatusCode::NOT_FOUND, "Dead delivery could not be found."),
+        Err(_) => message(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Delivery could not be retried.",
+        ),
+    }
+}
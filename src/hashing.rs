@@ -10,41 +10,175 @@
 //! # Example
 //!
 //! ```rust
-//! use crate::hashing::{hash_password, verify_password};
+//! use crate::hashing::{current_pepper_version, hash_password, verify_password};
 //!
 //! let password = "hunter2";
 //!
-//! let hash = hash_password(password).expect("Hashing failed");
-//! assert!(verify_password(password, &hash).unwrap());
+//! let pepper_version = current_pepper_version();
+//! let hash = hash_password(password, pepper_version).expect("Hashing failed");
+//! assert!(verify_password(password, &hash, pepper_version).unwrap());
 //! ```
 //!
 //! # Notes
 //!
 //! - All returned hashes are in [PHC string format](https://github.com/P-H-C/phc-string-format).
-//! - The hashing algorithm is Argon2id with default parameters.
+//! - The hashing algorithm is Argon2id, with the parameters below. A hash records the parameters it
+//!   was made with, so hashes made with older parameters still verify. Use `needs_rehash` to find
+//!   them, and replace them while the raw password is at hand.
 //! - Do not compare hashes manually—always use `verify_password`.
+//! - Passwords can be peppered: the secret is mixed into the hash (as the Argon2 secret), but is
+//!   kept in the environment rather than the database, so a leaked database alone is not enough to
+//!   crack any hashes. The pepper is not part of the PHC string, so the version of the pepper a hash
+//!   was made with must be stored alongside it, and passed back in when verifying it.
+//! - To rotate the pepper, add a new version and keep the old ones until every hash made with them
+//!   has been replaced. `needs_rehash` reports hashes made with an old pepper.
+//! - When there may be no hash to verify against (such as when a username does not exist), use
+//!   `verify_password_or_dummy`, so that response times do not reveal which case it was.
+//!
+//! # Environment
+//!
+//! * `ARGON2_MEMORY_KIB` - (Optional) The memory cost, in KiB. Defaults to 19456 (19 MiB).
+//! * `ARGON2_ITERATIONS` - (Optional) The time cost, in iterations. Defaults to 2.
+//! * `ARGON2_PARALLELISM` - (Optional) The degree of parallelism. Defaults to 1.
+//! * `PASSWORD_PEPPERS` - (Optional) A comma separated list of versioned peppers, like
+//!   `1:secret,2:newer-secret`. Versions are positive integers. Without any, passwords are not
+//!   peppered.
+//! * `PASSWORD_PEPPER_VERSION` - (Optional) The version of the pepper new hashes are made with.
+//!   Defaults to the highest version.
+
+use std::{collections::BTreeMap, env, sync::OnceLock};
 
 use argon2::{
     password_hash::{
         rand_core::OsRng, Error as HashError, PasswordHash, PasswordHasher, SaltString,
     },
-    Argon2, PasswordVerifier,
+    Algorithm, Argon2, Params, PasswordVerifier, Version,
 };
 
+use crate::config::from_env;
+
+/// The parameters new hashes are made with.
+///
+/// # Errors
+///
+/// Panics if the parameters in the environment are not valid together.
+fn configured_params() -> &'static Params {
+    static PARAMS: OnceLock<Params> = OnceLock::new();
+    PARAMS.get_or_init(|| {
+        Params::new(
+            from_env("ARGON2_MEMORY_KIB", Params::DEFAULT_M_COST),
+            from_env("ARGON2_ITERATIONS", Params::DEFAULT_T_COST),
+            from_env("ARGON2_PARALLELISM", Params::DEFAULT_P_COST),
+            None,
+        )
+        .expect("Environment is not set up properly; invalid Argon2 parameters")
+    })
+}
+
+/// The peppers which hashes can be made with, by version.
+struct Peppers {
+    /// The version new hashes are made with, if any.
+    current: Option<i32>,
+    secrets: BTreeMap<i32, Vec<u8>>,
+}
+
+impl Peppers {
+    /// Parse the peppers from the values of `PASSWORD_PEPPERS` and `PASSWORD_PEPPER_VERSION`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Peppers)` if the values are valid.
+    /// * `Err(String)` describing the first problem found, if not.
+    fn parse(peppers: Option<&str>, current: Option<&str>) -> Result<Self, String> {
+        let mut secrets = BTreeMap::new();
+        for entry in peppers.unwrap_or_default().split(',') {
+            let entry = entry.trim();
+            if entry.is_empty() {
+                continue;
+            }
+            let (version, secret) = entry
+                .split_once(':')
+                .ok_or("pepper is not formatted as `version:secret`")?;
+            let version = match version.trim().parse::<i32>() {
+                Ok(version) if version > 0 => version,
+                _ => return Err(format!("pepper version `{}` is invalid", version)),
+            };
+            if secret.is_empty() {
+                return Err(format!("pepper {} is empty", version));
+            }
+            if secrets
+                .insert(version, secret.as_bytes().to_vec())
+                .is_some()
+            {
+                return Err(format!("pepper {} is defined twice", version));
+            }
+        }
+
+        let current = match current.map(str::trim).filter(|value| !value.is_empty()) {
+            Some(value) => match value.parse::<i32>() {
+                Ok(version) if secrets.contains_key(&version) => Some(version),
+                _ => return Err(format!("pepper version `{}` is not defined", value)),
+            },
+            None => secrets.keys().next_back().copied(),
+        };
+        Ok(Peppers { current, secrets })
+    }
+}
+
+/// The peppers from the environment.
+///
+/// # Errors
+///
+/// Panics if the peppers in the environment are not valid.
+fn peppers() -> &'static Peppers {
+    static PEPPERS: OnceLock<Peppers> = OnceLock::new();
+    PEPPERS.get_or_init(|| {
+        Peppers::parse(
+            env::var("PASSWORD_PEPPERS").ok().as_deref(),
+            env::var("PASSWORD_PEPPER_VERSION").ok().as_deref(),
+        )
+        .unwrap_or_else(|e| panic!("Environment is not set up properly; {}", e))
+    })
+}
+
+/// The version of the pepper new hashes should be made with, or `None` if passwords are not
+/// peppered. Store it alongside the hash.
+pub fn current_pepper_version() -> Option<i32> {
+    peppers().current
+}
+
+/// An Argon2 instance with the configured parameters and the given pepper.
+///
+/// # Errors
+///
+/// Returns `HashError::Crypto` if there is no pepper with the given version.
+fn hasher(pepper_version: Option<i32>) -> Result<Argon2<'static>, HashError> {
+    let params = configured_params().clone();
+    match pepper_version {
+        None => Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params)),
+        Some(version) => {
+            let secret = peppers().secrets.get(&version).ok_or(HashError::Crypto)?;
+            Argon2::new_with_secret(secret, Algorithm::Argon2id, Version::V0x13, params)
+                .map_err(|_| HashError::Crypto)
+        }
+    }
+}
+
 /// Hashes a raw password using Argon2 and a randomly generated salt.
 ///
 /// # Arguments
 ///
 /// * `password` - The raw plaintext password to be hashed.
+/// * `pepper_version` - The version of the pepper to use (usually `current_pepper_version()`), or
+///   `None` for no pepper.
 ///
 /// # Returns
 ///
 /// * `Ok(String)` containing the password hash in PHC string format.
-/// * `Err(HashError)` if hashing fails.
-pub fn hash_password(password: &str) -> Result<String, HashError> {
+/// * `Err(HashError)` if hashing fails, or there is no pepper with the given version.
+pub fn hash_password(password: &str, pepper_version: Option<i32>) -> Result<String, HashError> {
     let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
-    Ok(argon2
+    Ok(hasher(pepper_version)?
         .hash_password(password.as_bytes(), &salt)?
         .to_string())
 }
@@ -55,6 +189,8 @@ pub fn hash_password(password: &str) -> Result<String, HashError> {
 ///
 /// * `password` - The raw plaintext password provided by the user.
 /// * `hash` - The previously computed Argon2 password hash (in PHC string format).
+/// * `pepper_version` - The version of the pepper the hash was made with, or `None` if it was not
+///   peppered.
 ///
 /// # Returns
 ///
@@ -63,23 +199,241 @@ pub fn hash_password(password: &str) -> Result<String, HashError> {
 ///
 /// # Errors
 ///
-/// Returns a `HashError` when the provided hash cannot be parsed.
-pub fn verify_password(password: &str, hash: &str) -> Result<bool, HashError> {
+/// Returns a `HashError` when the provided hash cannot be parsed, or there is no pepper with the
+/// given version.
+pub fn verify_password(
+    password: &str,
+    hash: &str,
+    pepper_version: Option<i32>,
+) -> Result<bool, HashError> {
     let parsed_hash = PasswordHash::new(hash)?;
-    Ok(Argon2::default()
+    // The algorithm, version and parameters are read from the hash itself.
+    Ok(hasher(pepper_version)?
         .verify_password(password.as_bytes(), &parsed_hash)
         .is_ok())
 }
 
+/// Check whether a hash was made with a different algorithm, different parameters or a different
+/// pepper than new hashes are, and so should be replaced.
+///
+/// # Arguments
+///
+/// * `hash` - A password hash (in PHC string format) which has just been verified.
+/// * `pepper_version` - The version of the pepper the hash was made with.
+///
+/// # Returns
+///
+/// `true` if the password should be hashed again, including when the hash cannot be parsed.
+pub fn needs_rehash(hash: &str, pepper_version: Option<i32>) -> bool {
+    pepper_version != current_pepper_version() || needs_rehash_with(hash, configured_params())
+}
+
+fn needs_rehash_with(hash: &str, params: &Params) -> bool {
+    let parsed = match PasswordHash::new(hash) {
+        Ok(parsed) => parsed,
+        Err(_) => return true,
+    };
+    let hash_params = match Params::try_from(&parsed) {
+        Ok(hash_params) => hash_params,
+        Err(_) => return true,
+    };
+    let output_len = |p: &Params| p.output_len().unwrap_or(Params::DEFAULT_OUTPUT_LEN);
+    Algorithm::try_from(parsed.algorithm) != Ok(Algorithm::Argon2id)
+        || parsed.version != Some(Version::V0x13.into())
+        || hash_params.m_cost() != params.m_cost()
+        || hash_params.t_cost() != params.t_cost()
+        || hash_params.p_cost() != params.p_cost()
+        || output_len(&hash_params) != output_len(params)
+}
+
+/// A hash of a random password, made with the same parameters and pepper as every new hash.
+fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| {
+        let password = SaltString::generate(&mut OsRng);
+        hash_password(password.as_str(), current_pepper_version())
+            .expect("Dummy password could not be hashed")
+    })
+}
+
+/// Verify a raw password against a hash which may not exist, taking the same time either way.
+///
+/// # Arguments
+///
+/// * `password` - The raw plaintext password provided by the user.
+/// * `hash` - The stored hash and the version of its pepper, or `None` if there is no such user.
+///   The password is verified against a dummy hash instead, which it can never match.
+///
+/// # Returns
+///
+/// `true` only if there is a hash, and the password matches it.
+pub fn verify_password_or_dummy(password: &str, hash: Option<(&str, Option<i32>)>) -> bool {
+    match hash {
+        Some((hash, pepper_version)) => {
+            verify_password(password, hash, pepper_version).unwrap_or(false)
+        }
+        None => {
+            let _ = verify_password(password, dummy_hash(), current_pepper_version());
+            false
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::time::{Duration, Instant};
+
     use super::*;
 
     #[test]
     pub fn test_hashing() {
         let password = "hunter2";
-        let hash = hash_password(password).expect("Hashing failed");
-        assert!(verify_password(password, &hash).unwrap());
-        assert!(!verify_password("wrongpassword", &hash).unwrap());
+        let hash = hash_password(password, None).expect("Hashing failed");
+        assert!(verify_password(password, &hash, None).unwrap());
+        assert!(!verify_password("wrongpassword", &hash, None).unwrap());
+        // Peppers which are not configured cannot be used.
+        assert!(hash_password(password, Some(i32::MAX)).is_err());
+        assert!(verify_password(password, &hash, Some(i32::MAX)).is_err());
+    }
+
+    #[test]
+    pub fn test_peppered_hash() {
+        let peppered = Argon2::new_with_secret(
+            b"pepper",
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(8 * 1024, 1, 1, None).unwrap(),
+        )
+        .unwrap()
+        .hash_password(b"hunter2", &SaltString::generate(&mut OsRng))
+        .unwrap()
+        .to_string();
+        // Without the pepper, even the right password does not match.
+        assert!(!verify_password("hunter2", &peppered, None).unwrap());
+    }
+
+    #[test]
+    pub fn test_parse_peppers() {
+        let peppers = Peppers::parse(None, None).unwrap();
+        assert_eq!(peppers.current, None);
+        assert!(peppers.secrets.is_empty());
+
+        let peppers = Peppers::parse(Some(" 1:old, 2:new:er ,"), None).unwrap();
+        assert_eq!(peppers.current, Some(2));
+        assert_eq!(peppers.secrets[&1], b"old");
+        assert_eq!(peppers.secrets[&2], b"new:er");
+
+        let peppers = Peppers::parse(Some("1:old,2:new"), Some("1")).unwrap();
+        assert_eq!(peppers.current, Some(1));
+
+        for (peppers, current) in [
+            ("secret", None),
+            ("0:secret", None),
+            ("x:secret", None),
+            ("1:", None),
+            ("1:a,1:b", None),
+            ("1:secret", Some("2")),
+            ("", Some("1")),
+        ] {
+            assert!(
+                Peppers::parse(Some(peppers), current).is_err(),
+                "{} {:?}",
+                peppers,
+                current
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_verify_password_or_dummy() {
+        let hash = hash_password("hunter2", None).expect("Hashing failed");
+        assert!(verify_password_or_dummy("hunter2", Some((&hash, None))));
+        assert!(!verify_password_or_dummy(
+            "wrongpassword",
+            Some((&hash, None))
+        ));
+        assert!(!verify_password_or_dummy("hunter2", None));
+        assert!(!verify_password_or_dummy("", None));
+    }
+
+    #[test]
+    pub fn test_needs_rehash() {
+        let params = Params::new(8 * 1024, 1, 1, None).unwrap();
+        let hash_with = |algorithm, version, params: &Params| {
+            Argon2::new(algorithm, version, params.clone())
+                .hash_password(b"hunter2", &SaltString::generate(&mut OsRng))
+                .unwrap()
+                .to_string()
+        };
+
+        let current = hash_with(Algorithm::Argon2id, Version::V0x13, &params);
+        assert!(!needs_rehash_with(&current, &params));
+
+        for outdated in [
+            hash_with(Algorithm::Argon2i, Version::V0x13, &params),
+            hash_with(Algorithm::Argon2id, Version::V0x10, &params),
+            hash_with(
+                Algorithm::Argon2id,
+                Version::V0x13,
+                &Params::new(4 * 1024, 1, 1, None).unwrap(),
+            ),
+            hash_with(
+                Algorithm::Argon2id,
+                Version::V0x13,
+                &Params::new(8 * 1024, 2, 1, None).unwrap(),
+            ),
+            hash_with(
+                Algorithm::Argon2id,
+                Version::V0x13,
+                &Params::new(8 * 1024, 1, 2, None).unwrap(),
+            ),
+            hash_with(
+                Algorithm::Argon2id,
+                Version::V0x13,
+                &Params::new(8 * 1024, 1, 1, Some(64)).unwrap(),
+            ),
+        ] {
+            assert!(needs_rehash_with(&outdated, &params), "{}", outdated);
+            // Outdated hashes still verify.
+            assert!(verify_password("hunter2", &outdated, None).unwrap());
+        }
+
+        assert!(needs_rehash_with("not a hash", &params));
+    }
+
+    /// The fastest of a few runs, which is the least affected by whatever else the machine is doing.
+    fn fastest(runs: usize, f: impl Fn()) -> Duration {
+        (0..runs)
+            .map(|_| {
+                let start = Instant::now();
+                f();
+                start.elapsed()
+            })
+            .min()
+            .unwrap()
+    }
+
+    /// Logging in to a username which does not exist must take as long as a wrong password, so that
+    /// response times do not reveal which usernames exist.
+    #[test]
+    pub fn test_verify_password_or_dummy_timing() {
+        let hash = hash_password("hunter2", None).expect("Hashing failed");
+        // Make sure the dummy hash is not computed during a measured run.
+        verify_password_or_dummy("warmup", None);
+
+        let existing = fastest(3, || {
+            verify_password_or_dummy("wrongpassword", Some((&hash, None)));
+        });
+        let missing = fastest(3, || {
+            verify_password_or_dummy("wrongpassword", None);
+        });
+
+        let ratio = missing.as_secs_f64() / existing.as_secs_f64();
+        assert!(
+            (0.5..2.0).contains(&ratio),
+            "existing: {:?}, missing: {:?}",
+            existing,
+            missing
+        );
     }
 }
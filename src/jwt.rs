@@ -1,25 +1,80 @@
-use std::env;
+//! This module encodes and decodes the authentication tokens (JWTs) issued to players.
+//!
+//! Tokens are signed with an asymmetric key, so that other services can verify them using the
+//! public keys published at `/.well-known/jwks.json` without being able to mint tokens themselves.
+//! The `kid` header of each token selects the key from the key ring which verifies it, so signing
+//! keys can be rotated without invalidating outstanding tokens.
+//!
+//! # Environment
+//!
+//! Either:
+//!
+//! * `JWT_KEY_DIR` - A directory of `.pem` keys, each named after its `kid`.
+//! * `JWT_ACTIVE_KEY_ID` - (Optional) The `kid` of the key to sign with. Defaults to the last
+//!   private key in the directory.
+//!
+//! Or:
+//!
+//! * `JWT_PRIVATE_KEY` - The PEM encoded private key to sign tokens with, **or**
+//! * `JWT_PRIVATE_KEY_FILE` - The path to a file containing it.
+//! * `JWT_KEY_ID` - (Optional) The `kid` to publish the key under. Defaults to its JWK thumbprint.
+//! * `JWT_VERIFICATION_KEYS` - (Optional) PEM encoded keys which are still accepted, but no longer
+//!   used for signing.
+
+mod key_ring;
+mod keys;
+
+use std::sync::OnceLock;
 
 use jsonwebtoken::{
-    decode, encode, errors::Error as JWTError, get_current_timestamp, Algorithm, DecodingKey,
-    EncodingKey, Header, TokenData, Validation,
+    decode, decode_header, encode,
+    errors::{Error as JWTError, ErrorKind},
+    get_current_timestamp,
+    jwk::JwkSet,
+    Header, TokenData, Validation,
 };
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use key_ring::KeyRing;
+
+static KEY_RING: OnceLock<KeyRing> = OnceLock::new();
+
+/// Get the key ring used to sign and verify authentication tokens, loading it from the environment
+/// the first time it is needed.
+///
+/// # Errors
+///
+/// Panics if the environment does not provide a valid key ring.
+pub fn key_ring() -> &'static KeyRing {
+    KEY_RING.get_or_init(|| {
+        KeyRing::from_env().unwrap_or_else(|e| panic!("Failed to load the JWT key ring: {}", e))
+    })
+}
+
 /// The unique inputs required for creating a new authentication token.
 pub struct AuthnTokenReqs {
     pub id: Uuid,
     pub username: String,
     pub email: String,
+    pub email_verified: bool,
+    pub session_id: Uuid,
 }
 
 impl AuthnTokenReqs {
-    pub fn new(id: Uuid, username: String, email: String) -> Self {
+    pub fn new(
+        id: Uuid,
+        username: String,
+        email: String,
+        email_verified: bool,
+        session_id: Uuid,
+    ) -> Self {
         AuthnTokenReqs {
-            id: id,
-            username: username,
-            email: email,
+            id,
+            username,
+            email,
+            email_verified,
+            session_id,
         }
     }
 }
@@ -30,15 +85,21 @@ pub struct AuthnTokenPayload {
     pub sub: Uuid,
     pub username: String,
     pub email: String,
+    /// Whether the player had verified their email address when the token was issued. Tokens
+    /// issued before this claim existed are treated as unverified.
+    #[serde(default)]
+    pub email_verified: bool,
     pub iat: u64,
     pub exp: u64,
     pub iss: String,
     pub nbf: u64,
+    pub jti: Uuid,
+    pub sid: Uuid,
 }
 
 impl AuthnTokenPayload {
     /// Create a new AuthnTokenPayload which is valid for 60 minutes following
-    /// its creation.
+    /// its creation. Each payload is given a unique `jti` so that it can be revoked.
     ///
     /// # Arguments
     ///
@@ -49,10 +110,13 @@ impl AuthnTokenPayload {
             sub: reqs.id,
             username: reqs.username,
             email: reqs.email,
-            iat: iat,
+            email_verified: reqs.email_verified,
+            iat,
             nbf: iat,
             exp: iat + 3600,
             iss: String::from("bitcasino.bigdevdog.com"),
+            jti: Uuid::new_v4(),
+            sid: reqs.session_id,
         }
     }
 }
@@ -68,18 +132,27 @@ impl AuthnTokenPayload {
 /// * `Ok(String)` when the token generates successfully.
 /// * `Err(JWTError)` when the token cannot be encoded.
 pub fn encode_authn_token(reqs: AuthnTokenReqs) -> Result<String, JWTError> {
-    let payload = AuthnTokenPayload::new(reqs);
-    let secret =
-        env::var("JWT_SECRET").expect("Environment is not set up properly; missing 'JWT_SECRET'");
-    encode(
-        &Header::default(),
-        &payload,
-        &EncodingKey::from_secret(secret.as_bytes()),
-    )
+    encode_with(key_ring(), &AuthnTokenPayload::new(reqs))
+}
+
+fn encode_with(ring: &KeyRing, payload: &AuthnTokenPayload) -> Result<String, JWTError> {
+    let key = ring.active_key();
+    let encoding = key
+        .encoding
+        .as_ref()
+        .expect("The active key is always a private key");
+    let mut header = Header::new(key.algorithm);
+    header.kid = Some(key.kid.clone());
+    encode(&header, payload, encoding)
 }
 
 /// Decodes an authentication token.
 ///
+/// # Notes
+///
+/// * This only checks the signature and the time-based claims. Handlers should use
+///   `handlers::helper::authenticate`, which also checks whether the token has been revoked.
+///
 /// # Arguments
 ///
 /// * `token` - The JWT to decode.
@@ -87,15 +160,29 @@ pub fn encode_authn_token(reqs: AuthnTokenReqs) -> Result<String, JWTError> {
 /// # Returns
 ///
 /// * `Ok(TokenData<AuthnTokenPayload>)` when the token is decoded.
-/// * `Err(JWTError)` if the token cannot be decoded.
+/// * `Err(JWTError)` if the token cannot be decoded, or was not signed by a key in the key ring.
 pub fn decode_authn_token(token: String) -> Result<TokenData<AuthnTokenPayload>, JWTError> {
-    let secret =
-        env::var("JWT_SECRET").expect("Environment is not set up properly; missing 'JWT_SECRET'");
-    decode(
-        &token,
-        &DecodingKey::from_secret(secret.as_bytes()),
-        &Validation::new(Algorithm::HS256),
-    )
+    decode_with(key_ring(), &token)
+}
+
+fn decode_with(ring: &KeyRing, token: &str) -> Result<TokenData<AuthnTokenPayload>, JWTError> {
+    let key = match decode_header(token)?.kid {
+        Some(kid) => ring.find(&kid),
+        None => None,
+    };
+    match key {
+        Some(key) => decode(token, &key.decoding, &Validation::new(key.algorithm)),
+        None => Err(ErrorKind::InvalidSignature.into()),
+    }
+}
+
+/// Get the public keys which can be used to verify authentication tokens.
+///
+/// # Returns
+///
+/// A JWK set, ready to be served to other services.
+pub fn public_jwks() -> JwkSet {
+    key_ring().jwks()
 }
 
 #[cfg(test)]
@@ -110,11 +197,103 @@ mod tests {
         let id = Uuid::new_v4();
         let username = String::from("b1gd3vd0g");
         let email = String::from("b1gd3vd0g@bigdevdog.com");
-        let reqs = AuthnTokenReqs::new(id.clone(), username.clone(), email.clone());
+        let session_id = Uuid::new_v4();
+        let reqs = AuthnTokenReqs::new(id, username.clone(), email.clone(), true, session_id);
         let token = encode_authn_token(reqs).unwrap();
         let decoded = decode_authn_token(token).unwrap();
         assert_eq!(decoded.claims.sub, id);
         assert_eq!(decoded.claims.username, username);
         assert_eq!(decoded.claims.email, email);
+        assert!(decoded.claims.email_verified);
+        assert_eq!(decoded.claims.sid, session_id);
+    }
+
+    #[test]
+    fn test_unique_jti() {
+        test_setup();
+        let reqs = || {
+            let id = Uuid::new_v4();
+            AuthnTokenReqs::new(id, String::from("b1gd3vd0g"), String::new(), false, id)
+        };
+        let a = decode_authn_token(encode_authn_token(reqs()).unwrap()).unwrap();
+        let b = decode_authn_token(encode_authn_token(reqs()).unwrap()).unwrap();
+        assert_ne!(a.claims.jti, b.claims.jti);
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let pem = |seed: u8| {
+            let mut der = vec![
+                0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22,
+                0x04, 0x20,
+            ];
+            der.extend([seed; 32]);
+            pem::encode(&pem::Pem::new("PRIVATE KEY", der))
+        };
+        let key = |seed: u8| keys::Key::from_pem(pem(seed).as_bytes(), None).unwrap();
+
+        let id = Uuid::new_v4();
+        let payload = || {
+            AuthnTokenPayload::new(AuthnTokenReqs::new(
+                id,
+                String::new(),
+                String::new(),
+                false,
+                id,
+            ))
+        };
+
+        let old_ring = KeyRing::new(key(1), vec![]).unwrap();
+        let old_token = encode_with(&old_ring, &payload()).unwrap();
+
+        // After rotating, the old key can still verify tokens, but new tokens use the new key.
+        let new_ring = KeyRing::new(key(2), vec![key(1)]).unwrap();
+        let new_token = encode_with(&new_ring, &payload()).unwrap();
+        assert!(decode_with(&new_ring, &old_token).is_ok());
+        assert!(decode_with(&new_ring, &new_token).is_ok());
+        assert!(decode_with(&old_ring, &new_token).is_err());
+
+        // Once the old key is retired, its tokens are no longer accepted.
+        let retired_ring = KeyRing::new(key(2), vec![]).unwrap();
+        assert!(decode_with(&retired_ring, &old_token).is_err());
+    }
+
+    #[test]
+    fn test_kid_header() {
+        test_setup();
+        let id = Uuid::new_v4();
+        let token = encode_authn_token(AuthnTokenReqs::new(
+            id,
+            String::new(),
+            String::new(),
+            false,
+            id,
+        ));
+        let header = decode_header(&token.unwrap()).unwrap();
+        assert_eq!(header.kid, public_jwks().keys[0].common.key_id);
+    }
+
+    #[test]
+    fn test_reject_symmetric_token() {
+        test_setup();
+        let id = Uuid::new_v4();
+        let payload = AuthnTokenPayload::new(AuthnTokenReqs::new(
+            id,
+            String::new(),
+            String::new(),
+            false,
+            id,
+        ));
+        let header = Header {
+            kid: Some(key_ring().active_key().kid.clone()),
+            ..Header::default()
+        };
+        let token = encode(
+            &header,
+            &payload,
+            &jsonwebtoken::EncodingKey::from_secret(b"supersecretvalue"),
+        )
+        .unwrap();
+        assert!(decode_authn_token(token).is_err());
     }
 }
@@ -0,0 +1,203 @@
+//! This module manages the key ring used for authentication tokens: exactly one **active** key which
+//! signs new tokens, and any number of **verification-only** keys which are still accepted (and
+//! published) so that tokens signed before a rotation keep working until they expire.
+//!
+//! # Rotating keys
+//!
+//! 1. Add the new key to the ring as the active key, keeping the old key as a verification-only
+//!    key. Its private key is no longer needed; its public key is enough.
+//! 2. Once every token signed by the old key has expired (60 minutes), remove the old key.
+
+use std::{env, fs, path::Path};
+
+use jsonwebtoken::jwk::JwkSet;
+
+use super::keys::Key;
+
+pub struct KeyRing {
+    active_kid: String,
+    keys: Vec<Key>,
+}
+
+impl KeyRing {
+    /// Create a new key ring.
+    ///
+    /// # Arguments
+    ///
+    /// * `active` - The key which signs new tokens. Must be a private key.
+    /// * `verification` - Keys which are only used to verify tokens.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(KeyRing)` on success.
+    /// * `Err(String)` if the active key cannot sign, or two keys share a `kid`.
+    pub fn new(active: Key, verification: Vec<Key>) -> Result<Self, String> {
+        if active.encoding.is_none() {
+            return Err(format!(
+                "The active key '{}' is not a private key",
+                active.kid
+            ));
+        }
+        let active_kid = active.kid.clone();
+        let mut keys = vec![active];
+        for key in verification {
+            if keys.iter().any(|k| k.kid == key.kid) {
+                return Err(format!("Duplicate key id '{}'", key.kid));
+            }
+            keys.push(key);
+        }
+        Ok(KeyRing { active_kid, keys })
+    }
+
+    /// Load a key ring from the environment.
+    ///
+    /// If `JWT_KEY_DIR` is set, the ring is loaded from that directory (see `KeyRing::from_dir`),
+    /// using `JWT_ACTIVE_KEY_ID` to select the active key. Otherwise, the active key is read from
+    /// `JWT_PRIVATE_KEY` or `JWT_PRIVATE_KEY_FILE` (with the optional `JWT_KEY_ID`), and any
+    /// PEM blocks in `JWT_VERIFICATION_KEYS` are added as verification-only keys.
+    pub fn from_env() -> Result<Self, String> {
+        if let Ok(dir) = env::var("JWT_KEY_DIR") {
+            return KeyRing::from_dir(Path::new(&dir), env::var("JWT_ACTIVE_KEY_ID").ok());
+        }
+
+        let pem = match env::var("JWT_PRIVATE_KEY") {
+            Ok(pem) => pem.into_bytes(),
+            Err(_) => {
+                let path = env::var("JWT_PRIVATE_KEY_FILE").map_err(|_| {
+                    String::from(
+                        "Missing 'JWT_KEY_DIR', 'JWT_PRIVATE_KEY' or 'JWT_PRIVATE_KEY_FILE'",
+                    )
+                })?;
+                fs::read(&path).map_err(|e| format!("Failed to read '{}': {}", path, e))?
+            }
+        };
+        let active = Key::from_pem(&pem, env::var("JWT_KEY_ID").ok())?;
+
+        let verification = match env::var("JWT_VERIFICATION_KEYS") {
+            Ok(pems) => pem::parse_many(pems)
+                .map_err(|e| format!("Invalid 'JWT_VERIFICATION_KEYS': {}", e))?
+                .iter()
+                .map(|pem| Key::from_parsed_pem(pem, None))
+                .collect::<Result<Vec<Key>, String>>()?,
+            Err(_) => Vec::new(),
+        };
+
+        KeyRing::new(active, verification)
+    }
+
+    /// Load a key ring from every `.pem` file in a directory. Each file holds a single private or
+    /// public key, and the file's name (without the extension) is used as the key's `kid`.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory to read.
+    /// * `active_kid` - The `kid` of the active key. Defaults to the last private key when sorted by
+    ///   `kid`, so naming keys by the date they were created makes the newest key active.
+    pub fn from_dir(dir: &Path, active_kid: Option<String>) -> Result<Self, String> {
+        let read_error = |e: std::io::Error| format!("Failed to read '{}': {}", dir.display(), e);
+
+        let mut keys = Vec::new();
+        for entry in fs::read_dir(dir).map_err(read_error)? {
+            let path = entry.map_err(read_error)?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("pem") {
+                continue;
+            }
+            let kid = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .map(String::from);
+            let pem = fs::read(&path).map_err(read_error)?;
+            let key = Key::from_pem(&pem, kid)
+                .map_err(|e| format!("Failed to load '{}': {}", path.display(), e))?;
+            keys.push(key);
+        }
+        keys.sort_by(|a, b| a.kid.cmp(&b.kid));
+
+        let active_kid = match active_kid {
+            Some(kid) => kid,
+            None => match keys.iter().rev().find(|k| k.encoding.is_some()) {
+                Some(key) => key.kid.clone(),
+                None => return Err(format!("No private keys found in '{}'", dir.display())),
+            },
+        };
+        let active = match keys.iter().position(|k| k.kid == active_kid) {
+            Some(i) => keys.remove(i),
+            None => return Err(format!("No key found with the id '{}'", active_kid)),
+        };
+
+        KeyRing::new(active, keys)
+    }
+
+    /// Get the key which signs new tokens.
+    pub fn active_key(&self) -> &Key {
+        self.find(&self.active_kid)
+            .expect("The active key is always in the ring")
+    }
+
+    /// Find the key with the given `kid`, whether it is active or verification-only.
+    pub fn find(&self, kid: &str) -> Option<&Key> {
+        self.keys.iter().find(|k| k.kid == kid)
+    }
+
+    /// Get the public half of every key in the ring.
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self.keys.iter().map(|k| k.jwk.clone()).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::{rand::SystemRandom, signature::Ed25519KeyPair};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::test_utils::rsa_key_pair;
+
+    fn ed25519_pem() -> String {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        pem::encode(&pem::Pem::new("PRIVATE KEY", pkcs8.as_ref()))
+    }
+
+    fn key_dir(files: &[(&str, &str)]) -> std::path::PathBuf {
+        let dir = env::temp_dir().join(format!("key-ring-{}", Uuid::new_v4()));
+        fs::create_dir(&dir).unwrap();
+        for (name, contents) in files {
+            fs::write(dir.join(name), contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_from_dir() {
+        let dir = key_dir(&[
+            ("2026-01-01.pem", &ed25519_pem()),
+            ("2026-04-01.pem", &ed25519_pem()),
+            ("2026-07-01.pem", &rsa_key_pair().1),
+            ("README.md", "Not a key."),
+        ]);
+
+        let ring = KeyRing::from_dir(&dir, None).unwrap();
+        assert_eq!(ring.active_key().kid, "2026-04-01");
+        assert!(ring.find("2026-01-01").is_some());
+        assert!(ring.find("2026-07-01").is_some());
+        assert!(ring.find("README").is_none());
+        assert_eq!(ring.jwks().keys.len(), 3);
+
+        let ring = KeyRing::from_dir(&dir, Some(String::from("2026-01-01"))).unwrap();
+        assert_eq!(ring.active_key().kid, "2026-01-01");
+
+        assert!(KeyRing::from_dir(&dir, Some(String::from("2026-07-01"))).is_err());
+        assert!(KeyRing::from_dir(&dir, Some(String::from("missing"))).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_duplicate_kid() {
+        let pem = ed25519_pem();
+        let active = Key::from_pem(pem.as_bytes(), None).unwrap();
+        let duplicate = Key::from_pem(pem.as_bytes(), None).unwrap();
+        assert!(KeyRing::new(active, vec![duplicate]).is_err());
+    }
+}
@@ -0,0 +1,262 @@
+//! This module loads the asymmetric keys used to sign and verify authentication tokens, and derives
+//! the public JSON Web Keys which other services use to verify them.
+//!
+//! # Notes
+//!
+//! - Keys are read from PEM encoded keys. RSA keys (PKCS#1 or PKCS#8) use `RS256`, and Ed25519 keys
+//!   (PKCS#8) use `EdDSA`.
+//! - Private keys can both sign and verify tokens. Public keys (`PUBLIC KEY` or `RSA PUBLIC KEY`)
+//!   can only verify them.
+//! - Unless a key id is provided, each key is identified by its RFC 7638 JWK thumbprint.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, KeyAlgorithm,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey,
+};
+use pem::Pem;
+use ring::{
+    rsa::PublicKeyComponents,
+    signature::{Ed25519KeyPair, KeyPair, RsaKeyPair},
+};
+use sha2::{Digest, Sha256};
+use simple_asn1::{from_der, ASN1Block};
+
+/// The DER encoding of an Ed25519 `SubjectPublicKeyInfo`, up to the key itself.
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// A key which can verify authentication tokens, and possibly sign them.
+pub struct Key {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    /// Only present if the key was loaded from a private key.
+    pub encoding: Option<EncodingKey>,
+    pub decoding: DecodingKey,
+    /// The public half of the key, as published by the JWKS endpoint.
+    pub jwk: Jwk,
+}
+
+impl Key {
+    /// Load a key from a single PEM encoded private or public key.
+    ///
+    /// # Arguments
+    ///
+    /// * `pem` - The PEM encoded key.
+    /// * `kid` - The key id to publish the key under. Defaults to the key's JWK thumbprint.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Key)` if the key could be loaded.
+    /// * `Err(String)` describing why the key could not be loaded.
+    pub fn from_pem(pem: &[u8], kid: Option<String>) -> Result<Self, String> {
+        let parsed = pem::parse(pem).map_err(|e| format!("Invalid PEM: {}", e))?;
+        Key::from_parsed_pem(&parsed, kid)
+    }
+
+    /// Load a key from a PEM block which has already been parsed.
+    ///
+    /// # Arguments
+    ///
+    /// * `pem` - The parsed PEM block.
+    /// * `kid` - The key id to publish the key under. Defaults to the key's JWK thumbprint.
+    pub fn from_parsed_pem(pem: &Pem, kid: Option<String>) -> Result<Self, String> {
+        let der = pem.contents();
+        let encoded = pem::encode(pem);
+        let encoded = encoded.as_bytes();
+
+        let (algorithm, encoding, params) = match pem.tag() {
+            "PRIVATE KEY" => match Ed25519KeyPair::from_pkcs8_maybe_unchecked(der) {
+                Ok(pair) => (
+                    Algorithm::EdDSA,
+                    Some(EncodingKey::from_ed_pem(encoded)),
+                    ed25519_params(pair.public_key().as_ref()),
+                ),
+                Err(_) => (
+                    Algorithm::RS256,
+                    Some(EncodingKey::from_rsa_pem(encoded)),
+                    rsa_params(&RsaKeyPair::from_pkcs8(der).map_err(|e| e.to_string())?),
+                ),
+            },
+            "RSA PRIVATE KEY" => (
+                Algorithm::RS256,
+                Some(EncodingKey::from_rsa_pem(encoded)),
+                rsa_params(&RsaKeyPair::from_der(der).map_err(|e| e.to_string())?),
+            ),
+            "PUBLIC KEY" => match der.strip_prefix(&ED25519_SPKI_PREFIX) {
+                Some(x) if x.len() == 32 => (Algorithm::EdDSA, None, ed25519_params(x)),
+                _ => (Algorithm::RS256, None, rsa_spki_params(der)?),
+            },
+            "RSA PUBLIC KEY" => (Algorithm::RS256, None, rsa_pkcs1_params(der)?),
+            tag => return Err(format!("Unsupported PEM label '{}'", tag)),
+        };
+        let encoding = encoding.transpose().map_err(|e| e.to_string())?;
+
+        let kid = kid.unwrap_or_else(|| thumbprint(&params));
+        let jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: Some(match algorithm {
+                    Algorithm::EdDSA => KeyAlgorithm::EdDSA,
+                    _ => KeyAlgorithm::RS256,
+                }),
+                key_id: Some(kid.clone()),
+                ..Default::default()
+            },
+            algorithm: params,
+        };
+        let decoding = DecodingKey::from_jwk(&jwk).map_err(|e| e.to_string())?;
+
+        Ok(Key {
+            kid,
+            algorithm,
+            encoding,
+            decoding,
+            jwk,
+        })
+    }
+}
+
+fn ed25519_params(public_key: &[u8]) -> AlgorithmParameters {
+    AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+        key_type: OctetKeyPairType::OctetKeyPair,
+        curve: EllipticCurve::Ed25519,
+        x: URL_SAFE_NO_PAD.encode(public_key),
+    })
+}
+
+fn rsa_params(pair: &RsaKeyPair) -> AlgorithmParameters {
+    let components = PublicKeyComponents::<Vec<u8>>::from(pair.public());
+    rsa_components_params(&components.n, &components.e)
+}
+
+fn rsa_components_params(n: &[u8], e: &[u8]) -> AlgorithmParameters {
+    AlgorithmParameters::RSA(RSAKeyParameters {
+        key_type: RSAKeyType::RSA,
+        n: URL_SAFE_NO_PAD.encode(n),
+        e: URL_SAFE_NO_PAD.encode(e),
+    })
+}
+
+/// Read the modulus and exponent from a PKCS#1 `RSAPublicKey`.
+fn rsa_pkcs1_params(der: &[u8]) -> Result<AlgorithmParameters, String> {
+    let invalid = || String::from("Invalid RSA public key");
+    match from_der(der).map_err(|_| invalid())?.as_slice() {
+        [ASN1Block::Sequence(_, fields)] => match fields.as_slice() {
+            [ASN1Block::Integer(_, n), ASN1Block::Integer(_, e)] => Ok(rsa_components_params(
+                &n.to_bytes_be().1,
+                &e.to_bytes_be().1,
+            )),
+            _ => Err(invalid()),
+        },
+        _ => Err(invalid()),
+    }
+}
+
+/// Read the modulus and exponent from an RSA `SubjectPublicKeyInfo`.
+fn rsa_spki_params(der: &[u8]) -> Result<AlgorithmParameters, String> {
+    let invalid = || String::from("Unsupported public key");
+    match from_der(der).map_err(|_| invalid())?.as_slice() {
+        [ASN1Block::Sequence(_, fields)] => match fields.as_slice() {
+            [ASN1Block::Sequence(_, _), ASN1Block::BitString(_, _, key)] => rsa_pkcs1_params(key),
+            _ => Err(invalid()),
+        },
+        _ => Err(invalid()),
+    }
+}
+
+/// Calculate the RFC 7638 thumbprint of a public key.
+fn thumbprint(params: &AlgorithmParameters) -> String {
+    // The required members, in lexicographic order, with no whitespace.
+    let canonical = match params {
+        AlgorithmParameters::OctetKeyPair(p) => {
+            format!(r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#, p.x)
+        }
+        AlgorithmParameters::RSA(p) => format!(r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#, p.e, p.n),
+        _ => unreachable!("Only RSA and Ed25519 keys are supported"),
+    };
+    URL_SAFE_NO_PAD.encode(Sha256::digest(canonical.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::rsa_key_pair;
+
+    /// The Ed25519 key from RFC 8037, appendix A.1, as PKCS#8.
+    fn rfc8037_key() -> Vec<u8> {
+        let d = URL_SAFE_NO_PAD
+            .decode("nWGxne_9WmC6hEr0kuwsxERJxWl7MmkZcDusAxyuf2A")
+            .unwrap();
+        let mut der = vec![
+            0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22,
+            0x04, 0x20,
+        ];
+        der.extend(d);
+        pem::encode(&Pem::new("PRIVATE KEY", der)).into_bytes()
+    }
+
+    #[test]
+    fn test_ed25519_key() {
+        let key = Key::from_pem(&rfc8037_key(), None).unwrap();
+        assert_eq!(key.algorithm, Algorithm::EdDSA);
+        assert!(key.encoding.is_some());
+        // The thumbprint given in RFC 8037, appendix A.3.
+        assert_eq!(key.kid, "kPrK_qmxVWaYVA9wwBF6Iuo3vVzz7TxHCTwXBygrS4k");
+        match key.jwk.algorithm {
+            AlgorithmParameters::OctetKeyPair(p) => {
+                assert_eq!(p.x, "11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo")
+            }
+            _ => panic!("Expected an octet key pair"),
+        }
+    }
+
+    #[test]
+    fn test_ed25519_public_key() {
+        let mut der = ED25519_SPKI_PREFIX.to_vec();
+        der.extend(
+            URL_SAFE_NO_PAD
+                .decode("11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo")
+                .unwrap(),
+        );
+        let pem = pem::encode(&Pem::new("PUBLIC KEY", der));
+        let key = Key::from_pem(pem.as_bytes(), None).unwrap();
+        assert!(key.encoding.is_none());
+        assert_eq!(key.kid, "kPrK_qmxVWaYVA9wwBF6Iuo3vVzz7TxHCTwXBygrS4k");
+    }
+
+    #[test]
+    fn test_rsa_key() {
+        let (pem, _) = rsa_key_pair();
+        let key = Key::from_pem(pem.as_bytes(), Some(String::from("rsa-1"))).unwrap();
+        assert_eq!(key.algorithm, Algorithm::RS256);
+        assert_eq!(key.kid, "rsa-1");
+        assert_eq!(key.jwk.common.key_id.as_deref(), Some("rsa-1"));
+        match key.jwk.algorithm {
+            AlgorithmParameters::RSA(p) => assert_eq!(p.e, "AQAB"),
+            _ => panic!("Expected an RSA key"),
+        }
+    }
+
+    #[test]
+    fn test_rsa_public_key() {
+        let (private, public) = rsa_key_pair();
+        let private = Key::from_pem(private.as_bytes(), None);
+        let public = Key::from_pem(public.as_bytes(), None);
+        let public = public.unwrap();
+        assert!(public.encoding.is_none());
+        assert_eq!(public.algorithm, Algorithm::RS256);
+        assert_eq!(public.kid, private.unwrap().kid);
+    }
+
+    #[test]
+    fn test_invalid_key() {
+        assert!(Key::from_pem(b"not a key", None).is_err());
+        let cert = pem::encode(&Pem::new("CERTIFICATE", vec![0u8; 8]));
+        assert!(Key::from_pem(cert.as_bytes(), None).is_err());
+    }
+}
@@ -0,0 +1,130 @@
+//! This module decides when an account is locked after repeated failed logins.
+//!
+//! # Notes
+//!
+//! - Once an account has `threshold` consecutive failed logins, it is locked for `base`. Every
+//!   further failure doubles the lock, up to `max`. A successful login resets the count.
+//! - Failures are counted against the player who has the username, however its case was typed.
+//!   Usernames which no player has are locked all the same (ignoring case), so that a lock does not
+//!   reveal which usernames exist.
+//!
+//! # Environment
+//!
+//! * `LOGIN_LOCKOUT_THRESHOLD` - (Optional) How many consecutive failures lock an account.
+//!   Defaults to 5.
+//! * `LOGIN_LOCKOUT_BASE_SECONDS` - (Optional) How long the first lock lasts. Defaults to 60.
+//! * `LOGIN_LOCKOUT_MAX_SECONDS` - (Optional) The longest a lock can last. Defaults to 3600.
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::config::from_env;
+
+/// When (and for how long) accounts are locked.
+pub struct LockoutPolicy {
+    pub threshold: i64,
+    pub base: Duration,
+    pub max: Duration,
+}
+
+impl LockoutPolicy {
+    /// Load the policy from the environment.
+    pub fn from_env() -> Self {
+        LockoutPolicy {
+            threshold: from_env("LOGIN_LOCKOUT_THRESHOLD", 5).max(1),
+            base: Duration::seconds(from_env("LOGIN_LOCKOUT_BASE_SECONDS", 60)),
+            max: Duration::seconds(from_env("LOGIN_LOCKOUT_MAX_SECONDS", 3600)),
+        }
+    }
+
+    /// How long an account is locked for after a number of consecutive failures.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(Duration)` if the failures lock the account.
+    /// * `None` if they do not.
+    pub fn lock_duration(&self, failures: i64) -> Option<Duration> {
+        if failures < self.threshold {
+            return None;
+        }
+        // Past 2^20, the lock is certainly capped, and the multiplication could overflow.
+        let doublings = (failures - self.threshold).min(20) as i32;
+        Some((self.base * 2i32.pow(doublings as u32)).min(self.max))
+    }
+
+    /// When an account is unlocked, given its consecutive failures and when the last one was.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(DateTime<Utc>)` if the failures lock the account.
+    /// * `None` if they do not (even if the lock has already expired).
+    pub fn locked_until(
+        &self,
+        failures: i64,
+        last_failure_at: Option<DateTime<Utc>>,
+    ) -> Option<DateTime<Utc>> {
+        Some(last_failure_at? + self.lock_duration(failures)?)
+    }
+
+    /// How long until an account is unlocked.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(i64)` containing the number of seconds (at least 1), if the account is locked now.
+    /// * `None` if it is not.
+    pub fn retry_after(
+        &self,
+        failures: i64,
+        last_failure_at: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Option<i64> {
+        let remaining = self.locked_until(failures, last_failure_at)? - now;
+        if remaining <= Duration::zero() {
+            return None;
+        }
+        // Round up, so that a client waiting this long is never still locked out.
+        Some((remaining + Duration::milliseconds(999)).num_seconds())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> LockoutPolicy {
+        LockoutPolicy {
+            threshold: 3,
+            base: Duration::seconds(60),
+            max: Duration::seconds(600),
+        }
+    }
+
+    #[test]
+    fn test_lock_duration() {
+        let policy = policy();
+        assert_eq!(policy.lock_duration(0), None);
+        assert_eq!(policy.lock_duration(2), None);
+        assert_eq!(policy.lock_duration(3), Some(Duration::seconds(60)));
+        assert_eq!(policy.lock_duration(4), Some(Duration::seconds(120)));
+        assert_eq!(policy.lock_duration(6), Some(Duration::seconds(480)));
+        assert_eq!(policy.lock_duration(7), Some(Duration::seconds(600)));
+        assert_eq!(policy.lock_duration(i64::MAX), Some(Duration::seconds(600)));
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let policy = policy();
+        let now = Utc::now();
+        let ago = |seconds| Some(now - Duration::seconds(seconds));
+
+        assert_eq!(policy.retry_after(2, ago(0), now), None);
+        assert_eq!(policy.retry_after(3, None, now), None);
+        assert_eq!(policy.retry_after(3, ago(0), now), Some(60));
+        assert_eq!(policy.retry_after(3, ago(59), now), Some(1));
+        assert_eq!(policy.retry_after(3, ago(60), now), None);
+        assert_eq!(policy.retry_after(4, ago(60), now), Some(60));
+        assert_eq!(
+            policy.retry_after(3, Some(now - Duration::milliseconds(59_500)), now),
+            Some(1)
+        );
+    }
+}
@@ -0,0 +1,194 @@
+//! This module sends emails to players, such as password reset links.
+//!
+//! The backend is chosen by the `MAILER` environment variable:
+//!
+//! * `stdout` (default) - Print each email to standard output. Useful for local testing.
+//! * `file` - Append each email to the file at `MAILER_FILE` (default `mail.log`).
+//! * `http` - POST each email as JSON to `MAILER_URL` (such as a transactional email provider's
+//!   relay), authenticating with the bearer token in `MAILER_API_KEY` if it is set.
+//!
+//! Emails are sent from `MAIL_FROM`, and links in emails point to the frontend at `FRONTEND_URL`.
+
+use std::{
+    env,
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    sync::{Mutex, OnceLock},
+};
+
+use axum::async_trait;
+use reqwest::Client;
+use serde::Serialize;
+
+/// A plain text email.
+#[derive(Serialize)]
+pub struct Email {
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Email {
+    /// Create a new email, sent from the `MAIL_FROM` address.
+    pub fn new(to: &str, subject: &str, body: String) -> Self {
+        Email {
+            from: env::var("MAIL_FROM")
+                .unwrap_or_else(|_| String::from("no-reply@bitcasino.bigdevdog.com")),
+            to: String::from(to),
+            subject: String::from(subject),
+            body,
+        }
+    }
+
+    fn to_text(&self) -> String {
+        format!(
+            "From: {}\nTo: {}\nSubject: {}\n\n{}\n\n",
+            self.from, self.to, self.subject, self.body
+        )
+    }
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    /// Send an email.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` if the email was handed off successfully.
+    /// * `Err(String)` describing why it was not.
+    async fn send(&self, email: &Email) -> Result<(), String>;
+}
+
+pub struct StdoutMailer;
+
+#[async_trait]
+impl Mailer for StdoutMailer {
+    async fn send(&self, email: &Email) -> Result<(), String> {
+        print!("{}", email.to_text());
+        Ok(())
+    }
+}
+
+pub struct FileMailer {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl FileMailer {
+    pub fn new(path: PathBuf) -> Self {
+        FileMailer {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: &Email) -> Result<(), String> {
+        let _guard = self.lock.lock().map_err(|e| e.to_string())?;
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(email.to_text().as_bytes()))
+            .map_err(|e| e.to_string())
+    }
+}
+
+pub struct HttpMailer {
+    client: Client,
+    url: String,
+    api_key: Option<String>,
+}
+
+#[async_trait]
+impl Mailer for HttpMailer {
+    async fn send(&self, email: &Email) -> Result<(), String> {
+        let mut request = self.client.post(&self.url).json(email);
+        if let Some(key) = &self.api_key {
+            request = request.bearer_auth(key);
+        }
+        match request.send().await {
+            Ok(response) if response.status().is_success() => Ok(()),
+            Ok(response) => Err(format!("Mail relay responded with {}", response.status())),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+static MAILER: OnceLock<Box<dyn Mailer>> = OnceLock::new();
+
+/// Get the mailer configured by the environment.
+///
+/// # Errors
+///
+/// Panics if `MAILER` names an unknown backend, or the backend is missing its configuration.
+pub fn mailer() -> &'static dyn Mailer {
+    MAILER
+        .get_or_init(|| match env::var("MAILER").as_deref() {
+            Err(_) | Ok("stdout") => Box::new(StdoutMailer),
+            Ok("file") => Box::new(FileMailer::new(PathBuf::from(
+                env::var("MAILER_FILE").unwrap_or_else(|_| String::from("mail.log")),
+            ))),
+            Ok("http") => Box::new(HttpMailer {
+                client: Client::new(),
+                url: env::var("MAILER_URL")
+                    .expect("Environment is not set up properly; missing 'MAILER_URL'"),
+                api_key: env::var("MAILER_API_KEY").ok(),
+            }),
+            Ok(other) => panic!("Unknown MAILER '{}'", other),
+        })
+        .as_ref()
+}
+
+/// Send an email in the background, so that the response to the client does not wait for (or
+/// reveal anything about) its delivery. Failures are logged.
+pub fn send_in_background(email: Email) {
+    tokio::spawn(async move {
+        if let Err(e) = mailer().send(&email).await {
+            eprintln!("Failed to send email to {}: {}", email.to, e);
+        }
+    });
+}
+
+/// Build a link to a page on the frontend which carries a token.
+///
+/// # Arguments
+///
+/// * `path` - The path of the page, such as `/password/reset`.
+/// * `token` - The token to include in the link's query string.
+pub fn frontend_link(path: &str, token: &str) -> String {
+    let base = env::var("FRONTEND_URL").unwrap_or_else(|_| String::from("http://localhost:60000"));
+    format!("{}{}?token={}", base.trim_end_matches('/'), path, token)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_file_mailer() {
+        let path = env::temp_dir().join(format!("mail-{}.log", Uuid::new_v4()));
+        let mailer = FileMailer::new(path.clone());
+        let email = |subject: &str| Email {
+            from: String::from("no-reply@bitcasino.bigdevdog.com"),
+            to: String::from("b1gd3vd0g@bigdevdog.com"),
+            subject: String::from(subject),
+            body: String::from("Hello!"),
+        };
+        mailer.send(&email("First")).await.unwrap();
+        mailer.send(&email("Second")).await.unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.contains("To: b1gd3vd0g@bigdevdog.com"));
+        assert!(contents.find("Subject: First") < contents.find("Subject: Second"));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,18 +1,34 @@
+mod account_deletion;
+mod backoff;
+mod cleanup;
+mod config;
 mod db;
 mod handlers;
 mod hashing;
 mod jwt;
+mod lockout;
+mod mailer;
+mod outbox;
+mod password_strength;
+mod rate_limit;
+mod recovery_codes;
 mod requests;
 mod router;
+#[cfg(test)]
 mod test_utils;
+mod tokens;
+mod totp;
 mod validators;
+mod wallet_reconciliation;
+mod webauthn;
+mod webhooks;
 
-use std::{env, net::SocketAddr};
+use std::{env, net::SocketAddr, sync::Arc};
 
 use dotenv::dotenv;
 use tokio::net::TcpListener;
 
-use crate::router::router;
+use crate::{rate_limit::RateLimiter, router::router};
 
 #[tokio::main]
 async fn main() {
@@ -29,11 +45,31 @@ async fn main() {
         } // Otherwise, the env should be set elsewhere (such as in the docker-compose.yaml file)
     }
 
+    // Hash the dummy password now, rather than during the first login to a missing username. This also
+    // checks the Argon2 parameters and peppers in the environment.
+    hashing::verify_password_or_dummy("", None);
+    // Load the JWT key ring now, so that a misconfigured deployment fails at startup rather than on the
+    // first authenticated request.
+    jwt::key_ring();
+    // Load the breached password list now, rather than during the first registration.
+    password_strength::breached_passwords();
+
     let db_pool = db::connect().await;
-    let app = router().with_state(db_pool);
+    account_deletion::spawn_purge_job(db_pool.clone());
+    cleanup::spawn_cleanup_job(db_pool.clone());
+    wallet_reconciliation::spawn_reconcile_job(db_pool.clone());
+    outbox::spawn_relay_job(db_pool.clone());
+    webhooks::spawn_delivery_job(db_pool.clone());
+    let rate_limiter = Arc::new(RateLimiter::from_env(db_pool.clone()));
+    let app = router(rate_limiter).with_state(db_pool);
 
     let address = SocketAddr::from(([0, 0, 0, 0], 3000));
     let listener = TcpListener::bind(address).await.unwrap();
-    println!("Listening on {}", address.to_string());
-    axum::serve(listener, app).await.unwrap();
+    println!("Listening on {}", address);
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
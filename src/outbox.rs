@@ -0,0 +1,232 @@
+//! This module relays events about players to other services, through a transactional outbox.
+//!
+//! # Notes
+//!
+//! - Every change to a player is written to the `outbox` table in the same transaction as the change
+//!   itself (see `db::queries::outbox`), so an event is recorded if and only if the change is. The
+//!   events are `player.created`, `player.updated`, `player.deleted` and `player.login`.
+//! - A background job relays the events to the sink configured by the environment (see `sinks`), in
+//!   the order they were recorded. An event is only marked delivered once the sink has accepted it,
+//!   so every event is delivered **at least once**; consumers should ignore ids they have already
+//!   seen. If the sink fails, the job waits (with exponential backoff) before trying again, and no
+//!   later event overtakes it. An event which fails too many times is marked dead (its `dead_at` is
+//!   set) and skipped, so that it does not hold back the rest.
+//! - When the service runs as several replicas, only one relays events at a time, holding a
+//!   Postgres advisory lock while it relays each event. The sink is given 10 seconds to accept an
+//!   event, so the lock (and its connection) is never held for long.
+//! - Delivered events are kept for a while, and then deleted. Dead events are kept.
+//!
+//! # Environment
+//!
+//! * `OUTBOX_POLL_INTERVAL_SECONDS` - (Optional) How often new events are relayed. Defaults to 1.
+//! * `OUTBOX_RETRY_SECONDS` - (Optional) How long to wait before trying the sink again after its
+//!   first failure. The wait doubles after each failure, up to 10 minutes. Defaults to 5.
+//! * `OUTBOX_MAX_ATTEMPTS` - (Optional) How many times an event is relayed before it is marked dead.
+//!   Defaults to 20.
+//! * `OUTBOX_RETENTION_DAYS` - (Optional) How long delivered events are kept. Defaults to 7.
+
+pub mod sinks;
+
+use std::time::Duration as StdDuration;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use tokio::time::timeout;
+use uuid::Uuid;
+
+use crate::{
+    backoff::retry_delay,
+    config::from_env,
+    db::{
+        models::OutboxEvent,
+        queries::outbox::{
+            delete_delivered_events, fail_event_delivery, get_next_undelivered_event,
+            lock_event_relay, mark_event_delivered,
+        },
+    },
+    outbox::sinks::{event_sink, EventSink},
+};
+
+/// The most events relayed in one run.
+const BATCH_SIZE: i64 = 100;
+
+/// The longest wait before trying the sink again.
+const MAX_RETRY_DELAY_SECONDS: i64 = 10 * 60;
+
+/// How long the sink has to accept an event.
+const PUBLISH_TIMEOUT: StdDuration = StdDuration::from_secs(10);
+
+/// An event, as it is sent to the sink.
+#[derive(Serialize)]
+pub struct EventMessage {
+    /// Unique to the event, so consumers can ignore events delivered more than once.
+    pub id: Uuid,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub player_id: Uuid,
+    pub occurred_at: DateTime<Utc>,
+    pub data: serde_json::Value,
+}
+
+impl From<OutboxEvent> for EventMessage {
+    fn from(event: OutboxEvent) -> Self {
+        EventMessage {
+            id: event.id,
+            event_type: event.event_type,
+            player_id: event.player_id,
+            occurred_at: event.created_at,
+            data: event.payload,
+        }
+    }
+}
+
+/// Relay the undelivered events to a sink, in order, stopping at the first failure or at an event
+/// which is waiting to be retried. An event which has failed too many times is marked dead, and
+/// the events after it are relayed.
+///
+/// Only one replica relays events at a time; the others return straight away. Each event is
+/// relayed in a transaction of its own, so a slow sink holds no more than one connection, for no
+/// longer than `PUBLISH_TIMEOUT`. If the relay stops after the sink has accepted an event, but
+/// before the transaction commits, the event is relayed again.
+///
+/// # Returns
+///
+/// The number of events which were delivered.
+pub async fn relay_events(pool: &PgPool, sink: &dyn EventSink) -> Result<usize, sqlx::Error> {
+    let mut delivered = 0;
+    for _ in 0..BATCH_SIZE {
+        let mut tx = pool.begin().await?;
+        if !lock_event_relay(&mut tx).await? {
+            break;
+        }
+        let event = match get_next_undelivered_event(&mut tx).await? {
+            Some(event) => event,
+            None => break,
+        };
+
+        let (id, attempts) = (event.id, event.attempts + 1);
+        let outcome = match timeout(PUBLISH_TIMEOUT, sink.publish(&EventMessage::from(event))).await
+        {
+            Ok(outcome) => outcome,
+            Err(_) => Err(String::from("Event sink timed out")),
+        };
+        let retrying = match outcome {
+            Ok(()) => {
+                mark_event_delivered(&mut tx, id).await?;
+                delivered += 1;
+                false
+            }
+            Err(error) => {
+                eprintln!(
+                    "Failed to relay event {} (attempt {}): {}",
+                    id, attempts, error
+                );
+                let retry_at = (attempts < from_env("OUTBOX_MAX_ATTEMPTS", 20)).then(|| {
+                    Utc::now()
+                        + retry_delay(
+                            attempts,
+                            from_env("OUTBOX_RETRY_SECONDS", 5),
+                            MAX_RETRY_DELAY_SECONDS,
+                        )
+                });
+                fail_event_delivery(&mut tx, id, &error, retry_at).await?;
+                if retry_at.is_none() {
+                    eprintln!("Gave up relaying event {} after {} attempts", id, attempts);
+                }
+                retry_at.is_some()
+            }
+        };
+        tx.commit().await?;
+        if retrying {
+            break;
+        }
+    }
+    Ok(delivered)
+}
+
+/// Start the background job which relays events to the sink configured by the environment, and
+/// deletes old delivered events.
+///
+/// # Errors
+///
+/// Panics straight away if the sink is not configured properly.
+pub fn spawn_relay_job(pool: PgPool) {
+    let sink = event_sink();
+    let interval = from_env("OUTBOX_POLL_INTERVAL_SECONDS", 1).max(1) as u64;
+    let retention = Duration::days(from_env("OUTBOX_RETENTION_DAYS", 7));
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(StdDuration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            if let Err(e) = relay_events(&pool, sink).await {
+                eprintln!("Failed to relay events: {}", e);
+            }
+            if let Err(e) = delete_delivered_events(&pool, Utc::now() - retention).await {
+                eprintln!("Failed to delete delivered events: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use axum::async_trait;
+
+    use super::*;
+    use crate::test_utils::create_test_player;
+
+    /// A sink which accepts events while `accepting` is set, and remembers their types.
+    struct TestSink {
+        accepting: Mutex<bool>,
+        published: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl EventSink for TestSink {
+        async fn publish(&self, message: &EventMessage) -> Result<(), String> {
+            if !*self.accepting.lock().unwrap() {
+                return Err(String::from("Sink is down"));
+            }
+            self.published
+                .lock()
+                .unwrap()
+                .push(message.event_type.clone());
+            Ok(())
+        }
+    }
+
+    #[sqlx::test]
+    async fn test_relay_events(pool: PgPool) {
+        let sink = TestSink {
+            accepting: Mutex::new(false),
+            published: Mutex::new(vec![]),
+        };
+        create_test_player(&pool, "b1gd3vd0g", "CorrectHorse!42").await;
+        create_test_player(&pool, "l1ttl3d0g", "CorrectHorse!42").await;
+
+        // The first event fails, and the second waits behind it.
+        assert_eq!(relay_events(&pool, &sink).await.unwrap(), 0);
+        assert_eq!(relay_events(&pool, &sink).await.unwrap(), 0);
+        let attempts: Vec<i32> =
+            sqlx::query_scalar("SELECT attempts FROM outbox ORDER BY sequence")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(attempts, vec![1, 0]);
+
+        *sink.accepting.lock().unwrap() = true;
+        sqlx::query("UPDATE outbox SET next_attempt_at = now()")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(relay_events(&pool, &sink).await.unwrap(), 2);
+        assert_eq!(
+            *sink.published.lock().unwrap(),
+            vec!["player.created", "player.created"]
+        );
+        assert_eq!(relay_events(&pool, &sink).await.unwrap(), 0);
+    }
+}
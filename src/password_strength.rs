@@ -0,0 +1,543 @@
+//! This module decides whether a password is strong enough to use, beyond the character rules in
+//! `validators::validate_password`.
+//!
+//! # Notes
+//!
+//! - A password's strength is estimated in bits, as the cheapest way to guess it: the password is
+//!   split into patterns an attacker would try first (common words, keyboard walks, sequences,
+//!   repeats, years and the player's own username or email address), and the remaining characters,
+//!   each of which costs a guess from its character class.
+//! - Common words are matched regardless of case, and with common character substitutions (such as
+//!   `p4$5w0rd` for `password`), but those cost a few more bits.
+//! - Passwords which appear in the breached password list (see `breached`) are always rejected.
+//!
+//! # Environment
+//!
+//! * `PASSWORD_MIN_STRENGTH_BITS` - (Optional) The least estimated strength a password must have.
+//!   Defaults to 30.
+
+mod breached;
+
+pub use breached::breached_passwords;
+
+use std::{collections::HashMap, sync::OnceLock};
+
+use crate::config::from_env;
+
+/// Common words and passwords, most common first.
+const COMMON_WORDS: &str = include_str!("password_strength/common_words.txt");
+
+/// The keyboard rows walks are found on, with how far each row is shifted to the right.
+const KEYBOARD_ROWS: [(&str, f64); 4] = [
+    ("`1234567890-=", 0.0),
+    ("qwertyuiop[]\\", 0.5),
+    ("asdfghjkl;'", 0.75),
+    ("zxcvbnm,./", 1.25),
+];
+
+/// The shortest common word, keyboard walk or sequence which counts as a pattern.
+const MIN_PATTERN_LENGTH: usize = 4;
+
+/// A reason a password is rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Weakness {
+    Breached,
+    Username,
+    Email,
+    CommonWord,
+    KeyboardWalk,
+    Sequence,
+    Repeat,
+    Year,
+    TooSimple,
+}
+
+impl Weakness {
+    /// The explanation shown to the player.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Weakness::Breached => "Password has appeared in a data breach.",
+            Weakness::Username => "Password contains the username.",
+            Weakness::Email => "Password contains the email address.",
+            Weakness::CommonWord => "Password contains a common word or password.",
+            Weakness::KeyboardWalk => "Password contains a keyboard pattern, like qwerty.",
+            Weakness::Sequence => "Password contains a sequence, like abcd or 1234.",
+            Weakness::Repeat => "Password contains repeated characters.",
+            Weakness::Year => "Password contains a year.",
+            Weakness::TooSimple => "Password is too easy to guess. Try making it longer.",
+        }
+    }
+}
+
+/// A part of a password which matches a pattern.
+struct Match {
+    start: usize,
+    end: usize,
+    bits: f64,
+    weakness: Weakness,
+}
+
+/// How strong a password is estimated to be.
+pub struct Estimate {
+    pub bits: f64,
+    /// The patterns used to guess the password, in order.
+    pub patterns: Vec<Weakness>,
+}
+
+/// The least estimated strength a password must have, in bits.
+fn min_strength_bits() -> f64 {
+    from_env("PASSWORD_MIN_STRENGTH_BITS", 30.0)
+}
+
+fn common_words() -> &'static HashMap<&'static str, usize> {
+    static WORDS: OnceLock<HashMap<&'static str, usize>> = OnceLock::new();
+    WORDS.get_or_init(|| {
+        let mut words = HashMap::new();
+        for (rank, word) in COMMON_WORDS.lines().map(str::trim).enumerate() {
+            if word.len() >= MIN_PATTERN_LENGTH {
+                words.entry(word).or_insert(rank + 1);
+            }
+        }
+        words
+    })
+}
+
+/// The letter a character is commonly substituted for, if any.
+fn unleet(c: char) -> Option<char> {
+    match c {
+        '4' | '@' => Some('a'),
+        '8' => Some('b'),
+        '3' => Some('e'),
+        '6' => Some('g'),
+        '1' | '!' => Some('i'),
+        '0' => Some('o'),
+        '$' | '5' => Some('s'),
+        '7' | '+' => Some('t'),
+        '2' => Some('z'),
+        _ => None,
+    }
+}
+
+/// How many guesses a character costs, from the size of its character class.
+fn char_bits(c: char) -> f64 {
+    let class_size: f64 = if c.is_ascii_lowercase() || c.is_ascii_uppercase() {
+        26.0
+    } else if c.is_ascii_digit() {
+        10.0
+    } else {
+        33.0
+    };
+    class_size.log2()
+}
+
+fn binomial(n: usize, k: usize) -> f64 {
+    (0..k).fold(1.0, |acc, i| acc * (n - i) as f64 / (i + 1) as f64)
+}
+
+/// The extra bits needed to guess which letters of a word are capitalized.
+fn capitalization_bits(word: &[char]) -> f64 {
+    let upper = word.iter().filter(|c| c.is_uppercase()).count();
+    let lower = word.iter().filter(|c| c.is_lowercase()).count();
+    if upper == 0 {
+        return 0.0;
+    }
+    if lower == 0 || (upper == 1 && word[0].is_uppercase()) {
+        return 1.0;
+    }
+    let ways: f64 = (1..=upper.min(lower))
+        .map(|k| binomial(upper + lower, k))
+        .sum();
+    ways.log2()
+}
+
+/// Find every place a word (lowercase) appears in the password, allowing substitutions.
+///
+/// # Returns
+///
+/// The start of each match, and how many characters were substituted.
+fn find_word(chars: &[char], word: &str) -> Vec<(usize, usize)> {
+    let word: Vec<char> = word.chars().collect();
+    if word.len() > chars.len() {
+        return Vec::new();
+    }
+    (0..=chars.len() - word.len())
+        .filter_map(|start| {
+            let mut substitutions = 0;
+            for (&c, &w) in chars[start..start + word.len()].iter().zip(&word) {
+                if c.to_ascii_lowercase() == w {
+                    continue;
+                }
+                if unleet(c) != Some(w) {
+                    return None;
+                }
+                substitutions += 1;
+            }
+            Some((start, substitutions))
+        })
+        .collect()
+}
+
+fn word_matches(chars: &[char], word: &str, rank: usize, weakness: Weakness) -> Vec<Match> {
+    find_word(chars, word)
+        .into_iter()
+        .map(|(start, substitutions)| {
+            let end = start + word.chars().count();
+            Match {
+                start,
+                end,
+                bits: (rank as f64).log2()
+                    + capitalization_bits(&chars[start..end])
+                    + substitutions as f64,
+                weakness,
+            }
+        })
+        .collect()
+}
+
+/// The parts of a username or email address which a player might put in their password.
+fn personal_words(username: &str, email: &str) -> Vec<(String, Weakness)> {
+    let mut words = vec![(username.to_lowercase(), Weakness::Username)];
+    let local_part = email.split('@').next().unwrap_or_default().to_lowercase();
+    words.extend(
+        local_part
+            .split(['.', '_', '-', '+'])
+            .chain([local_part.as_str()])
+            .map(|part| (part.to_string(), Weakness::Email)),
+    );
+    words.retain(|(word, _)| word.chars().count() >= MIN_PATTERN_LENGTH);
+    words
+}
+
+fn key_position(c: char) -> Option<(f64, f64)> {
+    let unshifted = match c {
+        '~' => '`',
+        '!' => '1',
+        '@' => '2',
+        '#' => '3',
+        '$' => '4',
+        '%' => '5',
+        '^' => '6',
+        '&' => '7',
+        '*' => '8',
+        '(' => '9',
+        ')' => '0',
+        '_' => '-',
+        '+' => '=',
+        c => c.to_ascii_lowercase(),
+    };
+    KEYBOARD_ROWS
+        .iter()
+        .enumerate()
+        .find_map(|(row, (keys, offset))| {
+            let column = keys.chars().position(|key| key == unshifted)?;
+            Some((row as f64, column as f64 + offset))
+        })
+}
+
+/// The direction from one key to a neighbouring key, or `None` if they are not neighbours.
+fn key_step(from: char, to: char) -> Option<(i8, i8)> {
+    let (from_row, from_x) = key_position(from)?;
+    let (to_row, to_x) = key_position(to)?;
+    let (rows, columns) = (to_row - from_row, to_x - from_x);
+    if rows.abs() > 1.0 || columns.abs() > 1.0 || (rows == 0.0 && columns == 0.0) {
+        return None;
+    }
+    Some((rows as i8, columns.signum() as i8))
+}
+
+fn keyboard_walks(chars: &[char]) -> Vec<Match> {
+    let mut matches = Vec::new();
+    let mut start = 0;
+    while start < chars.len() {
+        let mut end = start + 1;
+        let mut turns = 0;
+        let mut direction = None;
+        while end < chars.len() {
+            let step = match key_step(chars[end - 1], chars[end]) {
+                Some(step) => step,
+                None => break,
+            };
+            if direction.is_some_and(|d| d != step) {
+                turns += 1;
+            }
+            direction = Some(step);
+            end += 1;
+        }
+        if end - start >= MIN_PATTERN_LENGTH {
+            let shifted = chars[start..end]
+                .iter()
+                .any(|c| c.is_uppercase() || "~!@#$%^&*()_+".contains(*c));
+            matches.push(Match {
+                start,
+                end,
+                // The starting key, the length, and the direction of each turn.
+                bits: (47.0 * (end - start) as f64).log2()
+                    + turns as f64 * 6f64.log2()
+                    + if shifted { 1.0 } else { 0.0 },
+                weakness: Weakness::KeyboardWalk,
+            });
+        }
+        start = end;
+    }
+    matches
+}
+
+fn sequences(chars: &[char]) -> Vec<Match> {
+    let mut matches = Vec::new();
+    let same_class = |a: char, b: char| {
+        (a.is_ascii_lowercase() && b.is_ascii_lowercase())
+            || (a.is_ascii_uppercase() && b.is_ascii_uppercase())
+            || (a.is_ascii_digit() && b.is_ascii_digit())
+    };
+    let mut start = 0;
+    while start + 1 < chars.len() {
+        let delta = chars[start + 1] as i32 - chars[start] as i32;
+        let mut end = start + 1;
+        while end < chars.len()
+            && same_class(chars[start], chars[end])
+            && chars[end] as i32 - chars[end - 1] as i32 == delta
+        {
+            end += 1;
+        }
+        if end - start >= MIN_PATTERN_LENGTH - 1 && (1..=2).contains(&delta.abs()) {
+            let first = chars[start];
+            let starts: f64 = if "aAzZ019".contains(first) {
+                4.0
+            } else if first.is_ascii_digit() {
+                10.0
+            } else {
+                26.0
+            };
+            matches.push(Match {
+                start,
+                end,
+                bits: (starts * (end - start) as f64).log2()
+                    + if delta < 0 { 1.0 } else { 0.0 }
+                    + if delta.abs() == 2 { 1.0 } else { 0.0 },
+                weakness: Weakness::Sequence,
+            });
+            start = end - 1;
+        } else {
+            start += 1;
+        }
+    }
+    matches
+}
+
+fn repeats(chars: &[char]) -> Vec<Match> {
+    let mut matches = Vec::new();
+    for start in 0..chars.len() {
+        for length in 1..=(chars.len() - start) / 2 {
+            let unit = &chars[start..start + length];
+            let mut count = 1;
+            while start + (count + 1) * length <= chars.len()
+                && &chars[start + count * length..start + (count + 1) * length] == unit
+            {
+                count += 1;
+            }
+            // A single character must be repeated at least three times to count.
+            if count >= 2 && count * length >= 3 {
+                let unit: String = unit.iter().collect();
+                matches.push(Match {
+                    start,
+                    end: start + count * length,
+                    bits: estimate_bits(&unit, &[]) + (count as f64).log2(),
+                    weakness: Weakness::Repeat,
+                });
+            }
+        }
+    }
+    matches
+}
+
+fn years(chars: &[char]) -> Vec<Match> {
+    chars
+        .windows(4)
+        .enumerate()
+        .filter(|(_, window)| {
+            let year: String = window.iter().collect();
+            year.parse::<u32>()
+                .is_ok_and(|year| (1900..2100).contains(&year))
+        })
+        .map(|(start, _)| Match {
+            start,
+            end: start + 4,
+            bits: 200f64.log2(),
+            weakness: Weakness::Year,
+        })
+        .collect()
+}
+
+fn find_matches(chars: &[char], personal: &[(String, Weakness)]) -> Vec<Match> {
+    let mut matches = Vec::new();
+    for (word, weakness) in personal {
+        matches.extend(word_matches(chars, word, 1, *weakness));
+    }
+    for (word, rank) in common_words() {
+        matches.extend(word_matches(chars, word, *rank, Weakness::CommonWord));
+    }
+    matches.extend(keyboard_walks(chars));
+    matches.extend(sequences(chars));
+    matches.extend(years(chars));
+    matches.extend(repeats(chars));
+    matches
+}
+
+/// Split a password into the cheapest sequence of patterns and single characters.
+fn cheapest_guess(password: &str, personal: &[(String, Weakness)]) -> Estimate {
+    let chars: Vec<char> = password.chars().collect();
+    let matches = find_matches(&chars, personal);
+
+    // The cheapest way to guess the first `i` characters, and the match it ends with (if any).
+    let mut best: Vec<(f64, Option<usize>)> = vec![(f64::INFINITY, None); chars.len() + 1];
+    best[0] = (0.0, None);
+    for i in 0..chars.len() {
+        let (bits, _) = best[i];
+        if bits + char_bits(chars[i]) < best[i + 1].0 {
+            best[i + 1] = (bits + char_bits(chars[i]), None);
+        }
+        for (index, m) in matches.iter().enumerate().filter(|(_, m)| m.start == i) {
+            if bits + m.bits < best[m.end].0 {
+                best[m.end] = (bits + m.bits, Some(index));
+            }
+        }
+    }
+
+    let mut patterns = Vec::new();
+    let mut end = chars.len();
+    while end > 0 {
+        match best[end].1 {
+            Some(index) => {
+                patterns.push(matches[index].weakness);
+                end = matches[index].start;
+            }
+            None => end -= 1,
+        }
+    }
+    patterns.reverse();
+    Estimate {
+        bits: best[chars.len()].0,
+        patterns,
+    }
+}
+
+fn estimate_bits(password: &str, personal: &[(String, Weakness)]) -> f64 {
+    cheapest_guess(password, personal).bits
+}
+
+/// Estimate how strong a password is, for a player with the given username and email address.
+pub fn estimate_strength(password: &str, username: &str, email: &str) -> Estimate {
+    cheapest_guess(password, &personal_words(username, email))
+}
+
+/// Check that a password is strong enough, and has not appeared in a data breach.
+///
+/// # Arguments
+///
+/// * `password` - A password which has passed `validators::validate_password`.
+/// * `username` - The username of the player the password is for.
+/// * `email` - The email address of the player the password is for.
+///
+/// # Returns
+///
+/// * `Ok(())` if the password can be used.
+/// * `Err(Vec<Weakness>)` containing every reason it cannot.
+pub fn check_password_strength(
+    password: &str,
+    username: &str,
+    email: &str,
+) -> Result<(), Vec<Weakness>> {
+    let mut weaknesses = Vec::new();
+    if breached_passwords().contains(password) {
+        weaknesses.push(Weakness::Breached);
+    }
+    let estimate = estimate_strength(password, username, email);
+    if estimate.bits < min_strength_bits() {
+        if estimate.patterns.is_empty() {
+            weaknesses.push(Weakness::TooSimple);
+        }
+        weaknesses.extend(estimate.patterns);
+    }
+    if weaknesses.is_empty() {
+        return Ok(());
+    }
+    weaknesses.sort();
+    weaknesses.dedup();
+    Err(weaknesses)
+}
+
+/// The reasons a password was rejected, as shown to the player.
+pub fn reasons(weaknesses: &[Weakness]) -> Vec<String> {
+    weaknesses
+        .iter()
+        .map(|weakness| weakness.reason().to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(password: &str) -> Result<(), Vec<Weakness>> {
+        check_password_strength(password, "b1gd3vd0g", "devin.white@example.com")
+    }
+
+    #[test]
+    fn test_strong_passwords() {
+        for password in [
+            "Buffy!53",
+            "#redDOG77",
+            "J0EY&&phoebe",
+            "Tr0ub4dor&3",
+            "kT9#mW2$vL",
+        ] {
+            assert_eq!(check(password), Ok(()), "{}", password);
+        }
+    }
+
+    #[test]
+    fn test_weak_passwords() {
+        for (password, weakness) in [
+            ("Password1!", Weakness::CommonWord),
+            ("p4$5w0Rd", Weakness::CommonWord),
+            ("Jackpot7!", Weakness::CommonWord),
+            ("Poiuytr5!", Weakness::KeyboardWalk),
+            ("1qaz@WSX", Weakness::KeyboardWalk),
+            ("Abcdef1!", Weakness::Sequence),
+            ("Zz98765!", Weakness::Sequence),
+            ("Aaaaaaa1!", Weakness::Repeat),
+            ("Ab1!Ab1!Ab1!", Weakness::Repeat),
+            ("Summer2024!", Weakness::Year),
+            ("B1gd3vd0g!", Weakness::Username),
+            ("Devin#White9", Weakness::Email),
+        ] {
+            match check(password) {
+                Ok(()) => panic!("{} was accepted", password),
+                Err(weaknesses) => assert!(
+                    weaknesses.contains(&weakness),
+                    "{}: {:?}",
+                    password,
+                    weaknesses
+                ),
+            }
+        }
+        assert_eq!(check("x1!Q"), Err(vec![Weakness::TooSimple]));
+    }
+
+    #[test]
+    fn test_estimate_strength() {
+        let estimate = estimate_strength("Password1!", "", "");
+        assert_eq!(estimate.patterns, vec![Weakness::CommonWord]);
+        // Two characters outside the word, and the capital letter.
+        assert!(estimate.bits < 10.0, "{}", estimate.bits);
+
+        // Substitutions and unusual capitalization make a word slightly harder to guess.
+        assert!(
+            estimate_strength("p4$5w0Rd", "", "").bits > estimate_strength("password", "", "").bits
+        );
+
+        let random = estimate_strength("kT9#mW2$vL", "", "");
+        assert!(random.patterns.is_empty());
+        assert!(random.bits > 40.0, "{}", random.bits);
+    }
+}
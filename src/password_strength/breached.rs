@@ -0,0 +1,148 @@
+//! This module loads a list of passwords which have appeared in data breaches, so that players
+//! cannot choose them. The list is checked offline, so passwords never leave the service.
+//!
+//! # Notes
+//!
+//! - The list is a file of SHA-1 hashes of the passwords, one per line in hex, optionally followed by
+//!   `:count` (how many times the password was seen). This is the format of the Pwned Passwords
+//!   downloads.
+//! - Only the first 8 bytes of each hash are kept in memory. With even a billion hashes, the chance
+//!   of a password being rejected by mistake is around one in eighteen billion.
+//!
+//! # Environment
+//!
+//! * `BREACHED_PASSWORDS_FILE` - (Optional) The path of the list. Without it, no passwords are
+//!   considered breached.
+//! * `BREACHED_PASSWORDS_MIN_COUNT` - (Optional) Ignore passwords seen fewer times than this, to
+//!   keep the list small. Defaults to 1.
+
+use std::{
+    env,
+    fs::File,
+    io::{BufRead, BufReader},
+    sync::OnceLock,
+};
+
+use ring::digest::{digest, SHA1_FOR_LEGACY_USE_ONLY};
+
+use crate::config::from_env;
+
+/// The breached passwords, as sorted hash prefixes.
+pub struct BreachedPasswords {
+    prefixes: Vec<u64>,
+}
+
+fn prefix(hash: &[u8]) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&hash[..8]);
+    u64::from_be_bytes(bytes)
+}
+
+impl BreachedPasswords {
+    /// Parse a list of hashes.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The lines of the list.
+    /// * `min_count` - Lines with a lower count are skipped. Lines without a count are always kept.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(BreachedPasswords)` if every line is valid.
+    /// * `Err(String)` describing the first invalid line, if not.
+    fn parse(reader: impl BufRead, min_count: u64) -> Result<Self, String> {
+        let mut prefixes = Vec::new();
+        for (number, line) in reader.lines().enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || {
+                format!(
+                    "line {} of the breached password list is invalid",
+                    number + 1
+                )
+            };
+            let (hash, count) = match line.split_once(':') {
+                Some((hash, count)) => (hash, Some(count.parse::<u64>().map_err(|_| invalid())?)),
+                None => (line, None),
+            };
+            if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(invalid());
+            }
+            if count.is_some_and(|count| count < min_count) {
+                continue;
+            }
+            prefixes.push(u64::from_str_radix(&hash[..16], 16).map_err(|_| invalid())?);
+        }
+        prefixes.sort_unstable();
+        prefixes.dedup();
+        Ok(BreachedPasswords { prefixes })
+    }
+
+    /// Check whether a password is on the list.
+    pub fn contains(&self, password: &str) -> bool {
+        let hash = digest(&SHA1_FOR_LEGACY_USE_ONLY, password.as_bytes());
+        self.prefixes.binary_search(&prefix(hash.as_ref())).is_ok()
+    }
+}
+
+/// The breached passwords from the file in the environment. It is read the first time this is
+/// called, so call it at startup.
+///
+/// # Errors
+///
+/// Panics if the file cannot be read, or is not valid.
+pub fn breached_passwords() -> &'static BreachedPasswords {
+    static BREACHED: OnceLock<BreachedPasswords> = OnceLock::new();
+    BREACHED.get_or_init(|| {
+        let path = match env::var("BREACHED_PASSWORDS_FILE") {
+            Ok(path) => path,
+            Err(_) => {
+                return BreachedPasswords {
+                    prefixes: Vec::new(),
+                }
+            }
+        };
+        let min_count = from_env("BREACHED_PASSWORDS_MIN_COUNT", 1);
+        File::open(&path)
+            .map_err(|e| format!("{}: {}", path, e))
+            .and_then(|file| BreachedPasswords::parse(BufReader::new(file), min_count))
+            .unwrap_or_else(|e| panic!("Environment is not set up properly; {}", e))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        // The SHA-1 hashes of "password", "hunter2" and "123456".
+        let list = "\
+            5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824\n\
+            \n\
+            f3bbbd66a63d4bf1747940578ec3d0103530e21d\n\
+            7C4A8D09CA3762AF61E59520943DC26494F8941B:3\n";
+        let breached = BreachedPasswords::parse(list.as_bytes(), 1).unwrap();
+        assert_eq!(breached.prefixes.len(), 3);
+        assert!(breached.contains("password"));
+        assert!(breached.contains("hunter2"));
+        assert!(!breached.contains("Password"));
+
+        // "123456" was seen too few times to be kept.
+        let breached = BreachedPasswords::parse(list.as_bytes(), 10).unwrap();
+        assert_eq!(breached.prefixes.len(), 2);
+        assert!(breached.contains("password"));
+        assert!(!breached.contains("123456"));
+
+        for invalid in [
+            "password",
+            "5BAA61E4:1",
+            "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:lots",
+        ] {
+            assert!(BreachedPasswords::parse(invalid.as_bytes(), 1).is_err());
+        }
+    }
+}
@@ -0,0 +1,480 @@
+//! This module limits how quickly clients can make requests, so that bots cannot hammer
+//! registration and login.
+//!
+//! # Notes
+//!
+//! - Limits are token buckets. A limit of `10/60` allows a burst of 10 requests, and refills at 10
+//!   requests every 60 seconds.
+//! - Every request is limited by client IP. Login requests are also limited by the username being
+//!   logged in to, so that a bot spread across many IPs cannot focus on one account.
+//! - If the store cannot be reached, requests are allowed (and the error is logged), so that an
+//!   outage of the store does not become an outage of the service.
+//!
+//! # Environment
+//!
+//! * `RATE_LIMIT_BACKEND` - (Optional) Where buckets are stored: `memory` (default), or `postgres`
+//!   to share them between every replica of the service.
+//! * `RATE_LIMIT_IP` - (Optional) The limit for each client IP. Defaults to `120/60`.
+//! * `RATE_LIMIT_USERNAME` - (Optional) The limit for each username logged in to. Defaults to
+//!   `10/60`.
+//! * `TRUSTED_PROXY_HOPS` - (Optional) How many reverse proxies in front of the service append to
+//!   `X-Forwarded-For`. Defaults to 0, which ignores the header.
+//!
+//! Either limit can be set to `off` to disable it.
+
+mod middleware;
+
+pub use middleware::rate_limit;
+
+use std::{
+    collections::HashMap,
+    env,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use axum::{async_trait, http::HeaderMap};
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgPool;
+
+use crate::{
+    config::from_env,
+    db::queries::rate_limit_buckets::{delete_idle_rate_limit_buckets, update_rate_limit_bucket},
+};
+
+/// How long a bucket can go unused before the postgres store forgets it. Every sensible limit has
+/// refilled by then.
+const IDLE_BUCKET_LIFETIME_HOURS: i64 = 24;
+/// How often the memory store forgets buckets which have refilled.
+const MEMORY_STORE_PRUNE_INTERVAL_SECONDS: i64 = 60;
+/// How many requests the postgres store handles between deleting idle buckets.
+const POSTGRES_STORE_PRUNE_INTERVAL: u64 = 1_000;
+
+/// A token bucket limit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limit {
+    /// The most requests which can be made at once.
+    pub capacity: f64,
+    /// How many requests are regained every second.
+    pub per_second: f64,
+}
+
+impl Limit {
+    /// Parse a limit written as `requests/seconds`.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(Limit)` if the limit is valid.
+    /// * `None` if it is `off`, or not a valid limit.
+    pub fn parse(value: &str) -> Option<Self> {
+        let (requests, seconds) = value.trim().split_once('/')?;
+        let requests: f64 = requests.trim().parse().ok()?;
+        let seconds: f64 = seconds.trim().parse().ok()?;
+        if !(requests >= 1.0 && seconds > 0.0) {
+            return None;
+        }
+        Some(Limit {
+            capacity: requests,
+            per_second: requests / seconds,
+        })
+    }
+
+    fn from_env(key: &str, default: &str) -> Option<Self> {
+        Limit::parse(&env::var(key).unwrap_or_else(|_| String::from(default)))
+    }
+
+    /// Take a token from a bucket for one request.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket` - The bucket, or `None` if it has not been used before (or has been forgotten).
+    /// * `now` - The current time.
+    ///
+    /// # Returns
+    ///
+    /// The bucket's new state, and whether the request is allowed: `Ok(())` if it is, or `Err(i64)`
+    /// containing how many seconds (at least 1) until it would be.
+    pub fn take(&self, bucket: Option<Bucket>, now: DateTime<Utc>) -> (Bucket, Result<(), i64>) {
+        let tokens = match bucket {
+            Some(bucket) => {
+                let elapsed = (now - bucket.updated_at).num_milliseconds().max(0) as f64 / 1000.0;
+                (bucket.tokens + elapsed * self.per_second).min(self.capacity)
+            }
+            None => self.capacity,
+        };
+        if tokens >= 1.0 {
+            let bucket = Bucket {
+                tokens: tokens - 1.0,
+                updated_at: now,
+            };
+            return (bucket, Ok(()));
+        }
+        // Ignore rounding errors, so that a whole number of seconds is not rounded up past itself.
+        let wait = ((1.0 - tokens) / self.per_second - 1e-6).ceil().max(1.0) as i64;
+        let bucket = Bucket {
+            tokens,
+            updated_at: now,
+        };
+        (bucket, Err(wait))
+    }
+
+    /// When a bucket will have refilled, after which it is no different from a new bucket.
+    pub fn full_at(&self, bucket: &Bucket) -> DateTime<Utc> {
+        let seconds = (self.capacity - bucket.tokens).max(0.0) / self.per_second;
+        bucket.updated_at + Duration::milliseconds((seconds * 1000.0).ceil() as i64)
+    }
+}
+
+/// The state of one token bucket.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bucket {
+    pub tokens: f64,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Find the IP address of the client which made a request.
+///
+/// # Arguments
+///
+/// * `headers` - The headers of the request.
+/// * `peer` - The address of the connection the request arrived on.
+/// * `trusted_hops` - How many reverse proxies in front of the service append to
+///   `X-Forwarded-For`. The client is the address the outermost of them saw, so any addresses
+///   before it (which the client could have made up) are ignored.
+///
+/// # Returns
+///
+/// The client's address, or `peer` if there are no trusted proxies or the header is missing
+/// addresses.
+pub fn client_ip(headers: &HeaderMap, peer: Option<IpAddr>, trusted_hops: usize) -> Option<IpAddr> {
+    if trusted_hops == 0 {
+        return peer;
+    }
+    let forwarded: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+    forwarded
+        .len()
+        .checked_sub(trusted_hops)
+        .and_then(|index| forwarded[index].parse().ok())
+        .or(peer)
+}
+
+/// How many reverse proxies in front of the service append to `X-Forwarded-For`.
+pub fn trusted_proxy_hops() -> usize {
+    from_env("TRUSTED_PROXY_HOPS", 0)
+}
+
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Take a token from the bucket with the given key.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Ok(()))` if the request is allowed.
+    /// * `Ok(Err(i64))` containing how many seconds until it would be.
+    /// * `Err(String)` describing why the bucket could not be updated.
+    async fn take(&self, key: &str, limit: Limit) -> Result<Result<(), i64>, String>;
+}
+
+/// Keeps buckets in the memory of this replica.
+///
+/// Buckets are forgotten once they have refilled, since a new bucket starts full. They are looked
+/// for once a minute rather than on every request, so the store only ever holds the keys used
+/// within about a minute plus their limit's window.
+pub struct MemoryStore {
+    buckets: Mutex<MemoryBuckets>,
+}
+
+struct MemoryBuckets {
+    /// Each bucket, along with when it will have refilled.
+    buckets: HashMap<String, (Bucket, DateTime<Utc>)>,
+    pruned_at: DateTime<Utc>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore {
+            buckets: Mutex::new(MemoryBuckets {
+                buckets: HashMap::new(),
+                pruned_at: Utc::now(),
+            }),
+        }
+    }
+
+    fn take_at(
+        &self,
+        key: &str,
+        limit: Limit,
+        now: DateTime<Utc>,
+    ) -> Result<Result<(), i64>, String> {
+        let mut store = self.buckets.lock().map_err(|e| e.to_string())?;
+        if now - store.pruned_at >= Duration::seconds(MEMORY_STORE_PRUNE_INTERVAL_SECONDS) {
+            store.buckets.retain(|_, (_, full_at)| *full_at > now);
+            store.pruned_at = now;
+        }
+        let (bucket, result) = limit.take(store.buckets.get(key).map(|(b, _)| *b), now);
+        store
+            .buckets
+            .insert(String::from(key), (bucket, limit.full_at(&bucket)));
+        Ok(result)
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryStore {
+    async fn take(&self, key: &str, limit: Limit) -> Result<Result<(), i64>, String> {
+        self.take_at(key, limit, Utc::now())
+    }
+}
+
+/// Keeps buckets in the `rate_limit_buckets` table, so that every replica shares them.
+pub struct PostgresStore {
+    pool: PgPool,
+    requests: AtomicU64,
+}
+
+impl PostgresStore {
+    pub fn new(pool: PgPool) -> Self {
+        PostgresStore {
+            pool,
+            requests: AtomicU64::new(0),
+        }
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for PostgresStore {
+    async fn take(&self, key: &str, limit: Limit) -> Result<Result<(), i64>, String> {
+        if self
+            .requests
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(POSTGRES_STORE_PRUNE_INTERVAL)
+        {
+            let pool = self.pool.clone();
+            tokio::spawn(async move {
+                let idle_since = Utc::now() - Duration::hours(IDLE_BUCKET_LIFETIME_HOURS);
+                if let Err(e) = delete_idle_rate_limit_buckets(&pool, idle_since).await {
+                    eprintln!("Failed to delete idle rate limit buckets: {}", e);
+                }
+            });
+        }
+        update_rate_limit_bucket(&self.pool, key, |bucket| limit.take(bucket, Utc::now()))
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// The configured limits, and the store their buckets are kept in.
+pub struct RateLimiter {
+    store: Box<dyn RateLimitStore>,
+    ip_limit: Option<Limit>,
+    username_limit: Option<Limit>,
+    trusted_hops: usize,
+}
+
+impl RateLimiter {
+    /// Load the rate limiter from the environment.
+    ///
+    /// # Errors
+    ///
+    /// Panics if `RATE_LIMIT_BACKEND` names an unknown backend.
+    pub fn from_env(pool: PgPool) -> Self {
+        let store: Box<dyn RateLimitStore> = match env::var("RATE_LIMIT_BACKEND").as_deref() {
+            Err(_) | Ok("memory") => Box::new(MemoryStore::new()),
+            Ok("postgres") => Box::new(PostgresStore::new(pool)),
+            Ok(other) => panic!("Unknown RATE_LIMIT_BACKEND '{}'", other),
+        };
+        RateLimiter {
+            store,
+            ip_limit: Limit::from_env("RATE_LIMIT_IP", "120/60"),
+            username_limit: Limit::from_env("RATE_LIMIT_USERNAME", "10/60"),
+            trusted_hops: trusted_proxy_hops(),
+        }
+    }
+
+    /// A rate limiter which allows every request, for tests which are not about rate limiting.
+    #[cfg(test)]
+    pub fn unlimited() -> Self {
+        RateLimiter {
+            store: Box::new(MemoryStore::new()),
+            ip_limit: None,
+            username_limit: None,
+            trusted_hops: 0,
+        }
+    }
+
+    async fn check(&self, key: String, limit: Option<Limit>) -> Result<(), i64> {
+        let limit = match limit {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        match self.store.take(&key, limit).await {
+            Ok(result) => result,
+            Err(e) => {
+                eprintln!("Rate limit for '{}' could not be checked: {}", key, e);
+                Ok(())
+            }
+        }
+    }
+
+    /// Take a token for a request from a client IP.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` if the request is allowed.
+    /// * `Err(i64)` containing how many seconds until it would be.
+    pub async fn check_ip(&self, ip: IpAddr) -> Result<(), i64> {
+        self.check(format!("ip:{}", ip), self.ip_limit).await
+    }
+
+    /// Take a token for a login to a username. Usernames are not case sensitive, but must otherwise
+    /// match exactly (see `db::queries::get_player_by_username`), so each account has one bucket.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` if the request is allowed.
+    /// * `Err(i64)` containing how many seconds until it would be.
+    pub async fn check_username(&self, username: &str) -> Result<(), i64> {
+        self.check(
+            format!("username:{}", username.to_lowercase()),
+            self.username_limit,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_limit() {
+        assert_eq!(
+            Limit::parse("10/60"),
+            Some(Limit {
+                capacity: 10.0,
+                per_second: 10.0 / 60.0
+            })
+        );
+        assert_eq!(
+            Limit::parse(" 5 / 0.5 "),
+            Some(Limit {
+                capacity: 5.0,
+                per_second: 10.0
+            })
+        );
+        for invalid in ["off", "", "10", "0/60", "10/0", "-1/60", "ten/60", "NaN/1"] {
+            assert_eq!(Limit::parse(invalid), None);
+        }
+    }
+
+    #[test]
+    fn test_take() {
+        let limit = Limit::parse("2/10").unwrap();
+        let now = Utc::now();
+
+        let (bucket, result) = limit.take(None, now);
+        assert_eq!((bucket.tokens, result), (1.0, Ok(())));
+        let (bucket, result) = limit.take(Some(bucket), now);
+        assert_eq!((bucket.tokens, result), (0.0, Ok(())));
+        let (bucket, result) = limit.take(Some(bucket), now);
+        assert_eq!((bucket.tokens, result), (0.0, Err(5)));
+
+        // Tokens are regained over time, but never past the capacity.
+        let (bucket, result) = limit.take(Some(bucket), now + Duration::seconds(4));
+        assert_eq!(result, Err(1));
+        let (_, result) = limit.take(Some(bucket), now + Duration::seconds(5));
+        assert_eq!(result, Ok(()));
+        let (bucket, _) = limit.take(Some(bucket), now + Duration::hours(1));
+        assert_eq!(bucket.tokens, 1.0);
+
+        // A clock which goes backwards regains nothing.
+        let (bucket, _) = limit.take(None, now);
+        let (bucket, _) = limit.take(Some(bucket), now - Duration::seconds(30));
+        assert_eq!(bucket.tokens, 0.0);
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let peer: Option<IpAddr> = "10.0.0.2".parse().ok();
+        let ip = |value: &str| value.parse::<IpAddr>().ok();
+        let mut headers = HeaderMap::new();
+        headers.append(
+            "x-forwarded-for",
+            HeaderValue::from_static("6.6.6.6, 203.0.113.7"),
+        );
+        headers.append("x-forwarded-for", HeaderValue::from_static("10.0.0.1"));
+
+        assert_eq!(client_ip(&headers, peer, 0), peer);
+        assert_eq!(client_ip(&headers, peer, 1), ip("10.0.0.1"));
+        assert_eq!(client_ip(&headers, peer, 2), ip("203.0.113.7"));
+        assert_eq!(client_ip(&headers, peer, 3), ip("6.6.6.6"));
+        assert_eq!(client_ip(&headers, peer, 4), peer);
+        assert_eq!(client_ip(&HeaderMap::new(), peer, 1), peer);
+
+        let mut garbage = HeaderMap::new();
+        garbage.insert("x-forwarded-for", HeaderValue::from_static("not an ip"));
+        assert_eq!(client_ip(&garbage, peer, 1), peer);
+    }
+
+    #[tokio::test]
+    async fn test_memory_store() {
+        let store = MemoryStore::new();
+        let limit = Limit::parse("2/60").unwrap();
+        assert_eq!(store.take("a", limit).await, Ok(Ok(())));
+        assert_eq!(store.take("a", limit).await, Ok(Ok(())));
+        assert_eq!(store.take("a", limit).await, Ok(Err(30)));
+        assert_eq!(store.take("b", limit).await, Ok(Ok(())));
+    }
+
+    #[test]
+    fn test_memory_store_prune() {
+        let store = MemoryStore::new();
+        let now = Utc::now();
+        let keys = || {
+            let mut keys: Vec<String> = store
+                .buckets
+                .lock()
+                .unwrap()
+                .buckets
+                .keys()
+                .cloned()
+                .collect();
+            keys.sort();
+            keys
+        };
+
+        // `a` refills after 30 seconds, and `b` after 300.
+        store
+            .take_at("a", Limit::parse("2/60").unwrap(), now)
+            .unwrap()
+            .unwrap();
+        store
+            .take_at("b", Limit::parse("2/600").unwrap(), now)
+            .unwrap()
+            .unwrap();
+
+        // Nothing is forgotten until the store is next pruned.
+        let limit = Limit::parse("2/60").unwrap();
+        store
+            .take_at("c", limit, now + Duration::seconds(45))
+            .unwrap()
+            .unwrap();
+        assert_eq!(keys(), vec!["a", "b", "c"]);
+        store
+            .take_at("d", limit, now + Duration::seconds(61))
+            .unwrap()
+            .unwrap();
+        assert_eq!(keys(), vec!["b", "c", "d"]);
+    }
+}
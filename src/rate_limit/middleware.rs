@@ -0,0 +1,175 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::{ConnectInfo, Request, State},
+    http::{header::RETRY_AFTER, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+
+use crate::{
+    handlers::responses::MessageResponse,
+    rate_limit::{client_ip, RateLimiter},
+};
+
+/// The largest login request body which is read to find the username.
+const MAX_LOGIN_BODY_BYTES: usize = 64 * 1024;
+
+/// The part of the login request body which is limited.
+#[derive(Deserialize)]
+struct LoginBody {
+    username: String,
+}
+
+fn too_many_requests(retry_after: i64) -> Response {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(RETRY_AFTER, retry_after.to_string())],
+        Json(MessageResponse::new(
+            "Too many requests. Please try again later.",
+        )),
+    )
+        .into_response()
+}
+
+/// Reject requests from clients (and to usernames) which have used up their limit, with
+/// `429 Too Many Requests` and a `Retry-After` header.
+pub async fn rate_limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(address)| address.ip());
+    if let Some(ip) = client_ip(request.headers(), peer, limiter.trusted_hops) {
+        if let Err(wait) = limiter.check_ip(ip).await {
+            return too_many_requests(wait);
+        }
+    }
+
+    if request.method() != Method::POST || request.uri().path() != "/authn" {
+        return next.run(request).await;
+    }
+
+    // The body has to be read to find the username, so it is put back together for the handler.
+    let (parts, body) = request.into_parts();
+    let bytes = match to_bytes(body, MAX_LOGIN_BODY_BYTES).await {
+        Ok(bytes) => bytes,
+        Err(_) => return StatusCode::PAYLOAD_TOO_LARGE.into_response(),
+    };
+    if let Ok(login) = serde_json::from_slice::<LoginBody>(&bytes) {
+        if let Err(wait) = limiter.check_username(&login.username).await {
+            return too_many_requests(wait);
+        }
+    }
+    next.run(Request::from_parts(parts, Body::from(bytes)))
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        extract::ConnectInfo,
+        http::{header::RETRY_AFTER, Method, StatusCode},
+        Router,
+    };
+    use serde_json::json;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::{
+        rate_limit::{Limit, MemoryStore},
+        test_utils::{create_test_player, request, send, test_app_with},
+    };
+
+    /// Send a login request from a client IP.
+    async fn login(app: &Router, ip: &str, username: &str) -> (StatusCode, Option<String>) {
+        let body = json!({ "username": username, "password": "CorrectHorse!42" });
+        let mut request = request(Method::POST, "/authn", None, Some(body));
+        let address: SocketAddr = format!("{}:4000", ip).parse().unwrap();
+        request.extensions_mut().insert(ConnectInfo(address));
+        let (status, headers, _) = send(app, request).await;
+        let retry_after = headers
+            .get(RETRY_AFTER)
+            .map(|value| value.to_str().unwrap().to_string());
+        (status, retry_after)
+    }
+
+    #[sqlx::test]
+    async fn test_rate_limit(pool: PgPool) {
+        let app = test_app_with(
+            pool,
+            RateLimiter {
+                store: Box::new(MemoryStore::new()),
+                ip_limit: Limit::parse("3/60"),
+                username_limit: Limit::parse("2/60"),
+                trusted_hops: 0,
+            },
+        );
+        // Every login runs Argon2, so the buckets refill a little between requests.
+        let assert_limited = |(status, retry_after): (StatusCode, Option<String>), most: i64| {
+            assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+            let seconds: i64 = retry_after.unwrap().parse().unwrap();
+            assert!(seconds > 0 && seconds <= most);
+        };
+
+        // Requests within the limits reach the handler.
+        assert_eq!(
+            login(&app, "10.0.0.1", "b1gd3vd0g").await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            login(&app, "10.0.0.2", "b1gd3vd0g").await.0,
+            StatusCode::UNAUTHORIZED
+        );
+
+        // The username is limited no matter which IP the requests come from.
+        assert_limited(login(&app, "10.0.0.3", "b1gd3vd0g").await, 30);
+        assert_eq!(
+            login(&app, "10.0.0.3", "someone").await.0,
+            StatusCode::UNAUTHORIZED
+        );
+
+        // Each IP is limited too, across every username.
+        assert_eq!(
+            login(&app, "10.0.0.3", "someone else").await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_limited(login(&app, "10.0.0.3", "anyone").await, 20);
+    }
+
+    #[sqlx::test]
+    async fn test_rate_limit_username_variants(pool: PgPool) {
+        create_test_player(&pool, "b1gd3vd0g", "CorrectHorse!42").await;
+        let app = test_app_with(
+            pool,
+            RateLimiter {
+                store: Box::new(MemoryStore::new()),
+                ip_limit: None,
+                username_limit: Limit::parse("2/60"),
+                trusted_hops: 0,
+            },
+        );
+
+        // Every spelling of the account shares its bucket.
+        assert_eq!(login(&app, "10.0.0.1", "B1GD3VD0G").await.0, StatusCode::OK);
+        assert_eq!(login(&app, "10.0.0.2", "b1gd3vd0g").await.0, StatusCode::OK);
+        assert_eq!(
+            login(&app, "10.0.0.3", "b1Gd3vd0g").await.0,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+
+        // Wildcards get buckets of their own, but they are not the account.
+        for username in ["b1gd3vd0_", "b1gd3vd0%", "%1gd3vd0g"] {
+            assert_eq!(
+                login(&app, "10.0.0.4", username).await.0,
+                StatusCode::UNAUTHORIZED
+            );
+        }
+    }
+}
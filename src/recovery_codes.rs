@@ -0,0 +1,81 @@
+//! This module generates recovery codes, which a player can use in place of a code from their
+//! authenticator app if they lose it.
+//!
+//! # Notes
+//!
+//! - Each code is 10 characters (50 bits) from an alphabet without easily confused characters, shown
+//!   as two groups of 5 separated by a dash.
+//! - Codes are only ever shown to the player once. Store them hashed with `hashing::hash_password`,
+//!   and always `normalize` a code before hashing or verifying it. Codes are random, so they are
+//!   not peppered.
+
+use rand::{rngs::OsRng, Rng};
+
+/// How many recovery codes a player is given at once.
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+const ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+const GROUP_LENGTH: usize = 5;
+
+/// Generates a new set of recovery codes.
+///
+/// # Returns
+///
+/// `RECOVERY_CODE_COUNT` distinct codes, formatted like `abcde-fghjk`.
+pub fn generate_recovery_codes() -> Vec<String> {
+    let mut codes: Vec<String> = Vec::with_capacity(RECOVERY_CODE_COUNT);
+    while codes.len() < RECOVERY_CODE_COUNT {
+        let group = || -> String {
+            (0..GROUP_LENGTH)
+                .map(|_| ALPHABET[OsRng.gen_range(0..ALPHABET.len())] as char)
+                .collect()
+        };
+        let code = format!("{}-{}", group(), group());
+        if !codes.contains(&code) {
+            codes.push(code);
+        }
+    }
+    codes
+}
+
+/// Normalizes a recovery code as typed by a player, so that case, whitespace and the dash do not
+/// matter.
+///
+/// # Returns
+///
+/// * `Some(String)` containing the normalized code.
+/// * `None` if the input cannot be a recovery code, so there is no need to check it.
+pub fn normalize(code: &str) -> Option<String> {
+    let code: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    (code.len() == 2 * GROUP_LENGTH).then_some(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_recovery_codes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        for code in &codes {
+            assert_eq!(code.len(), 2 * GROUP_LENGTH + 1);
+            assert_eq!(code.chars().nth(GROUP_LENGTH), Some('-'));
+            assert!(normalize(code)
+                .unwrap()
+                .bytes()
+                .all(|b| ALPHABET.contains(&b)));
+        }
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("abcde-fghjk").as_deref(), Some("abcdefghjk"));
+        assert_eq!(normalize(" ABCDE fghjk\n").as_deref(), Some("abcdefghjk"));
+        assert_eq!(normalize("123456"), None);
+    }
+}
@@ -1 +1,139 @@
+//! This module contains the clients for the other casino services.
+//!
+//! # Environment
+//!
+//! * `CURRENCY_MS_URL` - (Optional) The base URL of the currency service. Defaults to
+//!   `http://currency-ms:3000`.
+//! * `REWARD_MS_URL` - (Optional) The base URL of the reward service. Defaults to
+//!   `http://reward-ms:3000`.
+//! * `SLOTS_MS_URL` - (Optional) The base URL of the slots service. Defaults to
+//!   `http://slots-ms:3000`.
+
 pub mod currency;
+pub mod reward;
+pub mod slots;
+
+use std::{env, time::Duration};
+
+use reqwest::{Client, StatusCode};
+use uuid::Uuid;
+
+use crate::{
+    db::models::Player,
+    handlers::responses::MessageResponse,
+    jwt::{encode_authn_token, AuthnTokenReqs},
+};
+
+/// How long a service has to respond.
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The base URL of a service, without a trailing slash.
+fn service_url(key: &str, default: &str) -> String {
+    env::var(key)
+        .unwrap_or_else(|_| String::from(default))
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Mint an access token for a player, for calls made on their behalf outside of one of their
+/// requests. It is not tied to a session, so it cannot be refreshed.
+pub fn player_token(player: &Player) -> Result<String, MessageResponse> {
+    encode_authn_token(AuthnTokenReqs::new(
+        player.id,
+        player.username.clone(),
+        player.email.clone(),
+        player.email_verified_at.is_some(),
+        Uuid::new_v4(),
+    ))
+    .map_err(|_| MessageResponse::token_creation_failure())
+}
+
+/// Ask a service to delete everything it holds about a player, by sending `DELETE /` with a token
+/// naming the player. A service which has nothing to delete (`404 Not Found`) counts as done.
+///
+/// # Arguments
+///
+/// * `base_url` - The base URL of the service.
+/// * `token` - An access token for the player.
+///
+/// # Returns
+///
+/// * `Ok(())` if the service no longer holds anything about the player.
+/// * `Err(MessageResponse)` describing why not, otherwise.
+pub async fn delete_player_data(base_url: &str, token: &str) -> Result<(), MessageResponse> {
+    let response = Client::new()
+        .delete(format!("{}/", base_url))
+        .timeout(TIMEOUT)
+        .bearer_auth(token)
+        .send()
+        .await
+        .map_err(|e| MessageResponse::new(&format!("Failed to call {}: {}", base_url, e)))?;
+
+    match response.status() {
+        status if status.is_success() || status == StatusCode::NOT_FOUND => Ok(()),
+        status => Err(MessageResponse::new(&format!(
+            "{} responded with {}",
+            base_url, status
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::StatusCode, routing::delete, Router};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Start a stub service which responds to `DELETE /` with the given status, and checks the token.
+    async fn stub_service(status: StatusCode) -> String {
+        let app = Router::new().route(
+            "/",
+            delete(move |headers: axum::http::HeaderMap| async move {
+                match headers.get("Authorization") {
+                    Some(value) if value == "Bearer player-token" => status,
+                    _ => StatusCode::UNAUTHORIZED,
+                }
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}", address)
+    }
+
+    #[tokio::test]
+    async fn test_delete_player_data() {
+        for status in [
+            StatusCode::OK,
+            StatusCode::NO_CONTENT,
+            StatusCode::NOT_FOUND,
+        ] {
+            let url = stub_service(status).await;
+            assert!(delete_player_data(&url, "player-token").await.is_ok());
+        }
+
+        for status in [
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::SERVICE_UNAVAILABLE,
+        ] {
+            let url = stub_service(status).await;
+            assert!(delete_player_data(&url, "player-token").await.is_err());
+        }
+
+        let url = stub_service(StatusCode::NO_CONTENT).await;
+        assert!(delete_player_data(&url, "wrong-token").await.is_err());
+
+        // Nothing is listening on this port once the listener is dropped.
+        let address = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        assert!(
+            delete_player_data(&format!("http://{}", address), "player-token")
+                .await
+                .is_err()
+        );
+    }
+}
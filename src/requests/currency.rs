@@ -1,8 +1,18 @@
 use axum::http::HeaderMap;
 use reqwest::{Client, StatusCode};
 
-use crate::handlers::responses::MessageResponse;
+use crate::{
+    handlers::responses::MessageResponse,
+    requests::{delete_player_data, service_url, TIMEOUT},
+};
 
+fn currency_ms_url() -> String {
+    service_url("CURRENCY_MS_URL", "http://currency-ms:3000")
+}
+
+/// Create a bit wallet for the player the token names. The currency service answers `409 Conflict`
+/// when the player already has a wallet, which counts as created, so this can be retried (see
+/// `wallet_reconciliation`).
 pub async fn create_bit_wallet(token: String) -> Result<(), MessageResponse> {
     let client = Client::new();
 
@@ -11,7 +21,8 @@ pub async fn create_bit_wallet(token: String) -> Result<(), MessageResponse> {
     hm.insert("Authorization", hv.parse().unwrap());
 
     let response = client
-        .post("http://currency-ms:3000")
+        .post(currency_ms_url())
+        .timeout(TIMEOUT)
         .headers(hm)
         .send()
         .await;
@@ -22,9 +33,14 @@ pub async fn create_bit_wallet(token: String) -> Result<(), MessageResponse> {
     };
 
     match response.status() {
-        StatusCode::CREATED => Ok(()),
+        StatusCode::CREATED | StatusCode::CONFLICT => Ok(()),
         _ => Err(MessageResponse::new(
             "The request to create a new wallet failed.",
         )),
     }
 }
+
+/// Close the bit wallet of the player the token names.
+pub async fn close_bit_wallet(token: &str) -> Result<(), MessageResponse> {
+    delete_player_data(&currency_ms_url(), token).await
+}
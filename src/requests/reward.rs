@@ -0,0 +1,13 @@
+use crate::{
+    handlers::responses::MessageResponse,
+    requests::{delete_player_data, service_url},
+};
+
+fn reward_ms_url() -> String {
+    service_url("REWARD_MS_URL", "http://reward-ms:3000")
+}
+
+/// Delete the daily bonus claims and streaks of the player the token names.
+pub async fn delete_reward_history(token: &str) -> Result<(), MessageResponse> {
+    delete_player_data(&reward_ms_url(), token).await
+}
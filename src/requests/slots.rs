@@ -0,0 +1,13 @@
+use crate::{
+    handlers::responses::MessageResponse,
+    requests::{delete_player_data, service_url},
+};
+
+fn slots_ms_url() -> String {
+    service_url("SLOTS_MS_URL", "http://slots-ms:3000")
+}
+
+/// Delete the slot machine history of the player the token names.
+pub async fn delete_slots_history(token: &str) -> Result<(), MessageResponse> {
+    delete_player_data(&slots_ms_url(), token).await
+}
@@ -1,17 +1,54 @@
+use std::sync::Arc;
+
 use axum::{
-    routing::{get, post},
+    middleware::from_fn_with_state,
+    routing::{delete, get, post, put},
     Router,
 };
 use sqlx::PgPool;
 
-use crate::handlers::{
-    authentication::{login::handle_login, token::handle_fetch_player_by_token},
-    creation::handle_player_creation,
-    deletion::handle_player_deletion,
-    documentation::handle_serve_documentation,
+use crate::{
+    handlers::{
+        account_deletions::{handle_account_deletion_retry, handle_list_account_deletions},
+        authentication::{
+            login::handle_login,
+            logout::{handle_logout, handle_logout_all},
+            magic_link::{handle_magic_link_redemption, handle_magic_link_request},
+            mfa::handle_mfa_login,
+            passkey::{handle_passkey_login, handle_passkey_login_options},
+            refresh::handle_token_refresh,
+            restoration::handle_account_restoration,
+            token::handle_fetch_player_by_token,
+        },
+        creation::handle_player_creation,
+        deletion::handle_player_deletion,
+        documentation::handle_serve_documentation,
+        email::{
+            change::{handle_email_change_confirmation, handle_email_change_request},
+            verification::{handle_email_verification, handle_resend_verification},
+        },
+        jwks::handle_serve_jwks,
+        passkeys::{
+            handle_list_passkeys, handle_passkey_deletion, handle_passkey_registration,
+            handle_passkey_registration_options,
+        },
+        password::{
+            change::handle_password_change,
+            reset::{handle_forgot_password, handle_password_reset},
+        },
+        recovery_codes::{handle_recovery_codes_regeneration, handle_recovery_codes_status},
+        sessions::{handle_list_sessions, handle_session_revocation},
+        totp::{handle_totp_confirmation, handle_totp_disable, handle_totp_enrollment},
+        username::handle_username_change,
+        webhooks::{
+            handle_list_webhook_deliveries, handle_list_webhooks, handle_webhook_creation,
+            handle_webhook_deletion, handle_webhook_delivery_retry,
+        },
+    },
+    rate_limit::{rate_limit, RateLimiter},
 };
 
-pub fn router() -> Router<PgPool> {
+pub fn router(rate_limiter: Arc<RateLimiter>) -> Router<PgPool> {
     Router::new()
         .route(
             "/",
@@ -19,8 +56,66 @@ pub fn router() -> Router<PgPool> {
                 .delete(handle_player_deletion)
                 .get(handle_serve_documentation),
         )
+        .route("/account-deletions", get(handle_list_account_deletions))
+        .route(
+            "/account-deletions/:player_id/retry",
+            post(handle_account_deletion_retry),
+        )
         .route(
             "/authn",
             get(handle_fetch_player_by_token).post(handle_login),
         )
+        .route("/authn/magic-link", post(handle_magic_link_request))
+        .route(
+            "/authn/magic-link/redeem",
+            post(handle_magic_link_redemption),
+        )
+        .route("/authn/mfa", post(handle_mfa_login))
+        .route("/authn/passkey", post(handle_passkey_login))
+        .route("/authn/passkey/options", post(handle_passkey_login_options))
+        .route("/authn/refresh", post(handle_token_refresh))
+        .route("/authn/restore", post(handle_account_restoration))
+        .route("/authn/logout", post(handle_logout))
+        .route("/authn/logout-all", post(handle_logout_all))
+        .route("/.well-known/jwks.json", get(handle_serve_jwks))
+        .route("/email", put(handle_email_change_request))
+        .route("/email/confirm", post(handle_email_change_confirmation))
+        .route("/email/verify", post(handle_email_verification))
+        .route("/email/verify/resend", post(handle_resend_verification))
+        .route(
+            "/mfa/totp",
+            post(handle_totp_enrollment).delete(handle_totp_disable),
+        )
+        .route("/mfa/totp/confirm", post(handle_totp_confirmation))
+        .route(
+            "/mfa/recovery-codes",
+            get(handle_recovery_codes_status).post(handle_recovery_codes_regeneration),
+        )
+        .route("/passkeys", get(handle_list_passkeys))
+        .route("/passkeys/:id", delete(handle_passkey_deletion))
+        .route("/passkeys/register", post(handle_passkey_registration))
+        .route(
+            "/passkeys/register/options",
+            post(handle_passkey_registration_options),
+        )
+        .route("/password", put(handle_password_change))
+        .route("/password/forgot", post(handle_forgot_password))
+        .route("/password/reset", post(handle_password_reset))
+        .route("/sessions", get(handle_list_sessions))
+        .route("/sessions/:id", delete(handle_session_revocation))
+        .route("/username", put(handle_username_change))
+        .route(
+            "/webhooks",
+            get(handle_list_webhooks).post(handle_webhook_creation),
+        )
+        .route("/webhooks/:id", delete(handle_webhook_deletion))
+        .route(
+            "/webhooks/:id/deliveries",
+            get(handle_list_webhook_deliveries),
+        )
+        .route(
+            "/webhooks/:id/deliveries/:delivery_id/retry",
+            post(handle_webhook_delivery_retry),
+        )
+        .layer(from_fn_with_state(rate_limiter, rate_limit))
 }
@@ -1,12 +1,116 @@
-use std::{env, sync::Once};
+use std::{
+    env,
+    sync::{Arc, Once, OnceLock},
+};
 
-#[allow(dead_code)]
-static INIT: Once = Once::new(); // Not dead
+use axum::{
+    body::{to_bytes, Body},
+    http::{header::AUTHORIZATION, header::CONTENT_TYPE, HeaderMap, Method, Request, StatusCode},
+    Router,
+};
+use rand::rngs::OsRng;
+use ring::{rand::SystemRandom, signature::Ed25519KeyPair};
+use rsa::{
+    pkcs8::{EncodePrivateKey, EncodePublicKey, LineEnding},
+    RsaPrivateKey,
+};
+use serde_json::Value;
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+use crate::{
+    db::{models::Player, queries::create_new_player},
+    hashing,
+    rate_limit::RateLimiter,
+    router::router,
+};
+
+static INIT: Once = Once::new();
+static RSA_KEY_PAIR: OnceLock<(String, String)> = OnceLock::new();
 
 /// Initializes the environment for testing.
-#[allow(dead_code)] // This is not dead code, just used in tests.
 pub fn test_setup() {
     INIT.call_once(|| {
-        env::set_var("JWT_SECRET", "supersecretvalue");
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let pem = pem::encode(&pem::Pem::new("PRIVATE KEY", pkcs8.as_ref()));
+        env::set_var("JWT_PRIVATE_KEY", pem);
     });
 }
+
+/// An RSA key pair, generated once per test run so that no key material has to be kept in the
+/// repository.
+///
+/// # Returns
+///
+/// The PEM encoded private key (PKCS#8) and public key (SPKI).
+pub fn rsa_key_pair() -> &'static (String, String) {
+    RSA_KEY_PAIR.get_or_init(|| {
+        let private = RsaPrivateKey::new(&mut OsRng, 2048).unwrap();
+        (
+            private.to_pkcs8_pem(LineEnding::LF).unwrap().to_string(),
+            private
+                .to_public_key()
+                .to_public_key_pem(LineEnding::LF)
+                .unwrap(),
+        )
+    })
+}
+
+/// Build the whole app on top of a test database, without rate limiting.
+pub fn test_app(pool: PgPool) -> Router {
+    test_app_with(pool, RateLimiter::unlimited())
+}
+
+/// Build the whole app on top of a test database, with the given rate limiter.
+pub fn test_app_with(pool: PgPool, rate_limiter: RateLimiter) -> Router {
+    test_setup();
+    router(Arc::new(rate_limiter)).with_state(pool)
+}
+
+/// Send a request to the app.
+///
+/// # Returns
+///
+/// The status and headers of the response, and its body as JSON (`Value::Null` if it is not JSON).
+pub async fn send(app: &Router, request: Request<Body>) -> (StatusCode, HeaderMap, Value) {
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+    (status, headers, body)
+}
+
+/// Build a request, with an optional bearer token and JSON body.
+pub fn request(
+    method: Method,
+    uri: &str,
+    bearer: Option<&str>,
+    body: Option<Value>,
+) -> Request<Body> {
+    let mut builder = Request::builder().method(method).uri(uri);
+    if let Some(token) = bearer {
+        builder = builder.header(AUTHORIZATION, format!("Bearer {}", token));
+    }
+    match body {
+        Some(body) => builder
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap(),
+        None => builder.body(Body::empty()).unwrap(),
+    }
+}
+
+/// Add a player directly to the database, skipping registration (and the currency service).
+pub async fn create_test_player(pool: &PgPool, username: &str, password: &str) -> Player {
+    let hash = hashing::hash_password(password, None).unwrap();
+    create_new_player(
+        pool,
+        String::from(username),
+        format!("{}@bigdevdog.com", username),
+        hash,
+        None,
+    )
+    .await
+    .unwrap()
+}
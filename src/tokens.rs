@@ -0,0 +1,64 @@
+//! This module provides functions for generating and hashing **opaque tokens** - random secrets
+//! handed to the client (such as refresh tokens) which carry no information of their own and are
+//! only meaningful when looked up in the database.
+//!
+//! # Notes
+//!
+//! - Tokens contain 256 bits of randomness from the operating system, encoded as URL-safe base64.
+//! - Only the SHA-256 hash of a token should ever be stored. Because the tokens are long and
+//!   random, a fast hash is sufficient - there is no need for Argon2 here.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+/// Generates a new random opaque token.
+///
+/// # Returns
+///
+/// A 43 character, URL-safe token.
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Hashes an opaque token so that it can be stored in (or looked up from) the database.
+///
+/// # Arguments
+///
+/// * `token` - The raw token provided to (or by) the client.
+///
+/// # Returns
+///
+/// The SHA-256 hash of the token, hex encoded.
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_token() {
+        let a = generate_token();
+        let b = generate_token();
+        assert_eq!(a.len(), 43);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_hash_token() {
+        let token = generate_token();
+        assert_eq!(hash_token(&token), hash_token(&token));
+        assert_ne!(hash_token(&token), hash_token(&generate_token()));
+        assert_eq!(
+            hash_token("hunter2"),
+            "f52fbd32b2b3b86ff88ef6c490628285f482af15ddcb29541f94bcf526a3f6c7"
+        );
+    }
+}
@@ -0,0 +1,99 @@
+//! This module makes sure every player eventually has a bit wallet.
+//!
+//! # Notes
+//!
+//! - Registration creates the player first, and then asks the currency service for their wallet. If
+//!   that fails, the player is still registered, with their wallet pending (`wallet_created_at` is
+//!   null).
+//! - A background job asks the currency service again for every player whose wallet is pending, until
+//!   it succeeds.
+//! - A request which appeared to fail (such as one which timed out) may still have created the
+//!   wallet. Retrying relies on the currency service answering `409 Conflict`, rather than creating
+//!   a second wallet, when the player already has one; that counts as created. The currency service
+//!   must keep to this for retries to be safe.
+//! - Players registered before wallets were tracked are assumed to have one (see migration 0017),
+//!   and are never retried.
+//! - Each player's wallet is retried with exponential backoff of its own, so wallets which keep failing
+//!   do not hold up the rest. Each replica claims the players it retries, so a wallet is not
+//!   requested twice at once.
+//!
+//! # Environment
+//!
+//! * `WALLET_RECONCILE_INTERVAL_SECONDS` - (Optional) How often pending wallets are checked. Defaults
+//!   to 60.
+//! * `WALLET_RETRY_SECONDS` - (Optional) How long to wait before retrying a wallet after its first
+//!   failure in the background. The wait doubles after each failure, up to 6 hours. Defaults to 60.
+
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+
+use crate::{
+    backoff::retry_delay,
+    config::from_env,
+    db::queries::{claim_players_without_wallets, fail_wallet_creation, mark_wallet_created},
+    requests::{currency::create_bit_wallet, player_token},
+};
+
+/// The most wallets retried in one run, so a long outage does not make one run take forever. They
+/// are retried one after another, so this many requests timing out (see `requests::TIMEOUT`) must
+/// fit well within the lease.
+const BATCH_SIZE: i64 = 50;
+
+/// The longest wait between attempts at a wallet.
+const MAX_RETRY_DELAY_SECONDS: i64 = 6 * 60 * 60;
+
+/// How long a replica has to create the wallets it claims before another replica may claim them.
+const WALLET_LEASE_MINUTES: i64 = 15;
+
+/// Retry creating the wallets which are pending.
+///
+/// # Returns
+///
+/// The number of wallets which were created.
+pub async fn reconcile_wallets(pool: &PgPool) -> Result<usize, sqlx::Error> {
+    let mut created = 0;
+    let lease_until = Utc::now() + Duration::minutes(WALLET_LEASE_MINUTES);
+    for player in claim_players_without_wallets(pool, BATCH_SIZE, lease_until).await? {
+        let result = match player_token(&player) {
+            Ok(token) => create_bit_wallet(token).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => {
+                mark_wallet_created(pool, player.id).await?;
+                created += 1;
+            }
+            Err(e) => {
+                let attempts = player.wallet_attempts + 1;
+                eprintln!(
+                    "Failed to create the wallet of player {} (attempt {}): {}",
+                    player.id, attempts, e.message
+                );
+                let retry_at = Utc::now()
+                    + retry_delay(
+                        attempts,
+                        from_env("WALLET_RETRY_SECONDS", 60),
+                        MAX_RETRY_DELAY_SECONDS,
+                    );
+                fail_wallet_creation(pool, player.id, retry_at).await?;
+            }
+        }
+    }
+    Ok(created)
+}
+
+/// Start the background job which retries creating pending wallets.
+pub fn spawn_reconcile_job(pool: PgPool) {
+    let interval: u64 = from_env("WALLET_RECONCILE_INTERVAL_SECONDS", 60).max(1);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            match reconcile_wallets(&pool).await {
+                Ok(0) => (),
+                Ok(created) => println!("Created {} pending wallets", created),
+                Err(e) => eprintln!("Failed to reconcile pending wallets: {}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,461 @@
+//! This module verifies the WebAuthn ceremonies which let players register passkeys and sign in
+//! with them instead of a password.
+//!
+//! # Notes
+//!
+//! - Only the `none` attestation conveyance is supported: the authenticator's attestation statement
+//!   (if any) is not verified, which is what browsers send for consumer passkeys anyway.
+//! - User verification (a PIN or biometric on the authenticator) is required, since a passkey
+//!   replaces both the password and the second factor.
+//! - `ES256`, `EdDSA` and `RS256` credentials are supported.
+//!
+//! # Environment
+//!
+//! * `WEBAUTHN_RP_ID` - (Optional) The relying party id, which is the domain passkeys are bound to.
+//!   Defaults to `localhost`.
+//! * `WEBAUTHN_ORIGIN` - (Optional) The origin ceremonies must be performed on. Defaults to
+//!   `FRONTEND_URL`.
+
+#[cfg(test)]
+mod test_authenticator;
+
+use std::{env, io::Cursor};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ciborium::Value;
+use ring::signature::{
+    RsaPublicKeyComponents, UnparsedPublicKey, ECDSA_P256_SHA256_ASN1, ED25519,
+    RSA_PKCS1_2048_8192_SHA256,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// The COSE algorithm identifier for ECDSA with P-256 and SHA-256.
+pub const ES256: i64 = -7;
+/// The COSE algorithm identifier for Ed25519.
+pub const EDDSA: i64 = -8;
+/// The COSE algorithm identifier for RSASSA-PKCS1-v1_5 with SHA-256.
+pub const RS256: i64 = -257;
+/// Every supported algorithm, in order of preference.
+pub const SUPPORTED_ALGORITHMS: [i64; 3] = [ES256, EDDSA, RS256];
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+/// The website players register and use passkeys on.
+pub struct RelyingParty {
+    pub id: String,
+    pub name: String,
+    pub origin: String,
+}
+
+impl RelyingParty {
+    /// Load the relying party from the environment.
+    pub fn from_env() -> Self {
+        let origin = env::var("WEBAUTHN_ORIGIN")
+            .or_else(|_| env::var("FRONTEND_URL"))
+            .unwrap_or_else(|_| String::from("http://localhost:60000"));
+        RelyingParty {
+            id: env::var("WEBAUTHN_RP_ID").unwrap_or_else(|_| String::from("localhost")),
+            name: String::from("Bit Casino"),
+            origin: String::from(origin.trim_end_matches('/')),
+        }
+    }
+}
+
+/// The parts of the client data (`CollectedClientData`) which are checked.
+#[derive(Deserialize)]
+pub struct ClientData {
+    #[serde(rename = "type")]
+    pub ceremony: String,
+    /// The challenge, base64url encoded.
+    pub challenge: String,
+    pub origin: String,
+}
+
+impl ClientData {
+    /// Parse the client data, so that its challenge can be looked up before the rest of the ceremony
+    /// is verified.
+    pub fn parse(client_data_json: &[u8]) -> Result<Self, String> {
+        serde_json::from_slice(client_data_json).map_err(|e| format!("Invalid client data: {}", e))
+    }
+}
+
+/// A credential which has passed the registration ceremony, ready to be stored.
+pub struct NewCredential {
+    pub credential_id: Vec<u8>,
+    /// The credential's public key, as a COSE key.
+    pub public_key: Vec<u8>,
+    pub sign_count: u32,
+}
+
+struct AuthenticatorData<'a> {
+    rp_id_hash: &'a [u8],
+    flags: u8,
+    sign_count: u32,
+    /// The attested credential data and extensions, if any.
+    rest: &'a [u8],
+}
+
+fn parse_authenticator_data(data: &[u8]) -> Result<AuthenticatorData<'_>, String> {
+    if data.len() < 37 {
+        return Err(String::from("Authenticator data is too short"));
+    }
+    Ok(AuthenticatorData {
+        rp_id_hash: &data[..32],
+        flags: data[32],
+        sign_count: u32::from_be_bytes([data[33], data[34], data[35], data[36]]),
+        rest: &data[37..],
+    })
+}
+
+fn check_client_data(
+    rp: &RelyingParty,
+    client_data: &ClientData,
+    ceremony: &str,
+) -> Result<(), String> {
+    if client_data.ceremony != ceremony {
+        return Err(format!("Expected a '{}' ceremony", ceremony));
+    }
+    if client_data.origin != rp.origin {
+        return Err(format!("Unexpected origin '{}'", client_data.origin));
+    }
+    Ok(())
+}
+
+fn check_authenticator_data(rp: &RelyingParty, data: &AuthenticatorData) -> Result<(), String> {
+    if data.rp_id_hash != Sha256::digest(rp.id.as_bytes()).as_slice() {
+        return Err(String::from(
+            "The credential belongs to another relying party",
+        ));
+    }
+    if data.flags & FLAG_USER_PRESENT == 0 || data.flags & FLAG_USER_VERIFIED == 0 {
+        return Err(String::from("The user was not verified"));
+    }
+    Ok(())
+}
+
+/// Verify the response to a registration ceremony.
+///
+/// # Notes
+///
+/// * This does not check the challenge. The caller must look up (and use up) the challenge from
+///   `ClientData::parse` first.
+///
+/// # Arguments
+///
+/// * `rp` - The relying party.
+/// * `client_data_json` - The `clientDataJSON` from the authenticator's response.
+/// * `attestation_object` - The `attestationObject` from the authenticator's response.
+///
+/// # Returns
+///
+/// * `Ok(NewCredential)` if the response is valid.
+/// * `Err(String)` describing why it is not.
+pub fn verify_registration(
+    rp: &RelyingParty,
+    client_data_json: &[u8],
+    attestation_object: &[u8],
+) -> Result<NewCredential, String> {
+    check_client_data(rp, &ClientData::parse(client_data_json)?, "webauthn.create")?;
+
+    let attestation: Value = ciborium::from_reader(attestation_object)
+        .map_err(|_| String::from("Invalid attestation object"))?;
+    let auth_data = match map_get(&attestation, &Value::Text(String::from("authData"))) {
+        Some(Value::Bytes(bytes)) => bytes,
+        _ => {
+            return Err(String::from(
+                "The attestation object has no authenticator data",
+            ))
+        }
+    };
+    let data = parse_authenticator_data(auth_data)?;
+    check_authenticator_data(rp, &data)?;
+    if data.flags & FLAG_ATTESTED_CREDENTIAL_DATA == 0 {
+        return Err(String::from("The authenticator data has no credential"));
+    }
+
+    // The AAGUID (16 bytes), then the length of the credential id (2 bytes), then the credential id,
+    // then the credential's public key.
+    let rest = data.rest;
+    if rest.len() < 18 {
+        return Err(String::from("The attested credential data is too short"));
+    }
+    let id_length = u16::from_be_bytes([rest[16], rest[17]]) as usize;
+    let rest = &rest[18..];
+    if rest.len() < id_length {
+        return Err(String::from("The attested credential data is too short"));
+    }
+    let (credential_id, rest) = rest.split_at(id_length);
+
+    let mut cursor = Cursor::new(rest);
+    let _: Value =
+        ciborium::from_reader(&mut cursor).map_err(|_| String::from("Invalid public key"))?;
+    let public_key = &rest[..cursor.position() as usize];
+    PublicKey::from_cose(public_key)?;
+
+    Ok(NewCredential {
+        credential_id: credential_id.to_vec(),
+        public_key: public_key.to_vec(),
+        sign_count: data.sign_count,
+    })
+}
+
+/// Verify the response to an authentication ceremony.
+///
+/// # Notes
+///
+/// * This does not check the challenge. The caller must look up (and use up) the challenge from
+///   `ClientData::parse` first.
+///
+/// # Arguments
+///
+/// * `rp` - The relying party.
+/// * `public_key` - The stored COSE public key of the credential.
+/// * `stored_sign_count` - The signature counter stored with the credential.
+/// * `client_data_json` - The `clientDataJSON` from the authenticator's response.
+/// * `authenticator_data` - The `authenticatorData` from the authenticator's response.
+/// * `signature` - The `signature` from the authenticator's response.
+///
+/// # Returns
+///
+/// * `Ok(u32)` containing the new signature counter, if the response is valid.
+/// * `Err(String)` describing why it is not.
+pub fn verify_assertion(
+    rp: &RelyingParty,
+    public_key: &[u8],
+    stored_sign_count: u32,
+    client_data_json: &[u8],
+    authenticator_data: &[u8],
+    signature: &[u8],
+) -> Result<u32, String> {
+    check_client_data(rp, &ClientData::parse(client_data_json)?, "webauthn.get")?;
+    let data = parse_authenticator_data(authenticator_data)?;
+    check_authenticator_data(rp, &data)?;
+
+    let mut signed = authenticator_data.to_vec();
+    signed.extend_from_slice(&Sha256::digest(client_data_json));
+    PublicKey::from_cose(public_key)?.verify(&signed, signature)?;
+
+    // Authenticators which do not count signatures always report zero. Otherwise, the counter must
+    // always increase, or the credential may have been cloned.
+    if (data.sign_count != 0 || stored_sign_count != 0) && data.sign_count <= stored_sign_count {
+        return Err(String::from("The signature counter did not increase"));
+    }
+    Ok(data.sign_count)
+}
+
+/// Decode a base64url value sent by the browser, with or without padding.
+pub fn decode_base64url(value: &str) -> Result<Vec<u8>, String> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|_| String::from("Invalid base64url"))
+}
+
+/// Encode a value as base64url, as the browser expects.
+pub fn encode_base64url(value: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(value)
+}
+
+fn map_get<'a>(map: &'a Value, key: &Value) -> Option<&'a Value> {
+    match map {
+        Value::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+        _ => None,
+    }
+}
+
+fn cose_bytes(key: &Value, label: i64) -> Result<&[u8], String> {
+    match map_get(key, &Value::Integer(label.into())) {
+        Some(Value::Bytes(bytes)) => Ok(bytes),
+        _ => Err(format!("The public key is missing parameter {}", label)),
+    }
+}
+
+enum PublicKey {
+    /// An uncompressed P-256 point.
+    Es256(Vec<u8>),
+    EdDsa(Vec<u8>),
+    Rs256 {
+        n: Vec<u8>,
+        e: Vec<u8>,
+    },
+}
+
+impl PublicKey {
+    fn from_cose(bytes: &[u8]) -> Result<Self, String> {
+        let key: Value =
+            ciborium::from_reader(bytes).map_err(|_| String::from("Invalid public key"))?;
+        let algorithm = match map_get(&key, &Value::Integer(3.into())) {
+            Some(Value::Integer(alg)) => i64::try_from(*alg).unwrap_or(0),
+            _ => return Err(String::from("The public key has no algorithm")),
+        };
+        match algorithm {
+            ES256 => {
+                let mut point = vec![0x04];
+                point.extend_from_slice(cose_bytes(&key, -2)?);
+                point.extend_from_slice(cose_bytes(&key, -3)?);
+                Ok(PublicKey::Es256(point))
+            }
+            EDDSA => Ok(PublicKey::EdDsa(cose_bytes(&key, -2)?.to_vec())),
+            RS256 => Ok(PublicKey::Rs256 {
+                n: cose_bytes(&key, -1)?.to_vec(),
+                e: cose_bytes(&key, -2)?.to_vec(),
+            }),
+            alg => Err(format!("Unsupported algorithm {}", alg)),
+        }
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> Result<(), String> {
+        let result = match self {
+            PublicKey::Es256(point) => {
+                UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, point).verify(message, signature)
+            }
+            PublicKey::EdDsa(x) => UnparsedPublicKey::new(&ED25519, x).verify(message, signature),
+            PublicKey::Rs256 { n, e } => RsaPublicKeyComponents { n, e }.verify(
+                &RSA_PKCS1_2048_8192_SHA256,
+                message,
+                signature,
+            ),
+        };
+        result.map_err(|_| String::from("Invalid signature"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_authenticator::SoftwareAuthenticator;
+
+    const CHALLENGE: &str = "dGhpcyBpcyBhIGNoYWxsZW5nZQ";
+
+    fn rp() -> RelyingParty {
+        RelyingParty {
+            id: String::from("bitcasino.bigdevdog.com"),
+            name: String::from("Bit Casino"),
+            origin: String::from("https://bitcasino.bigdevdog.com"),
+        }
+    }
+
+    fn register(authenticator: &SoftwareAuthenticator) -> Result<NewCredential, String> {
+        let rp = rp();
+        let (client_data, attestation) = authenticator.register(&rp.id, &rp.origin, CHALLENGE);
+        verify_registration(&rp, &client_data, &attestation)
+    }
+
+    fn assert(
+        authenticator: &mut SoftwareAuthenticator,
+        credential: &NewCredential,
+        stored_sign_count: u32,
+    ) -> Result<u32, String> {
+        let rp = rp();
+        let (client_data, auth_data, signature) =
+            authenticator.assert(&rp.id, &rp.origin, CHALLENGE);
+        verify_assertion(
+            &rp,
+            &credential.public_key,
+            stored_sign_count,
+            &client_data,
+            &auth_data,
+            &signature,
+        )
+    }
+
+    #[test]
+    fn test_client_data() {
+        let client_data = ClientData::parse(
+            br#"{"type":"webauthn.get","challenge":"abc","origin":"https://a.com","crossOrigin":false}"#,
+        )
+        .unwrap();
+        assert_eq!(client_data.ceremony, "webauthn.get");
+        assert_eq!(client_data.challenge, "abc");
+        assert!(ClientData::parse(b"not json").is_err());
+    }
+
+    #[test]
+    fn test_es256_ceremonies() {
+        let mut authenticator = SoftwareAuthenticator::es256();
+        let credential = register(&authenticator).unwrap();
+        assert_eq!(credential.credential_id, authenticator.credential_id);
+
+        let count = assert(&mut authenticator, &credential, credential.sign_count).unwrap();
+        assert_eq!(count, credential.sign_count + 1);
+        let count = assert(&mut authenticator, &credential, count).unwrap();
+
+        // A cloned authenticator would replay an old counter.
+        assert!(assert(&mut authenticator, &credential, count + 5).is_err());
+    }
+
+    #[test]
+    fn test_ed25519_ceremonies() {
+        let mut authenticator = SoftwareAuthenticator::ed25519();
+        let credential = register(&authenticator).unwrap();
+        assert!(assert(&mut authenticator, &credential, 0).is_ok());
+    }
+
+    #[test]
+    fn test_zero_sign_count() {
+        let mut authenticator = SoftwareAuthenticator::es256();
+        authenticator.counts_signatures = false;
+        let credential = register(&authenticator).unwrap();
+        assert_eq!(assert(&mut authenticator, &credential, 0), Ok(0));
+        assert_eq!(assert(&mut authenticator, &credential, 0), Ok(0));
+    }
+
+    #[test]
+    fn test_rejected_registrations() {
+        let rp = rp();
+        let authenticator = SoftwareAuthenticator::es256();
+
+        let (client_data, attestation) =
+            authenticator.register(&rp.id, "https://evil.example", CHALLENGE);
+        assert!(verify_registration(&rp, &client_data, &attestation).is_err());
+
+        let (client_data, attestation) =
+            authenticator.register("evil.example", &rp.origin, CHALLENGE);
+        assert!(verify_registration(&rp, &client_data, &attestation).is_err());
+
+        let mut unverified = SoftwareAuthenticator::es256();
+        unverified.user_verified = false;
+        assert!(register(&unverified).is_err());
+
+        // An assertion is not a registration.
+        let mut authenticator = SoftwareAuthenticator::es256();
+        let (client_data, _, _) = authenticator.assert(&rp.id, &rp.origin, CHALLENGE);
+        let (_, attestation) = authenticator.register(&rp.id, &rp.origin, CHALLENGE);
+        assert!(verify_registration(&rp, &client_data, &attestation).is_err());
+    }
+
+    #[test]
+    fn test_rejected_assertions() {
+        let rp = rp();
+        let mut authenticator = SoftwareAuthenticator::es256();
+        let credential = register(&authenticator).unwrap();
+
+        let (client_data, auth_data, mut signature) =
+            authenticator.assert(&rp.id, &rp.origin, CHALLENGE);
+        let last = signature.len() - 1;
+        signature[last] ^= 0x01;
+        let result = verify_assertion(
+            &rp,
+            &credential.public_key,
+            0,
+            &client_data,
+            &auth_data,
+            &signature,
+        );
+        assert!(result.is_err());
+
+        // A signature from a different key.
+        let other = register(&SoftwareAuthenticator::es256()).unwrap();
+        assert!(assert(&mut authenticator, &other, 0).is_err());
+    }
+
+    #[test]
+    fn test_base64url() {
+        assert_eq!(decode_base64url("AQID").unwrap(), vec![1, 2, 3]);
+        assert_eq!(decode_base64url("AQI=").unwrap(), vec![1, 2]);
+        assert_eq!(encode_base64url(&[1, 2]), "AQI");
+        assert!(decode_base64url("not base64!").is_err());
+    }
+}
@@ -0,0 +1,153 @@
+//! A software authenticator, which performs the authenticator and browser side of the WebAuthn
+//! ceremonies so that their verification can be tested.
+
+use ciborium::Value;
+use ring::{
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, Ed25519KeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING},
+};
+use sha2::{Digest, Sha256};
+
+use super::{EDDSA, ES256};
+
+enum SigningKey {
+    Es256(EcdsaKeyPair),
+    EdDsa(Ed25519KeyPair),
+}
+
+pub struct SoftwareAuthenticator {
+    key: SigningKey,
+    pub credential_id: Vec<u8>,
+    pub sign_count: u32,
+    /// Whether the signature counter is increased for every assertion, or always left at zero.
+    pub counts_signatures: bool,
+    pub user_verified: bool,
+}
+
+impl SoftwareAuthenticator {
+    fn new(key: SigningKey) -> Self {
+        let mut credential_id = vec![0u8; 16];
+        ring::rand::SecureRandom::fill(&SystemRandom::new(), &mut credential_id).unwrap();
+        SoftwareAuthenticator {
+            key,
+            credential_id,
+            sign_count: 0,
+            counts_signatures: true,
+            user_verified: true,
+        }
+    }
+
+    pub fn es256() -> Self {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)
+            .unwrap();
+        Self::new(SigningKey::Es256(key))
+    }
+
+    pub fn ed25519() -> Self {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        Self::new(SigningKey::EdDsa(key))
+    }
+
+    fn cose_key(&self) -> Vec<u8> {
+        let int = |i: i64| Value::Integer(i.into());
+        let entries = match &self.key {
+            SigningKey::Es256(key) => {
+                let point = key.public_key().as_ref();
+                vec![
+                    (int(1), int(2)),
+                    (int(3), int(ES256)),
+                    (int(-1), int(1)),
+                    (int(-2), Value::Bytes(point[1..33].to_vec())),
+                    (int(-3), Value::Bytes(point[33..].to_vec())),
+                ]
+            }
+            SigningKey::EdDsa(key) => vec![
+                (int(1), int(1)),
+                (int(3), int(EDDSA)),
+                (int(-1), int(6)),
+                (int(-2), Value::Bytes(key.public_key().as_ref().to_vec())),
+            ],
+        };
+        let mut bytes = Vec::new();
+        ciborium::into_writer(&Value::Map(entries), &mut bytes).unwrap();
+        bytes
+    }
+
+    fn authenticator_data(&self, rp_id: &str, extra_flags: u8) -> Vec<u8> {
+        let mut flags = 0x01 | extra_flags;
+        if self.user_verified {
+            flags |= 0x04;
+        }
+        let mut data = Sha256::digest(rp_id.as_bytes()).to_vec();
+        data.push(flags);
+        data.extend_from_slice(&self.sign_count.to_be_bytes());
+        data
+    }
+
+    fn client_data(ceremony: &str, origin: &str, challenge: &str) -> Vec<u8> {
+        format!(
+            r#"{{"type":"{}","challenge":"{}","origin":"{}","crossOrigin":false}}"#,
+            ceremony, challenge, origin
+        )
+        .into_bytes()
+    }
+
+    /// Create the credential.
+    ///
+    /// # Returns
+    /// The `clientDataJSON` and `attestationObject`.
+    pub fn register(&self, rp_id: &str, origin: &str, challenge: &str) -> (Vec<u8>, Vec<u8>) {
+        let mut auth_data = self.authenticator_data(rp_id, 0x40);
+        auth_data.extend_from_slice(&[0u8; 16]);
+        auth_data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+        auth_data.extend_from_slice(&self.credential_id);
+        auth_data.extend_from_slice(&self.cose_key());
+
+        let text = |s: &str| Value::Text(String::from(s));
+        let attestation = Value::Map(vec![
+            (text("fmt"), text("none")),
+            (text("attStmt"), Value::Map(vec![])),
+            (text("authData"), Value::Bytes(auth_data)),
+        ]);
+        let mut attestation_object = Vec::new();
+        ciborium::into_writer(&attestation, &mut attestation_object).unwrap();
+
+        (
+            Self::client_data("webauthn.create", origin, challenge),
+            attestation_object,
+        )
+    }
+
+    /// Sign in with the credential.
+    ///
+    /// # Returns
+    /// The `clientDataJSON`, `authenticatorData` and `signature`.
+    pub fn assert(
+        &mut self,
+        rp_id: &str,
+        origin: &str,
+        challenge: &str,
+    ) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        if self.counts_signatures {
+            self.sign_count += 1;
+        }
+        let client_data = Self::client_data("webauthn.get", origin, challenge);
+        let auth_data = self.authenticator_data(rp_id, 0);
+
+        let mut signed = auth_data.clone();
+        signed.extend_from_slice(&Sha256::digest(&client_data));
+        let signature = match &self.key {
+            SigningKey::Es256(key) => key
+                .sign(&SystemRandom::new(), &signed)
+                .unwrap()
+                .as_ref()
+                .to_vec(),
+            SigningKey::EdDsa(key) => key.sign(&signed).as_ref().to_vec(),
+        };
+
+        (client_data, auth_data, signature)
+    }
+}
@@ -0,0 +1,337 @@
+//! This module delivers player events to the webhooks subscribed to them.
+//!
+//! # Notes
+//!
+//! - When an event is recorded in the outbox, a delivery is queued in the same transaction for every
+//!   webhook subscribed to its type (see `db::queries::webhooks`).
+//! - A background job POSTs each delivery to its webhook, with the same body as the outbox sinks. A
+//!   delivery succeeds when the webhook responds with a `2xx` status. Otherwise, it is retried with
+//!   exponential backoff, until it has failed too many times and is marked dead. Dead deliveries
+//!   can be revived through the API.
+//! - Every request is signed with the subscription's secret. The `Webhook-Signature` header is
+//!   `t=<timestamp>,v1=<signature>`, where the timestamp is in seconds since the Unix epoch, and the
+//!   signature is the hex encoded HMAC-SHA256 of `<timestamp>.<body>`. Receivers should check the
+//!   signature, and reject timestamps more than a few minutes old to prevent replays.
+//! - Deliveries are at least once, and not necessarily in order. `Webhook-Id` is the id of the event,
+//!   so receivers can ignore events they have already seen.
+//! - When the service runs as several replicas, each claims the deliveries it attempts, so a
+//!   delivery is not sent by two replicas at once.
+//! - Delivered and dead deliveries are kept for a while, and then deleted, since they carry a copy
+//!   of the event.
+//!
+//! # Environment
+//!
+//! * `WEBHOOK_POLL_INTERVAL_SECONDS` - (Optional) How often deliveries are attempted. Defaults to 5.
+//! * `WEBHOOK_RETRY_SECONDS` - (Optional) How long to wait before retrying a delivery after its first
+//!   failure. The wait doubles after each failure, up to 6 hours. Defaults to 30.
+//! * `WEBHOOK_MAX_ATTEMPTS` - (Optional) How many times a delivery is attempted before it is marked
+//!   dead. Defaults to 10.
+//! * `WEBHOOK_RETENTION_DAYS` - (Optional) How long delivered and dead deliveries are kept. Defaults
+//!   to 7.
+
+use std::time::Duration as StdDuration;
+
+use chrono::{Duration, Utc};
+use reqwest::{header::CONTENT_TYPE, Client};
+use ring::hmac;
+use sqlx::PgPool;
+use tokio::task::JoinSet;
+
+use crate::{
+    backoff::retry_delay,
+    config::from_env,
+    db::queries::webhooks::{
+        claim_due_webhook_deliveries, complete_webhook_delivery,
+        delete_finished_webhook_deliveries, fail_webhook_delivery, DueWebhookDelivery,
+    },
+    outbox::EventMessage,
+};
+
+/// The most deliveries attempted in one run.
+const BATCH_SIZE: i64 = 100;
+
+/// How long a webhook has to respond.
+const TIMEOUT: StdDuration = StdDuration::from_secs(10);
+
+/// How long a replica has to attempt the deliveries it claims before another replica may claim
+/// them. Every delivery in a run is attempted at the same time, so this only has to outlast
+/// `TIMEOUT`.
+const LEASE_MINUTES: i64 = 5;
+
+/// The longest wait before retrying a delivery.
+const MAX_RETRY_DELAY_SECONDS: i64 = 6 * 60 * 60;
+
+/// Sign a request body.
+///
+/// # Arguments
+///
+/// * `secret` - The secret of the subscription.
+/// * `timestamp` - When the request is sent, in seconds since the Unix epoch.
+/// * `body` - The body of the request.
+///
+/// # Returns
+///
+/// The value of the `Webhook-Signature` header.
+pub fn signature_header(secret: &str, timestamp: i64, body: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let tag = hmac::sign(&key, format!("{}.{}", timestamp, body).as_bytes());
+    let signature: String = tag.as_ref().iter().map(|b| format!("{:02x}", b)).collect();
+    format!("t={},v1={}", timestamp, signature)
+}
+
+/// Send a delivery to its webhook.
+///
+/// # Returns
+///
+/// * `Ok(u16)` with the status code, if the webhook accepted it.
+/// * `Err((Option<u16>, String))` with the status code (if it responded) and why it failed, if not.
+async fn deliver(
+    client: &Client,
+    delivery: &DueWebhookDelivery,
+) -> Result<u16, (Option<u16>, String)> {
+    let message = EventMessage {
+        id: delivery.event_id,
+        event_type: delivery.event_type.clone(),
+        player_id: delivery.player_id,
+        occurred_at: delivery.occurred_at,
+        data: delivery.payload.clone(),
+    };
+    let body = serde_json::to_string(&message).map_err(|e| (None, e.to_string()))?;
+    let response = client
+        .post(&delivery.url)
+        .timeout(TIMEOUT)
+        .header(CONTENT_TYPE, "application/json")
+        .header("Webhook-Id", delivery.event_id.to_string())
+        .header("Webhook-Event", &delivery.event_type)
+        .header(
+            "Webhook-Signature",
+            signature_header(&delivery.secret, Utc::now().timestamp(), &body),
+        )
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| (None, e.to_string()))?;
+
+    let status = response.status();
+    if status.is_success() {
+        Ok(status.as_u16())
+    } else {
+        Err((
+            Some(status.as_u16()),
+            format!("Webhook responded with {}", status),
+        ))
+    }
+}
+
+/// Attempt a delivery, and record the outcome.
+async fn attempt(
+    pool: &PgPool,
+    client: &Client,
+    delivery: DueWebhookDelivery,
+) -> Result<bool, sqlx::Error> {
+    match deliver(client, &delivery).await {
+        Ok(status_code) => {
+            complete_webhook_delivery(pool, delivery.id, status_code as i32).await?;
+            Ok(true)
+        }
+        Err((status_code, error)) => {
+            let attempts = delivery.attempts + 1;
+            let retry_at = (attempts < from_env("WEBHOOK_MAX_ATTEMPTS", 10)).then(|| {
+                Utc::now()
+                    + retry_delay(
+                        attempts,
+                        from_env("WEBHOOK_RETRY_SECONDS", 30),
+                        MAX_RETRY_DELAY_SECONDS,
+                    )
+            });
+            if retry_at.is_none() {
+                eprintln!(
+                    "Gave up delivering event {} to {} after {} attempts: {}",
+                    delivery.event_id, delivery.url, attempts, error
+                );
+            }
+            let status_code = status_code.map(i32::from);
+            fail_webhook_delivery(pool, delivery.id, status_code, &error, retry_at).await?;
+            Ok(false)
+        }
+    }
+}
+
+/// Claim every delivery which is due, and attempt them at the same time.
+///
+/// # Returns
+///
+/// The number of deliveries which succeeded.
+pub async fn run_webhook_deliveries(pool: &PgPool, client: &Client) -> Result<usize, sqlx::Error> {
+    let lease_until = Utc::now() + Duration::minutes(LEASE_MINUTES);
+    let mut attempts = JoinSet::new();
+    for delivery in claim_due_webhook_deliveries(pool, BATCH_SIZE, lease_until).await? {
+        let (pool, client) = (pool.clone(), client.clone());
+        attempts.spawn(async move { attempt(&pool, &client, delivery).await });
+    }
+
+    let mut delivered = 0;
+    while let Some(result) = attempts.join_next().await {
+        match result {
+            Ok(Ok(true)) => delivered += 1,
+            Ok(Ok(false)) => (),
+            Ok(Err(e)) => eprintln!("Failed to record a webhook delivery: {}", e),
+            Err(e) => eprintln!("A webhook delivery panicked: {}", e),
+        }
+    }
+    Ok(delivered)
+}
+
+/// Start the background job which delivers events to webhooks, and deletes old deliveries which
+/// are finished.
+pub fn spawn_delivery_job(pool: PgPool) {
+    let interval = from_env("WEBHOOK_POLL_INTERVAL_SECONDS", 5).max(1) as u64;
+    let retention = Duration::days(from_env("WEBHOOK_RETENTION_DAYS", 7));
+    tokio::spawn(async move {
+        let client = Client::new();
+        let mut ticker = tokio::time::interval(StdDuration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            if let Err(e) = run_webhook_deliveries(&pool, &client).await {
+                eprintln!("Failed to deliver webhooks: {}", e);
+            }
+            if let Err(e) = delete_finished_webhook_deliveries(&pool, Utc::now() - retention).await
+            {
+                eprintln!("Failed to delete finished webhook deliveries: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::StatusCode, routing::post, Router};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::{
+        db::{
+            models::{DeliveryStatus, WebhookSubscription},
+            queries::webhooks::{create_webhook_subscription, get_webhook_deliveries},
+        },
+        test_utils::create_test_player,
+    };
+
+    #[test]
+    fn test_signature_header() {
+        let header = signature_header("whsec", 1700000000, "{\"type\":\"player.created\"}");
+        let (timestamp, signature) = header.split_once(",v1=").unwrap();
+        assert_eq!(timestamp, "t=1700000000");
+        assert_eq!(signature.len(), 64);
+
+        // The receiver's check.
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"whsec");
+        let signature: Vec<u8> = (0..signature.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&signature[i..i + 2], 16).unwrap())
+            .collect();
+        assert!(hmac::verify(
+            &key,
+            b"1700000000.{\"type\":\"player.created\"}",
+            &signature
+        )
+        .is_ok());
+
+        assert_ne!(
+            header,
+            signature_header("other", 1700000000, "{\"type\":\"player.created\"}")
+        );
+        assert_ne!(
+            header,
+            signature_header("whsec", 1700000001, "{\"type\":\"player.created\"}")
+        );
+    }
+
+    /// Serve a webhook which accepts deliveries at `/ok`, and rejects them at `/fail`.
+    async fn serve_webhooks() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let app = Router::new()
+            .route("/ok", post(|| async { StatusCode::OK }))
+            .route(
+                "/fail",
+                post(|| async { StatusCode::INTERNAL_SERVER_ERROR }),
+            );
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}", address)
+    }
+
+    async fn subscribe(pool: &PgPool, url: String) -> WebhookSubscription {
+        let events = vec![String::from("player.created")];
+        create_webhook_subscription(pool, url, events, String::from("whsec"))
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn test_run_webhook_deliveries(pool: PgPool) {
+        let url = serve_webhooks().await;
+        let ok = subscribe(&pool, format!("{}/ok", url)).await;
+        let fail = subscribe(&pool, format!("{}/fail", url)).await;
+        create_test_player(&pool, "b1gd3vd0g", "CorrectHorse!42").await;
+        let client = Client::new();
+        let delivery = |subscription_id| {
+            let pool = pool.clone();
+            async move {
+                get_webhook_deliveries(&pool, subscription_id, None, 1)
+                    .await
+                    .unwrap()
+                    .remove(0)
+            }
+        };
+
+        assert_eq!(run_webhook_deliveries(&pool, &client).await.unwrap(), 1);
+        let delivered = delivery(ok.id).await;
+        assert_eq!(delivered.status, DeliveryStatus::Delivered.as_str());
+        assert_eq!(delivered.last_status_code, Some(200));
+
+        // The failed delivery backs off, rather than waiting for its lease to end.
+        let failed = delivery(fail.id).await;
+        assert_eq!(failed.status, DeliveryStatus::Pending.as_str());
+        assert_eq!(failed.attempts, 1);
+        assert_eq!(failed.last_status_code, Some(500));
+        assert!(failed.next_attempt_at > Utc::now());
+        assert!(failed.next_attempt_at < Utc::now() + Duration::minutes(1));
+        assert_eq!(run_webhook_deliveries(&pool, &client).await.unwrap(), 0);
+        assert_eq!(delivery(fail.id).await.attempts, 1);
+
+        // A claimed delivery is not claimed again until its lease is over.
+        sqlx::query("UPDATE webhook_deliveries SET next_attempt_at = now()")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let lease_until = Utc::now() + Duration::minutes(LEASE_MINUTES);
+        let claimed = claim_due_webhook_deliveries(&pool, BATCH_SIZE, lease_until)
+            .await
+            .unwrap();
+        assert_eq!(claimed.len(), 1);
+        let claimed = claim_due_webhook_deliveries(&pool, BATCH_SIZE, lease_until)
+            .await
+            .unwrap();
+        assert!(claimed.is_empty());
+
+        // The last attempt marks the delivery dead.
+        sqlx::query("UPDATE webhook_deliveries SET attempts = 9, next_attempt_at = now()")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(run_webhook_deliveries(&pool, &client).await.unwrap(), 0);
+        let dead = delivery(fail.id).await;
+        assert_eq!(dead.status, DeliveryStatus::Dead.as_str());
+        assert_eq!(dead.attempts, 10);
+
+        let deleted = delete_finished_webhook_deliveries(&pool, Utc::now() - Duration::days(1))
+            .await
+            .unwrap();
+        assert_eq!(deleted, 0);
+        let deleted = delete_finished_webhook_deliveries(&pool, Utc::now())
+            .await
+            .unwrap();
+        assert_eq!(deleted, 2);
+    }
+}